@@ -0,0 +1,259 @@
+//! Agreements: the contracts that authorize actions.
+//!
+//! Every action in UBL happens under an Agreement (a tenant license, a room's
+//! governance, a workspace agreement or a tool grant). The request carries
+//! the governing agreement in `EvaluationContext::agreement`, and conditions
+//! read it through `agreement.*` paths:
+//!
+//! ```yaml
+//! - field: agreement.status
+//!   operator: equals
+//!   value: active
+//! - field: agreement.capabilities
+//!   operator: contains
+//!   value: send_messages
+//! ```
+//!
+//! `agreement.status` is the status at `environment.now`, so an active
+//! agreement past its `expires_at` reads as `expired`. An evaluator built
+//! with `require_agreement` denies every request that no active agreement
+//! authorizes before consulting any policy ("no Agreement, no action").
+
+use crate::context::EvaluationContext;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// An agreement governing a tenant, room, workspace or tool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Agreement {
+    pub id: String,
+
+    #[serde(rename = "type")]
+    pub agreement_type: AgreementType,
+
+    pub tenant_id: String,
+
+    pub status: AgreementStatus,
+
+    /// The identities bound by the agreement.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parties: Vec<Party>,
+
+    /// What the agreement permits, e.g. `send_messages`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+
+    /// When an active or suspended agreement becomes expired.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+/// Kind of agreement.
+///
+/// Like `ResourceType`, other names deserialize as `Custom` and kinds
+/// compare by name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgreementType {
+    TenantLicense,
+    RoomGovernance,
+    WorkspaceAgreement,
+    ToolAccess,
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl AgreementType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AgreementType::TenantLicense => "tenant_license",
+            AgreementType::RoomGovernance => "room_governance",
+            AgreementType::WorkspaceAgreement => "workspace_agreement",
+            AgreementType::ToolAccess => "tool_access",
+            AgreementType::Custom(name) => name,
+        }
+    }
+}
+
+impl PartialEq for AgreementType {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for AgreementType {}
+
+/// Lifecycle status of an agreement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgreementStatus {
+    Draft,
+    Active,
+    Suspended,
+    Revoked,
+    Expired,
+}
+
+impl AgreementStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AgreementStatus::Draft => "draft",
+            AgreementStatus::Active => "active",
+            AgreementStatus::Suspended => "suspended",
+            AgreementStatus::Revoked => "revoked",
+            AgreementStatus::Expired => "expired",
+        }
+    }
+}
+
+/// An identity bound by an agreement.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Party {
+    /// The party's user or service ID.
+    pub id: String,
+
+    /// The party's role under the agreement, e.g. `owner`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+}
+
+impl Agreement {
+    /// Creates a draft agreement with no parties or capabilities.
+    pub fn new(id: impl Into<String>, agreement_type: AgreementType, tenant_id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            agreement_type,
+            tenant_id: tenant_id.into(),
+            status: AgreementStatus::Draft,
+            parties: Vec::new(),
+            capabilities: Vec::new(),
+            created_by: None,
+            created_at: None,
+            expires_at: None,
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Sets the status.
+    pub fn with_status(mut self, status: AgreementStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Adds a party.
+    pub fn with_party(mut self, id: impl Into<String>, role: Option<String>) -> Self {
+        self.parties.push(Party { id: id.into(), role });
+        self
+    }
+
+    /// Adds capabilities.
+    pub fn with_capabilities(mut self, capabilities: Vec<String>) -> Self {
+        self.capabilities.extend(capabilities);
+        self
+    }
+
+    /// Sets the expiry time.
+    pub fn with_expires_at(mut self, expires_at: DateTime<Utc>) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Returns the status at `now`: an active or suspended agreement whose
+    /// expiry has passed is expired.
+    pub fn status_at(&self, now: DateTime<Utc>) -> AgreementStatus {
+        match self.status {
+            AgreementStatus::Active | AgreementStatus::Suspended
+                if self.expires_at.is_some_and(|expires_at| expires_at <= now) =>
+            {
+                AgreementStatus::Expired
+            }
+            status => status,
+        }
+    }
+
+    /// Returns true if the agreement grants the capability.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Returns true if the identity is a party to the agreement.
+    pub fn is_party(&self, id: &str) -> bool {
+        self.parties.iter().any(|party| party.id == id)
+    }
+
+    /// Checks that the agreement can authorize the request at `now`: it is
+    /// active, belongs to the request's tenant, and is the agreement the
+    /// resource names, if it names one. Returns why not otherwise.
+    pub fn authorizes(&self, context: &EvaluationContext, now: DateTime<Utc>) -> std::result::Result<(), String> {
+        let status = self.status_at(now);
+        if status != AgreementStatus::Active {
+            return Err(format!("Agreement '{}' is {}", self.id, status.as_str()));
+        }
+        if self.tenant_id != context.tenant.tenant_id {
+            return Err(format!(
+                "Agreement '{}' belongs to tenant '{}', not '{}'",
+                self.id, self.tenant_id, context.tenant.tenant_id
+            ));
+        }
+        if let Some(agreement_id) = context.resource.agreement_id.as_ref().filter(|id| **id != self.id) {
+            return Err(format!(
+                "Resource is governed by agreement '{}', not '{}'",
+                agreement_id, self.id
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_deserialize() {
+        let agreement: Agreement = serde_json::from_value(serde_json::json!({
+            "id": "a:room:r:general",
+            "type": "room_governance",
+            "tenant_id": "t:example.com",
+            "status": "active",
+            "parties": [{ "id": "u:owner", "role": "owner" }],
+            "capabilities": ["send_messages", "read_history"],
+            "expires_at": "2030-01-01T00:00:00Z",
+        }))
+        .unwrap();
+
+        assert_eq!(agreement.agreement_type, AgreementType::RoomGovernance);
+        assert!(agreement.has_capability("send_messages"));
+        assert!(!agreement.has_capability("manage_settings"));
+        assert!(agreement.is_party("u:owner"));
+
+        let custom: AgreementType = serde_json::from_str("\"data_processing\"").unwrap();
+        assert_eq!(custom, AgreementType::Custom("data_processing".to_string()));
+        assert!(serde_json::from_str::<AgreementStatus>("\"pending\"").is_err());
+    }
+
+    #[test]
+    fn test_status_at() {
+        let expires_at = Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap();
+        let agreement = Agreement::new("a:1", AgreementType::ToolAccess, "t:1")
+            .with_status(AgreementStatus::Active)
+            .with_expires_at(expires_at);
+
+        assert_eq!(agreement.status_at(expires_at - chrono::Duration::seconds(1)), AgreementStatus::Active);
+        assert_eq!(agreement.status_at(expires_at), AgreementStatus::Expired);
+
+        let revoked = agreement.with_status(AgreementStatus::Revoked);
+        assert_eq!(revoked.status_at(expires_at), AgreementStatus::Revoked);
+    }
+}
@@ -0,0 +1,835 @@
+//! Typed ledger atoms.
+//!
+//! Every ledger entry is an atom tagged with a versioned `kind`: an
+//! `action.v1` records what someone did, and an `effect.v1` records what
+//! came of it. The fields follow the Blueprint schemas as extended by the
+//! Worker (`ActionAtom` and `EffectAtom` in its `types/index.ts`).
+//!
+//! An atom's `cid` is the CID of its canonical JSON minus `cid`, with an
+//! action's `prev_hash` hashed as `""`, as the Worker's `computeCID` does:
+//! the CID names what the action is, not where the ledger appended it, so a
+//! sealed atom hashes identically here and in the Worker:
+//!
+//! ```
+//! use policy_engine::atom::{ActionAtom, ActionThis, Atom, Who};
+//! use policy_engine::canonicalization::CanonicalizationMode;
+//! use policy_engine::hash::GENESIS_HASH;
+//!
+//! let when = "2026-01-07T12:34:56.790Z".parse().unwrap();
+//! let action = ActionAtom::new(
+//!     "t:example.com",
+//!     when,
+//!     Who::new("u:alice", "alice@example.com"),
+//!     "messenger.send",
+//!     ActionThis::message("r:general", "m:1", 42, "b:0123456789"),
+//!     "req:123456",
+//! )
+//! .seal(GENESIS_HASH, CanonicalizationMode::Jcs)
+//! .unwrap();
+//!
+//! let atom = Atom::from(action);
+//! assert!(atom.verify_cid(CanonicalizationMode::Jcs).is_ok());
+//! ```
+//!
+//! Fields the schemas allow to be `null` keep an explicit `null` apart from
+//! an absent field, so parsing and re-serializing an atom never changes its
+//! canonical JSON, and so its `cid`.
+
+use crate::canonicalization::{canonicalize_with, remove_field, CanonicalizationMode};
+use crate::error::{PolicyError, Result};
+use crate::hash;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+/// The kind of an action atom.
+pub const ACTION_KIND: &str = "action.v1";
+
+/// The kind of an effect atom.
+pub const EFFECT_KIND: &str = "effect.v1";
+
+/// A ledger atom.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Atom {
+    #[serde(rename = "action.v1")]
+    Action(ActionAtom),
+    #[serde(rename = "effect.v1")]
+    Effect(EffectAtom),
+}
+
+/// An `action.v1` atom. Serialize it as an `Atom`, which adds the `kind`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActionAtom {
+    pub tenant_id: String,
+
+    /// Absent until the atom is sealed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+
+    /// The ledger head the action was appended to.
+    pub prev_hash: String,
+
+    /// When the action happened, as `toISOString` writes it.
+    pub when: String,
+
+    pub who: Who,
+
+    /// The tool name, e.g. `messenger.send`.
+    pub did: String,
+
+    pub this: ActionThis,
+
+    /// The agreement that authorized the action.
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub agreement_id: Option<Option<String>>,
+
+    pub status: ActionStatus,
+
+    pub trace: Trace,
+}
+
+/// Who performed an action.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Who {
+    pub user_id: String,
+
+    pub email: String,
+
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub is_service: Option<Option<bool>>,
+}
+
+/// What an action acted on: a room message for `messenger.*` actions, a
+/// workspace (and possibly a document) for `office.*` actions.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActionThis {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_seq: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_hash: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<String>,
+
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<Option<String>>,
+}
+
+/// Status of an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionStatus {
+    Executed,
+    Pending,
+    Failed,
+}
+
+/// Correlates an action with its request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Trace {
+    pub request_id: String,
+}
+
+/// An `effect.v1` atom. Serialize it as an `Atom`, which adds the `kind`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EffectAtom {
+    pub tenant_id: String,
+
+    /// Absent until the atom is sealed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+
+    /// The CID of the action this is an effect of.
+    pub ref_action_cid: String,
+
+    /// When the effect happened, as `toISOString` writes it.
+    pub when: String,
+
+    pub outcome: Outcome,
+
+    pub effects: Vec<EffectItem>,
+
+    pub pointers: Pointers,
+
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub error: Option<Option<EffectError>>,
+}
+
+/// Outcome of an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Ok,
+    Error,
+}
+
+/// A change an action made.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EffectItem {
+    pub op: EffectOp,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_seq: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<String>,
+}
+
+/// Kind of change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EffectOp {
+    #[serde(rename = "room.append")]
+    RoomAppend,
+    #[serde(rename = "workspace.append")]
+    WorkspaceAppend,
+    #[serde(rename = "document.create")]
+    DocumentCreate,
+}
+
+/// Where the results of an action can be found.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pointers {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<String>,
+}
+
+/// Why an action failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EffectError {
+    pub code: String,
+    pub message: String,
+}
+
+/// Deserializes a field that may be `null`, so that `null` is `Some(None)`
+/// and only an absent field is `None`.
+fn nullable<'de, D, T>(deserializer: D) -> std::result::Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl Atom {
+    /// Parses and validates an atom from JSON text, rejecting duplicate keys,
+    /// lone surrogates and inexact numbers (see `crate::strict_json`).
+    pub fn from_json(json: &str) -> Result<Self> {
+        // Atoms hold only integers, which every mode reads alike
+        let value = crate::strict_json::parse(json, CanonicalizationMode::Jcs)?;
+        let atom: Atom = serde_json::from_value(value).map_err(|e| PolicyError::ValidationError(format!("Invalid atom: {}", e)))?;
+        atom.validate()?;
+        Ok(atom)
+    }
+
+    /// Returns the atom's kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Atom::Action(_) => ACTION_KIND,
+            Atom::Effect(_) => EFFECT_KIND,
+        }
+    }
+
+    /// Returns the atom's CID, if it is sealed.
+    pub fn cid(&self) -> Option<&str> {
+        match self {
+            Atom::Action(action) => action.cid.as_deref(),
+            Atom::Effect(effect) => effect.cid.as_deref(),
+        }
+    }
+
+    /// Returns the atom as JSON.
+    pub fn to_value(&self) -> Result<Value> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    /// Returns the atom's canonical JSON.
+    pub fn to_canonical(&self, mode: CanonicalizationMode) -> Result<String> {
+        canonicalize_with(&self.to_value()?, mode)
+    }
+
+    /// Computes the atom's CID (see `compute_value_cid`).
+    pub fn compute_cid(&self, mode: CanonicalizationMode) -> Result<String> {
+        compute_value_cid(&self.to_value()?, mode)
+    }
+
+    /// Checks that the atom is sealed with the CID of its contents.
+    pub fn verify_cid(&self, mode: CanonicalizationMode) -> Result<()> {
+        let cid = self
+            .cid()
+            .ok_or_else(|| PolicyError::ValidationError(format!("{} atom has no cid", self.kind())))?;
+        let expected = self.compute_cid(mode)?;
+        if cid != expected {
+            return Err(PolicyError::HashError(format!("cid is {}, computed {}", cid, expected)));
+        }
+        Ok(())
+    }
+
+    /// Checks the atom against its schema.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Atom::Action(action) => action.validate(),
+            Atom::Effect(effect) => effect.validate(),
+        }
+    }
+}
+
+impl From<ActionAtom> for Atom {
+    fn from(action: ActionAtom) -> Self {
+        Atom::Action(action)
+    }
+}
+
+impl From<EffectAtom> for Atom {
+    fn from(effect: EffectAtom) -> Self {
+        Atom::Effect(effect)
+    }
+}
+
+impl ActionAtom {
+    /// Creates an unsealed, executed action.
+    pub fn new(
+        tenant_id: impl Into<String>,
+        when: DateTime<Utc>,
+        who: Who,
+        did: impl Into<String>,
+        this: ActionThis,
+        request_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            tenant_id: tenant_id.into(),
+            cid: None,
+            prev_hash: String::new(),
+            when: format_when(when),
+            who,
+            did: did.into(),
+            this,
+            agreement_id: None,
+            status: ActionStatus::Executed,
+            trace: Trace {
+                request_id: request_id.into(),
+            },
+        }
+    }
+
+    /// Sets the agreement that authorized the action.
+    pub fn with_agreement_id(mut self, agreement_id: impl Into<String>) -> Self {
+        self.agreement_id = Some(Some(agreement_id.into()));
+        self
+    }
+
+    /// Sets the status.
+    pub fn with_status(mut self, status: ActionStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Chains the action onto `prev_hash`, validates it, and sets its CID,
+    /// which does not depend on `prev_hash`.
+    pub fn seal(mut self, prev_hash: impl Into<String>, mode: CanonicalizationMode) -> Result<Self> {
+        self.prev_hash = prev_hash.into();
+        self.cid = None;
+        self.validate()?;
+        self.cid = Some(compute_value_cid(&tagged_value(ACTION_KIND, &self)?, mode)?);
+        Ok(self)
+    }
+
+    /// Checks the action against the `action.v1` schema.
+    pub fn validate(&self) -> Result<()> {
+        check_id("tenant_id", &self.tenant_id, "t:", 128)?;
+        if let Some(cid) = &self.cid {
+            check_hash("cid", cid, "c:")?;
+        }
+        if self.prev_hash != hash::GENESIS_HASH {
+            check_hash("prev_hash", &self.prev_hash, "h:")?;
+        }
+        check_when(&self.when)?;
+
+        check_id("who.user_id", &self.who.user_id, "u:", 256)?;
+        check_email(&self.who.email)?;
+
+        let did_is_valid = self.did.split('.').count() > 1
+            && self
+                .did
+                .split('.')
+                .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c == '_'));
+        if !did_is_valid {
+            return Err(invalid("did", &format!("'{}' is not a tool name", self.did)));
+        }
+
+        let this = &self.this;
+        check_optional_id("this.room_id", &this.room_id, "r:", 128)?;
+        check_optional_id("this.msg_id", &this.msg_id, "m:", 256)?;
+        check_optional_id("this.workspace_id", &this.workspace_id, "w:", 128)?;
+        check_optional_id("this.document_id", &this.document_id, "d:", 256)?;
+        check_room_seq("this.room_seq", this.room_seq)?;
+        for (field, value) in [("this.body_hash", &this.body_hash), ("this.content_hash", &this.content_hash.clone().flatten())] {
+            if value.as_ref().is_some_and(|value| value.len() < 10) {
+                return Err(invalid(field, "is too short"));
+            }
+        }
+        if self.did.starts_with("messenger.") {
+            require("this.room_id", &this.room_id)?;
+            require("this.msg_id", &this.msg_id)?;
+            require("this.room_seq", &this.room_seq)?;
+            require("this.body_hash", &this.body_hash)?;
+        } else if self.did.starts_with("office.") {
+            require("this.workspace_id", &this.workspace_id)?;
+        }
+
+        if let Some(Some(agreement_id)) = &self.agreement_id {
+            check_agreement_id(agreement_id)?;
+        }
+
+        let request_id = self.trace.request_id.chars().count();
+        if !(6..=128).contains(&request_id) {
+            return Err(invalid("trace.request_id", "must be 6 to 128 characters"));
+        }
+        Ok(())
+    }
+}
+
+impl Who {
+    /// Creates an identity that is not flagged as a service.
+    pub fn new(user_id: impl Into<String>, email: impl Into<String>) -> Self {
+        Self {
+            user_id: user_id.into(),
+            email: email.into(),
+            is_service: None,
+        }
+    }
+}
+
+impl ActionThis {
+    /// The subject of a `messenger.*` action.
+    pub fn message(
+        room_id: impl Into<String>,
+        msg_id: impl Into<String>,
+        room_seq: u64,
+        body_hash: impl Into<String>,
+    ) -> Self {
+        Self {
+            room_id: Some(room_id.into()),
+            msg_id: Some(msg_id.into()),
+            room_seq: Some(room_seq),
+            body_hash: Some(body_hash.into()),
+            ..Self::default()
+        }
+    }
+
+    /// The subject of an `office.*` action.
+    pub fn workspace(workspace_id: impl Into<String>) -> Self {
+        Self {
+            workspace_id: Some(workspace_id.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Sets the document acted on.
+    pub fn with_document(mut self, document_id: impl Into<String>) -> Self {
+        self.document_id = Some(document_id.into());
+        self
+    }
+
+    /// Sets the hash of the document's content.
+    pub fn with_content_hash(mut self, content_hash: impl Into<String>) -> Self {
+        self.content_hash = Some(Some(content_hash.into()));
+        self
+    }
+}
+
+impl EffectAtom {
+    /// Creates an unsealed effect of an action, with no changes.
+    pub fn new(
+        tenant_id: impl Into<String>,
+        ref_action_cid: impl Into<String>,
+        when: DateTime<Utc>,
+        outcome: Outcome,
+    ) -> Self {
+        Self {
+            tenant_id: tenant_id.into(),
+            cid: None,
+            ref_action_cid: ref_action_cid.into(),
+            when: format_when(when),
+            outcome,
+            effects: Vec::new(),
+            pointers: Pointers::default(),
+            error: None,
+        }
+    }
+
+    /// Adds a change.
+    pub fn with_effect(mut self, effect: EffectItem) -> Self {
+        self.effects.push(effect);
+        self
+    }
+
+    /// Sets the pointers.
+    pub fn with_pointers(mut self, pointers: Pointers) -> Self {
+        self.pointers = pointers;
+        self
+    }
+
+    /// Sets the error.
+    pub fn with_error(mut self, code: impl Into<String>, message: impl Into<String>) -> Self {
+        self.error = Some(Some(EffectError {
+            code: code.into(),
+            message: message.into(),
+        }));
+        self
+    }
+
+    /// Validates the effect and sets its CID.
+    pub fn seal(mut self, mode: CanonicalizationMode) -> Result<Self> {
+        self.cid = None;
+        self.validate()?;
+        self.cid = Some(compute_value_cid(&tagged_value(EFFECT_KIND, &self)?, mode)?);
+        Ok(self)
+    }
+
+    /// Checks the effect against the `effect.v1` schema.
+    pub fn validate(&self) -> Result<()> {
+        check_id("tenant_id", &self.tenant_id, "t:", 128)?;
+        if let Some(cid) = &self.cid {
+            check_hash("cid", cid, "c:")?;
+        }
+        check_hash("ref_action_cid", &self.ref_action_cid, "c:")?;
+        check_when(&self.when)?;
+
+        if self.effects.is_empty() && self.outcome == Outcome::Ok {
+            return Err(invalid("effects", "must not be empty"));
+        }
+        for effect in &self.effects {
+            check_optional_id("effects.room_id", &effect.room_id, "r:", 128)?;
+            check_optional_id("effects.workspace_id", &effect.workspace_id, "w:", 128)?;
+            check_optional_id("effects.document_id", &effect.document_id, "d:", 256)?;
+            check_room_seq("effects.room_seq", effect.room_seq)?;
+            match effect.op {
+                EffectOp::RoomAppend => {
+                    require("effects.room_id", &effect.room_id)?;
+                    require("effects.room_seq", &effect.room_seq)?;
+                }
+                EffectOp::WorkspaceAppend => require("effects.workspace_id", &effect.workspace_id)?,
+                EffectOp::DocumentCreate => require("effects.document_id", &effect.document_id)?,
+            }
+        }
+
+        check_optional_id("pointers.msg_id", &self.pointers.msg_id, "m:", 256)?;
+        check_optional_id("pointers.document_id", &self.pointers.document_id, "d:", 256)?;
+
+        match &self.error {
+            Some(Some(error)) => {
+                if error.code.chars().count() > 64 {
+                    return Err(invalid("error.code", "is longer than 64 characters"));
+                }
+                if error.message.chars().count() > 500 {
+                    return Err(invalid("error.message", "is longer than 500 characters"));
+                }
+            }
+            _ if self.outcome == Outcome::Error => return Err(invalid("error", "is required when outcome is error")),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl EffectItem {
+    /// A message appended to a room.
+    pub fn room_append(room_id: impl Into<String>, room_seq: u64) -> Self {
+        Self {
+            op: EffectOp::RoomAppend,
+            room_id: Some(room_id.into()),
+            room_seq: Some(room_seq),
+            workspace_id: None,
+            document_id: None,
+        }
+    }
+
+    /// A document created in a workspace.
+    pub fn document_create(workspace_id: impl Into<String>, document_id: impl Into<String>) -> Self {
+        Self {
+            op: EffectOp::DocumentCreate,
+            room_id: None,
+            room_seq: None,
+            workspace_id: Some(workspace_id.into()),
+            document_id: Some(document_id.into()),
+        }
+    }
+}
+
+/// Computes the CID of an atom given as JSON: the CID of its canonical JSON
+/// minus `cid`, with an action's `prev_hash` set to `""`.
+pub fn compute_value_cid(atom: &Value, mode: CanonicalizationMode) -> Result<String> {
+    let mut content = remove_field(atom, "cid");
+    if let Value::Object(fields) = &mut content {
+        if fields.get("kind").and_then(Value::as_str) == Some(ACTION_KIND) {
+            fields.insert("prev_hash".to_string(), Value::String(String::new()));
+        }
+    }
+    Ok(hash::compute_cid(&canonicalize_with(&content, mode)?))
+}
+
+/// Returns an atom's fields as JSON with its `kind`, as `Atom` serializes it.
+fn tagged_value(kind: &str, atom: &impl Serialize) -> Result<Value> {
+    let mut value = serde_json::to_value(atom)?;
+    if let Value::Object(fields) = &mut value {
+        fields.insert("kind".to_string(), Value::from(kind));
+    }
+    Ok(value)
+}
+
+/// Formats a time the way JavaScript's `toISOString` does.
+fn format_when(when: DateTime<Utc>) -> String {
+    when.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn invalid(field: &str, reason: &str) -> PolicyError {
+    PolicyError::ValidationError(format!("Invalid atom: {} {}", field, reason))
+}
+
+fn require<T>(field: &str, value: &Option<T>) -> Result<()> {
+    match value {
+        Some(_) => Ok(()),
+        None => Err(invalid(field, "is required")),
+    }
+}
+
+/// Checks an ID of the form `<prefix><rest>`, where `<rest>` is 1 to `max`
+/// characters with no control characters. The Worker builds IDs from
+/// slugs, UUIDs, email domains and, for users, the identity provider's
+/// subject (`u:dev-alice@example.com` in dev mode), so nothing narrower
+/// holds.
+fn check_id(field: &str, id: &str, prefix: &str, max: usize) -> Result<()> {
+    if !id.strip_prefix(prefix).is_some_and(|rest| is_id_text(rest, max)) {
+        return Err(invalid(field, &format!("'{}' is not a {}... ID", id, prefix)));
+    }
+    Ok(())
+}
+
+/// Checks an agreement ID of the form `a:<type>:<id>`, as the Worker's
+/// `generateAgreementId` writes it, e.g. `a:room:r:general`. The `<id>` is
+/// an entity ID or, for tool agreements, several joined with `:`.
+fn check_agreement_id(id: &str) -> Result<()> {
+    let valid = id
+        .strip_prefix("a:")
+        .and_then(|rest| rest.split_once(':'))
+        .is_some_and(|(kind, entity)| {
+            !kind.is_empty() && kind.chars().all(|c| c.is_ascii_lowercase() || c == '_') && is_id_text(entity, 512)
+        });
+    if !valid {
+        return Err(invalid("agreement_id", &format!("'{}' is not an a:<type>:<id> ID", id)));
+    }
+    Ok(())
+}
+
+fn is_id_text(text: &str, max: usize) -> bool {
+    (1..=max).contains(&text.chars().count()) && !text.chars().any(char::is_control)
+}
+
+fn check_optional_id(field: &str, id: &Option<String>, prefix: &str, max: usize) -> Result<()> {
+    match id {
+        Some(id) => check_id(field, id, prefix, max),
+        None => Ok(()),
+    }
+}
+
+/// Checks a hash of the form `<prefix><64 lowercase hex digits>`.
+fn check_hash(field: &str, value: &str, prefix: &str) -> Result<()> {
+    let valid = value
+        .strip_prefix(prefix)
+        .is_some_and(|hex| hex.len() == 64 && hex.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')));
+    if !valid {
+        return Err(invalid(field, &format!("'{}' is not a {}... hash", value, prefix)));
+    }
+    Ok(())
+}
+
+fn check_room_seq(field: &str, room_seq: Option<u64>) -> Result<()> {
+    if room_seq == Some(0) {
+        return Err(invalid(field, "must be at least 1"));
+    }
+    Ok(())
+}
+
+fn check_when(when: &str) -> Result<()> {
+    if DateTime::parse_from_rfc3339(when).is_err() {
+        return Err(invalid("when", &format!("'{}' is not an RFC 3339 time", when)));
+    }
+    Ok(())
+}
+
+fn check_email(email: &str) -> Result<()> {
+    let valid = email
+        .split_once('@')
+        .is_some_and(|(local, domain)| !local.is_empty() && !domain.is_empty() && !domain.contains('@'));
+    if !valid {
+        return Err(invalid("who.email", &format!("'{}' is not an email address", email)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn when() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 1, 7, 12, 34, 56).unwrap()
+    }
+
+    fn action() -> ActionAtom {
+        ActionAtom::new(
+            "t:example.com",
+            when(),
+            Who::new("u:alice", "alice@example.com"),
+            "messenger.send",
+            ActionThis::message("r:general", "m:1", 42, "b:0123456789"),
+            "req:123456",
+        )
+        .with_agreement_id("a:room:r:general")
+    }
+
+    #[test]
+    fn test_seal() {
+        let mode = CanonicalizationMode::Jcs;
+        let sealed = action().seal(hash::GENESIS_HASH, mode).unwrap();
+        assert_eq!(sealed.when, "2026-01-07T12:34:56.000Z");
+
+        // The cid covers everything but itself and the chain position
+        let value = Atom::from(sealed.clone()).to_value().unwrap();
+        assert_eq!(value["kind"], ACTION_KIND);
+        let mut content = remove_field(&value, "cid");
+        content["prev_hash"] = Value::from("");
+        let canonical = canonicalize_with(&content, mode).unwrap();
+        assert_eq!(sealed.cid.as_deref(), Some(hash::compute_cid(&canonical).as_str()));
+        let head = hash::compute_head_hash(hash::GENESIS_HASH, sealed.cid.as_ref().unwrap());
+        assert_eq!(action().seal(head, mode).unwrap().cid, sealed.cid);
+
+        let effect = EffectAtom::new("t:example.com", sealed.cid.clone().unwrap(), when(), Outcome::Ok)
+            .with_effect(EffectItem::room_append("r:general", 42))
+            .with_pointers(Pointers {
+                msg_id: Some("m:1".to_string()),
+                ..Pointers::default()
+            })
+            .seal(mode)
+            .unwrap();
+        let effect = Atom::from(effect);
+        assert_eq!(effect.kind(), EFFECT_KIND);
+        effect.verify_cid(mode).unwrap();
+
+        let mut tampered = Atom::from(sealed);
+        if let Atom::Action(action) = &mut tampered {
+            action.this.room_seq = Some(43);
+        }
+        assert!(tampered.verify_cid(mode).is_err());
+
+        assert!(action().seal("h:nope", mode).is_err());
+        let unattached = EffectAtom::new("t:example.com", "c:nope", when(), Outcome::Ok);
+        assert!(unattached.seal(mode).is_err());
+    }
+
+    #[test]
+    fn test_worker_atoms() {
+        // As the Worker's LedgerShardObject stored a message sent by RoomObject
+        let action = r#"{"kind":"action.v1","tenant_id":"t:example.com","cid":"c:858e4eada65c112a34d5a24f185f7075fc13b93290b1c33cf6f772d97c5d1fb6","prev_hash":"h:149f2756904c84c29478e084fb84da1f63fd5d1162ff8f85465f8bdc6301ab59","when":"2026-01-07T12:35:02.113Z","who":{"user_id":"u:bob","email":"bob@example.com"},"did":"messenger.send","this":{"room_id":"r:general","msg_id":"m:2c9a8b7d-6e5f-4a3b-9c2d-1e0f9a8b7c6d","room_seq":2,"body_hash":"b:c012fa0893292c85dd09caa9298b77ee69eb396cd956091999f3bf521266c4a9"},"agreement_id":"a:room:r:general","status":"executed","trace":{"request_id":"req:7a6b5c4d-3e2f-4a1b-8c9d-0e1f2a3b4c5e"}}"#;
+        let effect = r#"{"kind":"effect.v1","tenant_id":"t:example.com","cid":"c:48d3a0a5ddfbceed7dcd780af34dbe578c99d270f43a43bf6505d8e4d144c131","ref_action_cid":"c:858e4eada65c112a34d5a24f185f7075fc13b93290b1c33cf6f772d97c5d1fb6","when":"2026-01-07T12:35:02.113Z","outcome":"ok","effects":[{"op":"room.append","room_id":"r:general","room_seq":2}],"pointers":{"msg_id":"m:2c9a8b7d-6e5f-4a3b-9c2d-1e0f9a8b7c6d"}}"#;
+        let mode = CanonicalizationMode::Jcs;
+        let stored = Atom::from_json(action).unwrap();
+        stored.verify_cid(mode).unwrap();
+        Atom::from_json(effect).unwrap().verify_cid(mode).unwrap();
+
+        let sealed = ActionAtom::new(
+            "t:example.com",
+            "2026-01-07T12:35:02.113Z".parse().unwrap(),
+            Who::new("u:bob", "bob@example.com"),
+            "messenger.send",
+            ActionThis::message(
+                "r:general",
+                "m:2c9a8b7d-6e5f-4a3b-9c2d-1e0f9a8b7c6d",
+                2,
+                "b:c012fa0893292c85dd09caa9298b77ee69eb396cd956091999f3bf521266c4a9",
+            ),
+            "req:7a6b5c4d-3e2f-4a1b-8c9d-0e1f2a3b4c5e",
+        )
+        .with_agreement_id("a:room:r:general")
+        .seal("h:149f2756904c84c29478e084fb84da1f63fd5d1162ff8f85465f8bdc6301ab59", mode)
+        .unwrap();
+        assert_eq!(Atom::from(sealed), stored);
+    }
+
+    #[test]
+    fn test_dev_mode_atom() {
+        // Sent as a dev-mode identity, whose user ID is `u:dev-<email>`
+        let json = r#"{"kind":"action.v1","tenant_id":"t:example.com","cid":"c:10b6dd70cdf04933c59cdfca17119ea48336d7bf32178314bfc8908848051607","prev_hash":"h:genesis","when":"2026-01-07T12:34:56.790Z","who":{"user_id":"u:dev-alice@example.com","email":"alice@example.com"},"did":"messenger.send","this":{"room_id":"r:general","msg_id":"m:0b6f3c52-8f1e-4c1a-9d0e-6a7b2f9e4d11","room_seq":1,"body_hash":"b:e7b995efa755c5ff3b84d2188b58cb4ae916a59470eb3761df8a814f11763500"},"agreement_id":"a:room:r:general","status":"executed","trace":{"request_id":"req:5d1c7e2a-3b4f-4a6e-8c9d-0e1f2a3b4c5d"}}"#;
+        let mode = CanonicalizationMode::Jcs;
+        let atom = Atom::from_json(json).unwrap();
+        atom.verify_cid(mode).unwrap();
+        let canonical = crate::canonicalization::canonicalize_str(json, mode).unwrap();
+        assert_eq!(atom.to_canonical(mode).unwrap(), canonical);
+
+        for user_id in ["u:", "u:dev-\\u0000alice", "alice"] {
+            let json = json.replace("u:dev-alice@example.com", user_id);
+            assert!(Atom::from_json(&json).is_err(), "{}", user_id);
+        }
+    }
+
+    #[test]
+    fn test_agreement_id() {
+        for id in ["a:room:r:general", "a:tenant:t:example.com", "a:tool:t:example.com:messenger.send:u:dev-alice@example.com"] {
+            action().with_agreement_id(id).seal(hash::GENESIS_HASH, CanonicalizationMode::Jcs).unwrap();
+        }
+        for id in ["a.room.r.general", "a:room", "a:room:", "a::r:general", "a:Room:r:general", "a:room:r\ngeneral"] {
+            assert!(action().with_agreement_id(id).seal(hash::GENESIS_HASH, CanonicalizationMode::Jcs).is_err(), "{}", id);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = r#"{"kind":"action.v1","tenant_id":"t:example.com","prev_hash":"h:genesis","when":"2026-01-07T12:34:56.790Z","who":{"user_id":"u:alice","email":"alice@example.com","is_service":null},"did":"office.document.search","this":{"workspace_id":"w:docs"},"agreement_id":null,"status":"executed","trace":{"request_id":"req:123456"}}"#;
+        for mode in [CanonicalizationMode::V1, CanonicalizationMode::Jcs] {
+            let atom = Atom::from_json(json).unwrap();
+            let canonical = crate::canonicalization::canonicalize_str(json, mode).unwrap();
+            assert_eq!(atom.to_canonical(mode).unwrap(), canonical);
+        }
+
+        let effect = r#"{"kind":"effect.v1","tenant_id":"t:example.com","ref_action_cid":"c:0000000000000000000000000000000000000000000000000000000000000000","when":"2026-01-07T12:34:56.800Z","outcome":"error","effects":[],"pointers":{},"error":{"code":"E_FULL","message":"Room is full"}}"#;
+        let atom = Atom::from_json(effect).unwrap();
+        assert!(matches!(atom, Atom::Effect(EffectAtom { outcome: Outcome::Error, .. })));
+
+        // Unknown fields, kinds and shapes are rejected
+        let extra = json.replace(r#""status""#, r#""extra":1,"status""#);
+        assert!(Atom::from_json(&extra).is_err());
+        let v2 = json.replace("action.v1", "action.v2");
+        assert!(Atom::from_json(&v2).is_err());
+        let messenger = json.replace("office.document.search", "messenger.send");
+        let err = Atom::from_json(&messenger).unwrap_err();
+        assert!(err.to_string().contains("this.room_id is required"));
+    }
+}
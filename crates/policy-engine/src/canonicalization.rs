@@ -1,23 +1,87 @@
 //! JSON canonicalization for deterministic hashing.
 //!
-//! Implements strict canonical JSON serialization:
+//! Two rule sets are available (see `CanonicalizationMode`):
+//!
+//! `V1`, the original rules and the default. Stored CIDs depend on its
+//! exact output, so it does not change:
 //! - UTF-8 encoding
-//! - Object keys sorted lexicographically
+//! - Object keys sorted lexicographically (by code point)
 //! - No insignificant whitespace
-//! - Numbers rendered consistently
-//! - Unicode normalized (NFC)
+//! - Numbers rendered as parsed (`1.0` and `1` differ)
+//! - In string values, `\r\n` and `\r` are replaced by `\n`; strings are
+//!   not Unicode-normalized
+//!
+//! `Jcs`, the JSON Canonicalization Scheme of RFC 8785 applied to normalized
+//! text, which is what the Workers' `canon.ts` produces for the atoms and
+//! bodies they hash:
+//! - Object keys sorted by their UTF-16 code units
+//! - Numbers are IEEE 754 doubles serialized as ECMAScript does, so `1.0`
+//!   is `1` and `1e21` is `1e+21`
+//! - Only `"`, `\` and control characters below U+0020 are escaped
+//! - Keys and string values are normalized to NFC, with `\r\n` and `\r`
+//!   replaced by `\n`, first; keys that become equal are an error
+//!
+//! `canon.ts` does not produce `V1`, and differs from `Jcs` on inputs such
+//! as integer-like keys; the conformance vectors record where (see
+//! `crate::conformance`).
 
 use crate::error::{PolicyError, Result};
+use crate::text::nfc;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::borrow::Cow;
 use std::io::Write;
+use std::str::FromStr;
+
+/// The rules a value is canonicalized with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CanonicalizationMode {
+    /// The original rules.
+    #[default]
+    V1,
+    /// RFC 8785 (JCS) over normalized strings, as the Workers canonicalize.
+    Jcs,
+}
+
+impl FromStr for CanonicalizationMode {
+    type Err = PolicyError;
 
-/// Canonicalizes a JSON value to a deterministic string representation.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "v1" => Ok(CanonicalizationMode::V1),
+            "jcs" => Ok(CanonicalizationMode::Jcs),
+            _ => Err(PolicyError::CanonicalizationError(format!("Unknown canonicalization mode '{}'", s))),
+        }
+    }
+}
+
+/// Canonicalizes a JSON value to a deterministic string representation
+/// using the `V1` rules.
 pub fn canonicalize(value: &Value) -> Result<String> {
+    canonicalize_with(value, CanonicalizationMode::V1)
+}
+
+/// Canonicalizes a JSON value using the given rules.
+pub fn canonicalize_with(value: &Value, mode: CanonicalizationMode) -> Result<String> {
     let mut output = Vec::new();
-    write_canonical(&mut output, value)?;
+    match mode {
+        CanonicalizationMode::V1 => write_canonical(&mut output, value)?,
+        CanonicalizationMode::Jcs => write_jcs(&mut output, value)?,
+    }
     String::from_utf8(output).map_err(|e| PolicyError::CanonicalizationError(e.to_string()))
 }
 
+/// Parses JSON text strictly and canonicalizes it.
+///
+/// Unlike parsing with `serde_json` first, duplicate keys, lone surrogates
+/// and numbers that would lose digits are errors (see `crate::strict_json`),
+/// so no two texts that read as different values are silently given the
+/// same canonical form.
+pub fn canonicalize_str(json: &str, mode: CanonicalizationMode) -> Result<String> {
+    canonicalize_with(&crate::strict_json::parse(json, mode)?, mode)
+}
+
 /// Writes a canonical JSON representation to a writer.
 fn write_canonical<W: Write>(writer: &mut W, value: &Value) -> Result<()> {
     match value {
@@ -36,10 +100,8 @@ fn write_canonical<W: Write>(writer: &mut W, value: &Value) -> Result<()> {
             write!(writer, "{}", n)?;
         }
         Value::String(s) => {
-            // Normalize Unicode to NFC
-            let normalized: String = s.chars().collect();
             // Normalize line endings
-            let normalized = normalized.replace("\r\n", "\n").replace('\r', "\n");
+            let normalized = s.replace("\r\n", "\n").replace('\r', "\n");
             // Write with proper escaping
             write_escaped_string(writer, &normalized)?;
         }
@@ -79,6 +141,147 @@ fn write_canonical<W: Write>(writer: &mut W, value: &Value) -> Result<()> {
     Ok(())
 }
 
+/// Writes a value following RFC 8785 over normalized strings.
+fn write_jcs<W: Write>(writer: &mut W, value: &Value) -> Result<()> {
+    match value {
+        Value::Null => writer.write_all(b"null")?,
+        Value::Bool(true) => writer.write_all(b"true")?,
+        Value::Bool(false) => writer.write_all(b"false")?,
+        Value::Number(n) => {
+            // JSON numbers are doubles in JCS, as in JavaScript; integers
+            // beyond 2^53 round exactly as JSON.parse rounds them.
+            let n = n
+                .as_f64()
+                .ok_or_else(|| PolicyError::CanonicalizationError(format!("Number {} is not representable", n)))?;
+            writer.write_all(format_es_number(n)?.as_bytes())?;
+        }
+        Value::String(s) => write_jcs_string(writer, &normalize(s))?,
+        Value::Array(arr) => {
+            writer.write_all(b"[")?;
+            for (i, item) in arr.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b",")?;
+                }
+                write_jcs(writer, item)?;
+            }
+            writer.write_all(b"]")?;
+        }
+        Value::Object(obj) => {
+            let mut entries: Vec<(Vec<u16>, Cow<'_, str>, &Value)> = obj
+                .iter()
+                .map(|(key, value)| {
+                    let key = normalize(key);
+                    (key.encode_utf16().collect(), key, value)
+                })
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            if let Some(pair) = entries.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+                return Err(PolicyError::CanonicalizationError(format!(
+                    "Duplicate key {:?} after normalization",
+                    pair[0].1
+                )));
+            }
+
+            writer.write_all(b"{")?;
+            for (i, (_, key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b",")?;
+                }
+                write_jcs_string(writer, key)?;
+                writer.write_all(b":")?;
+                write_jcs(writer, value)?;
+            }
+            writer.write_all(b"}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Normalizes a `Jcs` string to NFC with `\n` line endings.
+fn normalize(s: &str) -> Cow<'_, str> {
+    let s = nfc(s);
+    if !s.contains('\r') {
+        return s;
+    }
+    Cow::Owned(s.replace("\r\n", "\n").replace('\r', "\n"))
+}
+
+/// Writes a string escaped as `JSON.stringify` escapes it.
+fn write_jcs_string<W: Write>(writer: &mut W, s: &str) -> Result<()> {
+    writer.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => writer.write_all(b"\\\"")?,
+            '\\' => writer.write_all(b"\\\\")?,
+            '\u{8}' => writer.write_all(b"\\b")?,
+            '\u{c}' => writer.write_all(b"\\f")?,
+            '\n' => writer.write_all(b"\\n")?,
+            '\r' => writer.write_all(b"\\r")?,
+            '\t' => writer.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(writer, "\\u{:04x}", c as u32)?,
+            c => {
+                let mut buf = [0u8; 4];
+                writer.write_all(c.encode_utf8(&mut buf).as_bytes())?;
+            }
+        }
+    }
+    writer.write_all(b"\"")?;
+    Ok(())
+}
+
+/// Formats a double as ECMAScript's `Number.prototype.toString` does.
+///
+/// Rust's `{:e}` formatting yields the shortest digits that round-trip,
+/// which is what ECMAScript requires; only their layout differs.
+pub fn format_es_number(n: f64) -> Result<String> {
+    if !n.is_finite() {
+        return Err(PolicyError::CanonicalizationError(format!("Cannot canonicalize non-finite number {}", n)));
+    }
+    if n == 0.0 {
+        // Covers -0, which ECMAScript prints as 0.
+        return Ok("0".to_string());
+    }
+
+    let scientific = format!("{:e}", n.abs());
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .ok_or_else(|| PolicyError::InternalError(format!("Unexpected float format '{}'", scientific)))?;
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let exponent: i32 = exponent
+        .parse()
+        .map_err(|_| PolicyError::InternalError(format!("Unexpected float format '{}'", scientific)))?;
+
+    // n = 0.digits × 10^point
+    let k = digits.len() as i32;
+    let point = exponent + 1;
+    let mut out = String::new();
+    if n < 0.0 {
+        out.push('-');
+    }
+    if k <= point && point <= 21 {
+        out.push_str(&digits);
+        out.extend(std::iter::repeat_n('0', (point - k) as usize));
+    } else if 0 < point && point <= 21 {
+        out.push_str(&digits[..point as usize]);
+        out.push('.');
+        out.push_str(&digits[point as usize..]);
+    } else if -6 < point && point <= 0 {
+        out.push_str("0.");
+        out.extend(std::iter::repeat_n('0', (-point) as usize));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        out.push('e');
+        out.push(if point > 0 { '+' } else { '-' });
+        out.push_str(&(point - 1).abs().to_string());
+    }
+    Ok(out)
+}
+
 /// Writes a JSON-escaped string.
 fn write_escaped_string<W: Write>(writer: &mut W, s: &str) -> Result<()> {
     writer.write_all(b"\"")?;
@@ -121,7 +324,12 @@ impl From<std::fmt::Error> for PolicyError {
 
 /// Computes the canonical hash of a JSON value.
 pub fn canonical_hash(value: &Value) -> Result<String> {
-    let canonical = canonicalize(value)?;
+    canonical_hash_with(value, CanonicalizationMode::V1)
+}
+
+/// Computes the canonical hash of a JSON value using the given rules.
+pub fn canonical_hash_with(value: &Value, mode: CanonicalizationMode) -> Result<String> {
+    let canonical = canonicalize_with(value, mode)?;
     Ok(crate::hash::sha256_str(&canonical))
 }
 
@@ -187,6 +395,86 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_v1_is_unchanged() {
+        assert_eq!(canonicalize(&json!("cafe\u{0301}\r\n")).unwrap(), "\"cafe\u{0301}\\n\"");
+        assert_eq!(canonicalize(&json!("\u{8}\u{7f}")).unwrap(), "\"\\u0008\\u007f\"");
+        assert_eq!(canonicalize(&json!(1.0)).unwrap(), "1.0");
+    }
+
+    #[test]
+    fn test_jcs_rfc8785_example() {
+        // The RFC writes the first number as 333333333.33333329, which is
+        // rejected since it canonicalizes to a different value
+        let json = r#"{"numbers":[333333333.3333333,1E30,4.50,2e-3,0.000000000000000000000000001],"string":"\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/","literals":[null,true,false]}"#;
+        assert_eq!(
+            canonicalize_str(json, CanonicalizationMode::Jcs).unwrap(),
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+        );
+        assert!(canonicalize_str("[333333333.33333329]", CanonicalizationMode::Jcs).is_err());
+    }
+
+    #[test]
+    fn test_jcs_numbers() {
+        let cases = [
+            (0.0, "0"),
+            (-0.0, "0"),
+            (1.0, "1"),
+            (-1.5, "-1.5"),
+            (1e20, "100000000000000000000"),
+            (1e21, "1e+21"),
+            (0.000001, "0.000001"),
+            (1e-7, "1e-7"),
+            (123456789012.5, "123456789012.5"),
+            (9007199254740993.0, "9007199254740992"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (5e-324, "5e-324"),
+            (-1.5e-10, "-1.5e-10"),
+        ];
+        for (n, expected) in cases {
+            assert_eq!(format_es_number(n).unwrap(), expected, "{}", n);
+        }
+        assert!(format_es_number(f64::NAN).is_err());
+        assert!(format_es_number(f64::INFINITY).is_err());
+
+        let jcs = |v: Value| canonicalize_with(&v, CanonicalizationMode::Jcs).unwrap();
+        assert_eq!(jcs(json!(1.0)), jcs(json!(1)));
+        assert_eq!(jcs(json!(u64::MAX)), "18446744073709552000");
+    }
+
+    #[test]
+    fn test_jcs_strings_and_keys() {
+        let jcs = |v: &Value| canonicalize_with(v, CanonicalizationMode::Jcs);
+
+        // Keys sort by UTF-16 code units: the surrogate pair of U+1F600
+        // sorts before U+FB01, unlike in code point order.
+        let value = json!({"\u{FB01}": 1, "\u{1F600}": 2, "\u{00F6}": 3, "\t": 4, "1": 5});
+        assert_eq!(
+            jcs(&value).unwrap(),
+            "{\"\\t\":4,\"1\":5,\"\u{00F6}\":3,\"\u{1F600}\":2,\"\u{FB01}\":1}"
+        );
+
+        // Strings are NFC-normalized with \n line endings; DEL and U+2028
+        // are not escaped
+        assert_eq!(jcs(&json!("cafe\u{0301}\u{7f}\u{2028}\r\n")).unwrap(), "\"caf\u{00E9}\u{7f}\u{2028}\\n\"");
+        assert!(jcs(&json!({"caf\u{00E9}": 1, "cafe\u{0301}": 2})).is_err());
+        assert!(jcs(&json!({"a\r": 1, "a\n": 2})).is_err());
+
+        assert_ne!(
+            canonical_hash_with(&json!({"a": 1.0}), CanonicalizationMode::Jcs).unwrap(),
+            canonical_hash(&json!({"a": 1.0})).unwrap()
+        );
+        assert_eq!("jcs".parse::<CanonicalizationMode>().unwrap(), CanonicalizationMode::Jcs);
+        assert!("v2".parse::<CanonicalizationMode>().is_err());
+    }
+
+    #[test]
+    fn test_canonicalize_str() {
+        assert_eq!(canonicalize_str(r#"{"b": 1, "a": [true]}"#, CanonicalizationMode::V1).unwrap(), r#"{"a":[true],"b":1}"#);
+        // serde_json would keep the last value
+        assert!(canonicalize_str(r#"{"a": 1, "a": 2}"#, CanonicalizationMode::Jcs).is_err());
+    }
+
     #[test]
     fn test_remove_field() {
         let obj = json!({"a": 1, "b": 2, "cid": "xxx"});
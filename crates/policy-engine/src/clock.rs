@@ -0,0 +1,98 @@
+//! Time sources for evaluation.
+//!
+//! The evaluator reads the current time through a `Clock`, both to resolve
+//! `environment.now` and to measure `evaluation_time_us`. `SystemClock` is
+//! the default; `FixedClock` injects a time for tests and for hosts (such as
+//! WASM) that supply their own.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt::Debug;
+
+/// A source of wall-clock time and, optionally, elapsed time.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current wall-clock time.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Returns a monotonic reading in microseconds, or `None` if this clock
+    /// cannot measure elapsed time.
+    fn monotonic_micros(&self) -> Option<u64>;
+}
+
+/// Formats a time as the RFC 3339 string used for `environment.now`.
+pub fn format_now(now: DateTime<Utc>) -> String {
+    now.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// The system clock.
+///
+/// Elapsed time is not measured on `wasm32`, where `std::time::Instant`
+/// is unavailable.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn monotonic_micros(&self) -> Option<u64> {
+        use std::sync::OnceLock;
+        use std::time::Instant;
+
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        Some(ORIGIN.get_or_init(Instant::now).elapsed().as_micros() as u64)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn monotonic_micros(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// A clock that always reports the same time and never measures elapsed time.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock {
+    now: DateTime<Utc>,
+}
+
+impl FixedClock {
+    /// Creates a clock fixed at the given time.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self { now }
+    }
+
+    /// Creates a clock fixed at the given Unix time in milliseconds.
+    pub fn from_unix_millis(millis: i64) -> Option<Self> {
+        DateTime::from_timestamp_millis(millis).map(Self::new)
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.now
+    }
+
+    fn monotonic_micros(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_clock() {
+        let clock = FixedClock::from_unix_millis(1_767_225_600_123).unwrap();
+        assert_eq!(format_now(clock.now()), "2026-01-01T00:00:00.123Z");
+        assert_eq!(clock.monotonic_micros(), None);
+    }
+
+    #[test]
+    fn test_system_clock_is_monotonic() {
+        let clock = SystemClock;
+        let start = clock.monotonic_micros().unwrap();
+        assert!(clock.monotonic_micros().unwrap() >= start);
+    }
+}
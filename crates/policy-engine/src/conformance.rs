@@ -0,0 +1,284 @@
+//! Canonicalization and hashing conformance vectors.
+//!
+//! Receipts are hashed by the Workers and re-verified by this crate, so both
+//! must produce byte-identical canonical JSON and hashes. The crate ships a
+//! versioned suite of test vectors per canonicalization mode (the JSON
+//! files under `conformance/`), each giving an input JSON text and the
+//! expected:
+//!
+//! - `canonical`: the canonical JSON of the input;
+//! - `body_hash`: `"b:" + sha256(canonical)`;
+//! - `cid`: `"c:" + sha256(canonical JSON of the input without its "cid" field)`;
+//! - `head_hash`: `"h:" + sha256(prev_hash + ":" + cid)`.
+//!
+//! Vectors marked `error` are inputs a conforming implementation rejects.
+//! Inputs are parsed with `crate::strict_json`.
+//!
+//! The Workers canonicalize with `canon.ts`, which is checked against both
+//! suites (`workers/ubl-mvp1/test/canon.test.ts`). Vectors it disagrees
+//! with say how in `known_divergence`: `canon.ts` does not implement `v1`,
+//! and it follows the `jcs` rules except at their edges: it canonicalizes
+//! values rather than text, so it cannot see duplicate keys, it lists
+//! integer-like keys first, and it lets lone surrogates and keys that
+//! collide after normalization through.
+//!
+//! Any implementation can be checked against a suite: in Rust by passing a
+//! function to `run`, from JavaScript by computing `Outputs` for every vector
+//! and handing them to `check_outputs` (exposed through WASM, so a Worker
+//! can self-test on deploy).
+
+use crate::canonicalization::{canonicalize_with, remove_field, CanonicalizationMode};
+use crate::error::{PolicyError, Result};
+use crate::hash;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const V1_VECTORS: &str = include_str!("../conformance/v1.json");
+const JCS_VECTORS: &str = include_str!("../conformance/jcs.json");
+
+/// A versioned set of vectors for one canonicalization mode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VectorSuite {
+    /// Incremented whenever vectors are changed or removed.
+    pub version: u32,
+    pub mode: CanonicalizationMode,
+    pub vectors: Vec<Vector>,
+}
+
+/// One input and its expected outputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Vector {
+    pub name: String,
+
+    /// The input, as JSON text.
+    pub input: String,
+
+    /// The head hash the input is chained onto.
+    #[serde(default = "genesis")]
+    pub prev_hash: String,
+
+    /// How the Workers' `canon.ts` differs on this vector, if it does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub known_divergence: Option<String>,
+
+    /// True if the input must be rejected; the other outputs are absent.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub error: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_hash: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head_hash: Option<String>,
+}
+
+fn genesis() -> String {
+    hash::GENESIS_HASH.to_string()
+}
+
+/// What an implementation computed for a vector. Outputs left out are not
+/// checked; `error` reports that the implementation rejected the input.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Outputs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_hash: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head_hash: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The result of checking an implementation against a suite.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConformanceReport {
+    pub version: u32,
+    pub mode: CanonicalizationMode,
+    /// The number of vectors every checked output matched.
+    pub passed: usize,
+    pub failures: Vec<Failure>,
+}
+
+impl ConformanceReport {
+    /// Returns true if every vector passed.
+    pub fn is_conformant(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// An output that differed from the vector.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure {
+    pub vector: String,
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Returns the vectors shipped for a mode.
+pub fn suite(mode: CanonicalizationMode) -> Result<VectorSuite> {
+    serde_json::from_str(suite_json(mode)).map_err(|e| PolicyError::InternalError(format!("Invalid vectors: {}", e)))
+}
+
+/// Returns the vectors shipped for a mode as JSON text.
+pub fn suite_json(mode: CanonicalizationMode) -> &'static str {
+    match mode {
+        CanonicalizationMode::V1 => V1_VECTORS,
+        CanonicalizationMode::Jcs => JCS_VECTORS,
+    }
+}
+
+/// Computes a vector's outputs with this crate.
+pub fn compute(input: &str, prev_hash: &str, mode: CanonicalizationMode) -> Result<Outputs> {
+    let value = crate::strict_json::parse(input, mode)?;
+    let canonical = canonicalize_with(&value, mode)?;
+    let cid = hash::compute_cid(&canonicalize_with(&remove_field(&value, "cid"), mode)?);
+    Ok(Outputs {
+        body_hash: Some(hash::compute_body_hash(&canonical)),
+        head_hash: Some(hash::compute_head_hash(prev_hash, &cid)),
+        canonical: Some(canonical),
+        cid: Some(cid),
+        error: None,
+    })
+}
+
+/// Checks an implementation against a suite by running it on every vector.
+pub fn run<F>(suite: &VectorSuite, mut implementation: F) -> ConformanceReport
+where
+    F: FnMut(&Vector) -> Outputs,
+{
+    let mut report = ConformanceReport {
+        version: suite.version,
+        mode: suite.mode,
+        passed: 0,
+        failures: Vec::new(),
+    };
+    for vector in &suite.vectors {
+        let failures = check_vector(vector, &implementation(vector));
+        if failures.is_empty() {
+            report.passed += 1;
+        }
+        report.failures.extend(failures);
+    }
+    report
+}
+
+/// Checks precomputed outputs, keyed by vector name, against a suite.
+/// Vectors without outputs fail.
+pub fn check_outputs(suite: &VectorSuite, outputs: &HashMap<String, Outputs>) -> ConformanceReport {
+    run(suite, |vector| outputs.get(&vector.name).cloned().unwrap_or_default())
+}
+
+/// Checks the crate's own implementation against a suite.
+pub fn self_test(suite: &VectorSuite) -> ConformanceReport {
+    run(suite, |vector| {
+        compute(&vector.input, &vector.prev_hash, suite.mode).unwrap_or_else(|e| Outputs {
+            error: Some(e.to_string()),
+            ..Outputs::default()
+        })
+    })
+}
+
+fn check_vector(vector: &Vector, outputs: &Outputs) -> Vec<Failure> {
+    let failure = |field: &str, expected: &str, actual: &str| Failure {
+        vector: vector.name.clone(),
+        field: field.to_string(),
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+    };
+
+    if vector.error {
+        return match outputs.error {
+            Some(_) => Vec::new(),
+            None => vec![failure("error", "an error", "no error")],
+        };
+    }
+    if let Some(error) = &outputs.error {
+        return vec![failure("error", "no error", error)];
+    }
+
+    let fields = [
+        ("canonical", &vector.canonical, &outputs.canonical),
+        ("body_hash", &vector.body_hash, &outputs.body_hash),
+        ("cid", &vector.cid, &outputs.cid),
+        ("head_hash", &vector.head_hash, &outputs.head_hash),
+    ];
+    if fields.iter().all(|(_, _, actual)| actual.is_none()) {
+        return vec![failure("outputs", "at least one output", "none")];
+    }
+    fields
+        .iter()
+        .filter_map(|(field, expected, actual)| {
+            let (expected, actual) = (expected.as_deref().unwrap_or_default(), actual.as_deref()?);
+            (expected != actual).then(|| failure(field, expected, actual))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test() {
+        for mode in [CanonicalizationMode::V1, CanonicalizationMode::Jcs] {
+            let suite = suite(mode).unwrap();
+            assert_eq!(suite.mode, mode);
+            assert!(!suite.vectors.is_empty());
+            let report = self_test(&suite);
+            assert!(report.is_conformant(), "{:?}", report.failures);
+            assert_eq!(report.passed, suite.vectors.len());
+        }
+    }
+
+    #[test]
+    fn test_check_outputs() {
+        let suite = suite(CanonicalizationMode::Jcs).unwrap();
+        let mut outputs: HashMap<String, Outputs> = suite
+            .vectors
+            .iter()
+            .map(|v| {
+                let outputs = compute(&v.input, &v.prev_hash, suite.mode).unwrap_or_else(|e| Outputs {
+                    error: Some(e.to_string()),
+                    ..Outputs::default()
+                });
+                (v.name.clone(), outputs)
+            })
+            .collect();
+        assert!(check_outputs(&suite, &outputs).is_conformant());
+
+        // The v1 rules disagree with JCS on numbers
+        let input = &suite.vectors.iter().find(|v| v.name == "numbers").unwrap().input;
+        let numbers = compute(input, hash::GENESIS_HASH, CanonicalizationMode::V1).unwrap();
+        outputs.insert("numbers".to_string(), numbers);
+        // Only canonicalizing is fine
+        outputs.insert(
+            "empty-object".to_string(),
+            Outputs {
+                canonical: Some("{}".to_string()),
+                ..Outputs::default()
+            },
+        );
+        outputs.remove("escapes");
+
+        let report = check_outputs(&suite, &outputs);
+        assert_eq!(report.passed, suite.vectors.len() - 2);
+        assert!(report.failures.iter().any(|f| f.vector == "numbers" && f.field == "canonical"));
+        assert!(report.failures.iter().any(|f| f.vector == "escapes" && f.field == "outputs"));
+    }
+}
@@ -1,8 +1,11 @@
 //! Evaluation context for policy decisions.
 
+use crate::agreement::Agreement;
 use crate::error::{PolicyError, Result};
 use crate::types::{Action, Environment, Identity, Resource, Role, Tenant};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 /// The complete context for a policy evaluation.
@@ -23,6 +26,10 @@ pub struct EvaluationContext {
     /// The role of the identity in the current context.
     pub role: Option<Role>,
 
+    /// The agreement the request is made under, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agreement: Option<Agreement>,
+
     /// Environment information.
     #[serde(default)]
     pub environment: Environment,
@@ -32,6 +39,51 @@ pub struct EvaluationContext {
     pub attributes: HashMap<String, serde_json::Value>,
 }
 
+/// A context as one evaluation reads it: `environment.now`, and so the
+/// effective `agreement.status`, is the evaluation time rather than whatever
+/// the request carries. Everything else is read from the context.
+#[derive(Debug, Clone, Copy)]
+pub struct ContextAt<'a> {
+    context: &'a EvaluationContext,
+    now: Option<&'a str>,
+}
+
+impl<'a> ContextAt<'a> {
+    /// Reads the context at `now` (RFC 3339).
+    pub fn new(context: &'a EvaluationContext, now: &'a str) -> Self {
+        Self { context, now: Some(now) }
+    }
+
+    /// Returns the evaluation time, if there is one.
+    pub fn now(&self) -> Option<&'a str> {
+        self.now
+    }
+
+    /// Gets a value from the context by field path (see
+    /// `EvaluationContext::get_value`).
+    pub fn get_value(&self, field_path: &str) -> Option<serde_json::Value> {
+        self.context.get_value_at(field_path, self.now)
+    }
+}
+
+/// Reads the context at the `environment.now` it carries.
+impl<'a> From<&'a EvaluationContext> for ContextAt<'a> {
+    fn from(context: &'a EvaluationContext) -> Self {
+        Self {
+            context,
+            now: context.environment.now.as_deref(),
+        }
+    }
+}
+
+impl std::ops::Deref for ContextAt<'_> {
+    type Target = EvaluationContext;
+
+    fn deref(&self) -> &EvaluationContext {
+        self.context
+    }
+}
+
 impl EvaluationContext {
     /// Creates a new evaluation context.
     pub fn new(
@@ -46,6 +98,7 @@ impl EvaluationContext {
             resource,
             action,
             role: None,
+            agreement: None,
             environment: Environment::default(),
             attributes: HashMap::new(),
         }
@@ -57,6 +110,12 @@ impl EvaluationContext {
         self
     }
 
+    /// Sets the agreement the request is made under.
+    pub fn with_agreement(mut self, agreement: Agreement) -> Self {
+        self.agreement = Some(agreement);
+        self
+    }
+
     /// Sets the environment for this context.
     pub fn with_environment(mut self, environment: Environment) -> Self {
         self.environment = environment;
@@ -77,36 +136,73 @@ impl EvaluationContext {
     /// - "resource.resource_type"
     /// - "action.action_name"
     /// - "role"
+    /// - "agreement.status"
+    /// - "environment.now"
     /// - "environment.ip_address"
     /// - "attributes.custom_field"
+    ///
+    /// Below `attributes`, `environment.attributes` and `identity.groups`,
+    /// paths can descend into nested values: `attributes.tool.args.path`,
+    /// `attributes.items[0]`, `attributes["key.with.dots"]`. A `*` or `[*]`
+    /// segment projects over every element of an array (or value of an
+    /// object), producing an array of the matches: `attributes.items[*].id`.
+    /// The same goes for `agreement.capabilities`, `agreement.parties`,
+    /// `agreement.party_ids` and `agreement.metadata`.
     pub fn get_value(&self, field_path: &str) -> Option<serde_json::Value> {
-        let parts: Vec<&str> = field_path.split('.').collect();
+        self.get_value_at(field_path, self.environment.now.as_deref())
+    }
 
-        if parts.is_empty() {
-            return None;
-        }
+    /// Gets a value as `get_value` does, reading `now` as `environment.now`.
+    fn get_value_at(&self, field_path: &str, now: Option<&str>) -> Option<serde_json::Value> {
+        let segments = parse_path(field_path)?;
+        let (base, rest) = self.resolve_base(&segments, now)?;
+        project(&base, rest)
+    }
 
-        match parts[0] {
-            "identity" => self.get_identity_field(&parts[1..]),
-            "tenant" => self.get_tenant_field(&parts[1..]),
-            "resource" => self.get_resource_field(&parts[1..]),
-            "action" => self.get_action_field(&parts[1..]),
-            "role" => self.role.as_ref().map(|r| serde_json::json!(r.as_str())),
-            "environment" => self.get_environment_field(&parts[1..]),
-            "attributes" => {
-                if parts.len() > 1 {
-                    self.attributes.get(parts[1]).cloned()
-                } else {
-                    Some(serde_json::json!(self.attributes))
-                }
-            }
+    /// Resolves the typed part of a path, returning its value and the
+    /// segments left to apply to it.
+    fn resolve_base<'s>(
+        &self,
+        segments: &'s [PathSegment],
+        now: Option<&str>,
+    ) -> Option<(Cow<'_, serde_json::Value>, &'s [PathSegment])> {
+        let key = |i: usize| match segments.get(i) {
+            Some(PathSegment::Key(key)) => Some(key.as_str()),
             _ => None,
+        };
+
+        let field = |value: Option<serde_json::Value>, consumed: usize| value.map(|v| (Cow::Owned(v), &segments[consumed..]));
+
+        match key(0)? {
+            "role" => field(self.role.as_ref().map(|r| serde_json::json!(r.as_str())), 1),
+            "attributes" => map_base(&self.attributes, segments, 1),
+            "environment" if key(1) == Some("attributes") => map_base(&self.environment.attributes, segments, 2),
+            "agreement" if key(1) == Some("metadata") => map_base(&self.agreement.as_ref()?.metadata, segments, 2),
+            root => {
+                // Typed objects are one level deep; only whole objects and
+                // their named fields resolve.
+                let (name, consumed) = match segments.len() {
+                    1 => (None, 1),
+                    _ => (Some(key(1)?), 2),
+                };
+                let parts: Vec<&str> = name.into_iter().collect();
+                let value = match root {
+                    "identity" => self.get_identity_field(&parts),
+                    "tenant" => self.get_tenant_field(&parts),
+                    "resource" => self.get_resource_field(&parts),
+                    "action" => self.get_action_field(&parts),
+                    "environment" => self.get_environment_field(&parts, now),
+                    "agreement" => self.get_agreement_field(&parts, now),
+                    _ => None,
+                };
+                field(value, consumed)
+            }
         }
     }
 
     fn get_identity_field(&self, parts: &[&str]) -> Option<serde_json::Value> {
         if parts.is_empty() {
-            return Some(serde_json::to_value(&self.identity).ok()?);
+            return serde_json::to_value(&self.identity).ok();
         }
 
         match parts[0] {
@@ -121,7 +217,7 @@ impl EvaluationContext {
 
     fn get_tenant_field(&self, parts: &[&str]) -> Option<serde_json::Value> {
         if parts.is_empty() {
-            return Some(serde_json::to_value(&self.tenant).ok()?);
+            return serde_json::to_value(&self.tenant).ok();
         }
 
         match parts[0] {
@@ -133,7 +229,7 @@ impl EvaluationContext {
 
     fn get_resource_field(&self, parts: &[&str]) -> Option<serde_json::Value> {
         if parts.is_empty() {
-            return Some(serde_json::to_value(&self.resource).ok()?);
+            return serde_json::to_value(&self.resource).ok();
         }
 
         match parts[0] {
@@ -147,7 +243,7 @@ impl EvaluationContext {
 
     fn get_action_field(&self, parts: &[&str]) -> Option<serde_json::Value> {
         if parts.is_empty() {
-            return Some(serde_json::to_value(&self.action).ok()?);
+            return serde_json::to_value(&self.action).ok();
         }
 
         match parts[0] {
@@ -157,24 +253,104 @@ impl EvaluationContext {
         }
     }
 
-    fn get_environment_field(&self, parts: &[&str]) -> Option<serde_json::Value> {
+    fn get_agreement_field(&self, parts: &[&str], now: Option<&str>) -> Option<serde_json::Value> {
+        let agreement = self.agreement.as_ref()?;
+        if parts.is_empty() {
+            return serde_json::to_value(agreement).ok();
+        }
+
+        match parts[0] {
+            "id" => Some(serde_json::json!(agreement.id)),
+            "type" => Some(serde_json::json!(agreement.agreement_type.as_str())),
+            "tenant_id" => Some(serde_json::json!(agreement.tenant_id)),
+            "status" => {
+                let now = now.and_then(|now| DateTime::parse_from_rfc3339(now).ok());
+                let status = match now {
+                    Some(now) => agreement.status_at(now.with_timezone(&Utc)),
+                    None => agreement.status,
+                };
+                Some(serde_json::json!(status.as_str()))
+            }
+            "parties" => serde_json::to_value(&agreement.parties).ok(),
+            "party_ids" => Some(serde_json::json!(agreement
+                .parties
+                .iter()
+                .map(|party| party.id.as_str())
+                .collect::<Vec<_>>())),
+            "capabilities" => Some(serde_json::json!(agreement.capabilities)),
+            "created_by" => agreement.created_by.as_ref().map(|v| serde_json::json!(v)),
+            "created_at" => agreement.created_at.as_ref().map(|v| serde_json::json!(v)),
+            "expires_at" => agreement.expires_at.as_ref().map(|v| serde_json::json!(v)),
+            _ => None,
+        }
+    }
+
+    fn get_environment_field(&self, parts: &[&str], now: Option<&str>) -> Option<serde_json::Value> {
         if parts.is_empty() {
-            return Some(serde_json::to_value(&self.environment).ok()?);
+            let mut environment = serde_json::to_value(&self.environment).ok()?;
+            if let Some(now) = now {
+                environment["now"] = serde_json::json!(now);
+            }
+            return Some(environment);
         }
 
         match parts[0] {
+            "now" => now.map(|v| serde_json::json!(v)),
             "timestamp" => self.environment.timestamp.as_ref().map(|v| serde_json::json!(v)),
             "request_id" => self.environment.request_id.as_ref().map(|v| serde_json::json!(v)),
             "ip_address" => self.environment.ip_address.as_ref().map(|v| serde_json::json!(v)),
             "user_agent" => self.environment.user_agent.as_ref().map(|v| serde_json::json!(v)),
-            "attributes" => {
-                if parts.len() > 1 {
-                    self.environment.attributes.get(parts[1]).cloned()
-                } else {
-                    Some(serde_json::json!(self.environment.attributes))
+            _ => None,
+        }
+    }
+
+    /// Returns true if `field_path` names a field that `get_value` can resolve.
+    ///
+    /// Free-form attribute paths (`attributes.*`, `environment.attributes.*`)
+    /// are always considered known since their keys are not declared up front.
+    pub fn is_known_path(field_path: &str) -> bool {
+        let Some(segments) = parse_path(field_path) else {
+            return false;
+        };
+        let keys: Vec<Option<&str>> = segments
+            .iter()
+            .map(|segment| match segment {
+                PathSegment::Key(key) => Some(key.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        let fields: &[&str] = match keys[0] {
+            Some("identity") => {
+                if keys.get(1) == Some(&Some("groups")) {
+                    return true;
                 }
+                &["user_id", "email", "email_domain", "groups", "is_service"]
             }
-            _ => None,
+            Some("tenant") => &["tenant_id", "tenant_type"],
+            Some("resource") => &["resource_type", "resource_id", "owner_id", "agreement_id"],
+            Some("action") => &["action_type", "action_name"],
+            Some("role") => return segments.len() == 1,
+            Some("agreement") => {
+                if matches!(keys.get(1), Some(Some("metadata" | "capabilities" | "parties" | "party_ids"))) {
+                    return true;
+                }
+                &["id", "type", "tenant_id", "status", "created_by", "created_at", "expires_at"]
+            }
+            Some("attributes") => return true,
+            Some("environment") => {
+                if keys.get(1) == Some(&Some("attributes")) {
+                    return true;
+                }
+                &["now", "timestamp", "request_id", "ip_address", "user_agent"]
+            }
+            _ => return false,
+        };
+
+        match segments.len() {
+            1 => true,
+            2 => keys[1].is_some_and(|key| fields.contains(&key)),
+            _ => false,
         }
     }
 
@@ -193,6 +369,148 @@ impl EvaluationContext {
     }
 }
 
+/// A segment of a field path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    /// An object key: `.name` or `["name"]`.
+    Key(String),
+    /// An array index: `[0]`.
+    Index(usize),
+    /// Every element or value: `.*` or `[*]`.
+    Wildcard,
+}
+
+/// Parses a field path, returning `None` if it is malformed.
+pub fn parse_path(path: &str) -> Option<Vec<PathSegment>> {
+    let mut segments = Vec::new();
+    let mut chars = path.chars().peekable();
+    let mut expect_key = true;
+
+    while let Some(c) = chars.peek().copied() {
+        match c {
+            '[' if !expect_key => {
+                chars.next();
+                let segment = match chars.peek() {
+                    Some('*') => {
+                        chars.next();
+                        PathSegment::Wildcard
+                    }
+                    Some('"') => {
+                        chars.next();
+                        let mut key = String::new();
+                        loop {
+                            match chars.next()? {
+                                '"' => break,
+                                '\\' => key.push(chars.next()?),
+                                c => key.push(c),
+                            }
+                        }
+                        PathSegment::Key(key)
+                    }
+                    _ => {
+                        let mut digits = String::new();
+                        while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                            digits.push(*c);
+                            chars.next();
+                        }
+                        PathSegment::Index(digits.parse().ok()?)
+                    }
+                };
+                if chars.next()? != ']' {
+                    return None;
+                }
+                segments.push(segment);
+                expect_key = false;
+            }
+            '.' if !expect_key => {
+                chars.next();
+                expect_key = true;
+            }
+            _ if expect_key => {
+                let mut key = String::new();
+                while let Some(c) = chars.peek().filter(|c| **c != '.' && **c != '[') {
+                    key.push(*c);
+                    chars.next();
+                }
+                if key.is_empty() {
+                    return None;
+                }
+                segments.push(if key == "*" { PathSegment::Wildcard } else { PathSegment::Key(key) });
+                expect_key = false;
+            }
+            _ => return None,
+        }
+    }
+
+    if expect_key || !matches!(segments.first(), Some(PathSegment::Key(_))) {
+        return None;
+    }
+    Some(segments)
+}
+
+/// Resolves a free-form map (`attributes`) at `depth`, looking its first
+/// key up directly rather than converting the whole map.
+fn map_base<'a, 's>(
+    map: &'a HashMap<String, serde_json::Value>,
+    segments: &'s [PathSegment],
+    depth: usize,
+) -> Option<(Cow<'a, serde_json::Value>, &'s [PathSegment])> {
+    match segments.get(depth) {
+        Some(PathSegment::Key(key)) => map.get(key).map(|v| (Cow::Borrowed(v), &segments[depth + 1..])),
+        _ => Some((Cow::Owned(serde_json::json!(map)), &segments[depth..])),
+    }
+}
+
+/// Applies path segments to a value. Paths with a wildcard produce an
+/// array of every match; others produce the single value, if present.
+fn project(value: &serde_json::Value, segments: &[PathSegment]) -> Option<serde_json::Value> {
+    if segments.contains(&PathSegment::Wildcard) {
+        let mut matches = Vec::new();
+        collect_matches(value, segments, &mut matches);
+        return Some(serde_json::Value::Array(matches.into_iter().cloned().collect()));
+    }
+
+    let mut current = value;
+    for segment in segments {
+        current = step(current, segment)?;
+    }
+    Some(current.clone())
+}
+
+fn step<'a>(value: &'a serde_json::Value, segment: &PathSegment) -> Option<&'a serde_json::Value> {
+    match (segment, value) {
+        (PathSegment::Key(key), serde_json::Value::Object(map)) => map.get(key),
+        (PathSegment::Index(index), serde_json::Value::Array(items)) => items.get(*index),
+        _ => None,
+    }
+}
+
+fn collect_matches<'a>(value: &'a serde_json::Value, segments: &[PathSegment], matches: &mut Vec<&'a serde_json::Value>) {
+    let Some((segment, rest)) = segments.split_first() else {
+        matches.push(value);
+        return;
+    };
+
+    match (segment, value) {
+        (PathSegment::Wildcard, serde_json::Value::Array(items)) => {
+            for item in items {
+                collect_matches(item, rest, matches);
+            }
+        }
+        (PathSegment::Wildcard, serde_json::Value::Object(map)) => {
+            for item in map.values() {
+                collect_matches(item, rest, matches);
+            }
+        }
+        (PathSegment::Wildcard, _) => {}
+        (segment, value) => {
+            if let Some(next) = step(value, segment) {
+                collect_matches(next, rest, matches);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -249,6 +567,95 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_is_known_path() {
+        assert!(EvaluationContext::is_known_path("identity.user_id"));
+        assert!(EvaluationContext::is_known_path("resource.owner_id"));
+        assert!(EvaluationContext::is_known_path("role"));
+        assert!(EvaluationContext::is_known_path("attributes.anything"));
+        assert!(EvaluationContext::is_known_path("environment.attributes.region"));
+        assert!(EvaluationContext::is_known_path("attributes.tool.files[*].id"));
+        assert!(EvaluationContext::is_known_path("identity.groups[0]"));
+        assert!(EvaluationContext::is_known_path("agreement.status"));
+        assert!(EvaluationContext::is_known_path("agreement.parties[*].role"));
+        assert!(EvaluationContext::is_known_path("agreement.metadata.room_id"));
+        assert!(!EvaluationContext::is_known_path("agreement.state"));
+        assert!(!EvaluationContext::is_known_path("identity.user_id[0]"));
+        assert!(!EvaluationContext::is_known_path("attributes..x"));
+        assert!(!EvaluationContext::is_known_path("identity.nope"));
+        assert!(!EvaluationContext::is_known_path("role.name"));
+        assert!(!EvaluationContext::is_known_path("unknown"));
+    }
+
+    #[test]
+    fn test_nested_paths() {
+        let ctx = create_test_context()
+            .with_attribute(
+                "tool",
+                serde_json::json!({
+                    "name": "fs.read",
+                    "args": { "path": "/etc/hosts", "flags": ["r", "b"] },
+                    "files": [{ "id": "f1", "size": 10 }, { "id": "f2" }, { "id": "f3", "size": 30 }],
+                }),
+            )
+            .with_attribute("a.b", serde_json::json!(1));
+
+        assert_eq!(ctx.get_value("attributes.tool.args.path"), Some(serde_json::json!("/etc/hosts")));
+        assert_eq!(ctx.get_value("attributes.tool.args.flags[1]"), Some(serde_json::json!("b")));
+        assert_eq!(ctx.get_value("attributes.tool.files[0].id"), Some(serde_json::json!("f1")));
+        assert_eq!(ctx.get_value("attributes.tool.files[5].id"), None);
+        assert_eq!(ctx.get_value("attributes.tool.name.first"), None);
+        assert_eq!(ctx.get_value("attributes[\"a.b\"]"), Some(serde_json::json!(1)));
+        assert_eq!(ctx.get_value("identity.groups[0]"), Some(serde_json::json!("admin")));
+
+        // Wildcards project every match, skipping elements without the key
+        assert_eq!(ctx.get_value("attributes.tool.files[*].size"), Some(serde_json::json!([10, 30])));
+        assert_eq!(ctx.get_value("attributes.tool.files.*.id"), Some(serde_json::json!(["f1", "f2", "f3"])));
+        assert_eq!(ctx.get_value("attributes.tool.args.*[0]"), Some(serde_json::json!(["r"])));
+    }
+
+    #[test]
+    fn test_agreement_paths() {
+        use crate::agreement::{AgreementStatus, AgreementType};
+
+        let agreement = Agreement::new("a:room:r:general", AgreementType::RoomGovernance, "t:example.com")
+            .with_status(AgreementStatus::Active)
+            .with_party("u:owner", Some("owner".to_string()))
+            .with_party("u:test", None)
+            .with_capabilities(vec!["send_messages".to_string()])
+            .with_expires_at("2030-01-01T00:00:00Z".parse().unwrap());
+        let mut ctx = create_test_context();
+        assert_eq!(ctx.get_value("agreement.status"), None);
+
+        ctx = ctx.with_agreement(agreement);
+        assert_eq!(ctx.get_value("agreement.type"), Some(serde_json::json!("room_governance")));
+        assert_eq!(ctx.get_value("agreement.status"), Some(serde_json::json!("active")));
+        assert_eq!(ctx.get_value("agreement.capabilities[0]"), Some(serde_json::json!("send_messages")));
+        assert_eq!(ctx.get_value("agreement.party_ids"), Some(serde_json::json!(["u:owner", "u:test"])));
+        assert_eq!(ctx.get_value("agreement.parties[*].role"), Some(serde_json::json!(["owner"])));
+        assert_eq!(ctx.get_value("agreement.expires_at"), Some(serde_json::json!("2030-01-01T00:00:00Z")));
+
+        ctx.environment.now = Some("2031-01-01T00:00:00Z".to_string());
+        assert_eq!(ctx.get_value("agreement.status"), Some(serde_json::json!("expired")));
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse_path("attributes.items[2][*].name"),
+            Some(vec![
+                PathSegment::Key("attributes".to_string()),
+                PathSegment::Key("items".to_string()),
+                PathSegment::Index(2),
+                PathSegment::Wildcard,
+                PathSegment::Key("name".to_string()),
+            ])
+        );
+        for invalid in ["", ".role", "role.", "a..b", "a.[0]", "[0]", "a[x]", "a[1", "a[\"b]", "*.a"] {
+            assert_eq!(parse_path(invalid), None, "{}", invalid);
+        }
+    }
+
     #[test]
     fn test_validate() {
         let ctx = create_test_context();
@@ -1,14 +1,104 @@
 //! Policy decision types.
 
-use crate::types::Effect;
+use crate::types::{CombiningAlgorithm, Effect, Obligation};
 use serde::{Deserialize, Serialize};
 
-/// The final decision from policy evaluation.
+/// The decision from policy evaluation.
+///
+/// Follows XACML 3.0: besides allow and deny, a policy may be not applicable
+/// to the request, or indeterminate because it could not be evaluated. The
+/// indeterminate variants record which effects could have resulted had the
+/// evaluation succeeded (D = deny, P = allow, DP = either).
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "snake_case")]
 pub enum Decision {
     Allow,
     Deny,
+    NotApplicable,
+    IndeterminateD,
+    IndeterminateP,
+    #[serde(rename = "indeterminate_dp")]
+    IndeterminateDP,
+}
+
+impl Decision {
+    /// Returns the indeterminate decision for a rule or policy with the given effect.
+    pub fn indeterminate_for(effect: Effect) -> Self {
+        match effect {
+            Effect::Allow => Decision::IndeterminateP,
+            Effect::Deny => Decision::IndeterminateD,
+        }
+    }
+
+    /// Returns true for any of the indeterminate variants.
+    pub fn is_indeterminate(&self) -> bool {
+        matches!(
+            self,
+            Decision::IndeterminateD | Decision::IndeterminateP | Decision::IndeterminateDP
+        )
+    }
+
+    /// Returns the effect for allow/deny decisions.
+    pub fn effect(&self) -> Option<Effect> {
+        match self {
+            Decision::Allow => Some(Effect::Allow),
+            Decision::Deny => Some(Effect::Deny),
+            _ => None,
+        }
+    }
+
+    /// Combines decisions using XACML 3.0 combining semantics.
+    ///
+    /// Returns the combined decision and the index of the input that
+    /// determined it (`None` when nothing was applicable). The unanimous
+    /// algorithms behave like the overrides algorithm favouring the effect
+    /// that breaks unanimity.
+    pub fn combine(algorithm: CombiningAlgorithm, decisions: &[Decision]) -> (Decision, Option<usize>) {
+        match algorithm {
+            CombiningAlgorithm::FirstApplicable => decisions
+                .iter()
+                .position(|d| *d != Decision::NotApplicable)
+                .map(|i| (decisions[i], Some(i)))
+                .unwrap_or((Decision::NotApplicable, None)),
+            CombiningAlgorithm::DenyOverrides | CombiningAlgorithm::UnanimousAllow => {
+                Self::overrides(decisions, Effect::Deny)
+            }
+            CombiningAlgorithm::AllowOverrides | CombiningAlgorithm::UnanimousDeny => {
+                Self::overrides(decisions, Effect::Allow)
+            }
+        }
+    }
+
+    /// The XACML 3.0 deny-overrides / permit-overrides algorithm.
+    fn overrides(decisions: &[Decision], winner: Effect) -> (Decision, Option<usize>) {
+        let loser = match winner {
+            Effect::Allow => Effect::Deny,
+            Effect::Deny => Effect::Allow,
+        };
+        let (win, lose) = (Decision::from(winner), Decision::from(loser));
+        let (win_ind, lose_ind) = (Decision::indeterminate_for(winner), Decision::indeterminate_for(loser));
+        let find = |f: &dyn Fn(&Decision) -> bool| decisions.iter().position(f);
+
+        if let Some(i) = find(&|d| *d == win) {
+            return (win, Some(i));
+        }
+        let first_win_ind = find(&|d| *d == win_ind);
+        let any_dp = find(&|d| *d == Decision::IndeterminateDP);
+        let any_lose = decisions.iter().any(|d| *d == lose || *d == lose_ind);
+        if any_dp.is_some() || (first_win_ind.is_some() && any_lose) {
+            return (Decision::IndeterminateDP, find(&|d| d.is_indeterminate()));
+        }
+        if first_win_ind.is_some() {
+            return (win_ind, first_win_ind);
+        }
+        if let Some(i) = find(&|d| *d == lose) {
+            return (lose, Some(i));
+        }
+        if let Some(i) = find(&|d| *d == lose_ind) {
+            return (lose_ind, Some(i));
+        }
+        (Decision::NotApplicable, None)
+    }
 }
 
 impl From<Effect> for Decision {
@@ -20,6 +110,41 @@ impl From<Effect> for Decision {
     }
 }
 
+/// How not-applicable and indeterminate results are mapped to allow/deny at
+/// the enforcement point. Both default to deny.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecisionMapping {
+    pub not_applicable: Effect,
+    pub indeterminate: Effect,
+}
+
+impl Default for DecisionMapping {
+    fn default() -> Self {
+        Self {
+            not_applicable: Effect::Deny,
+            indeterminate: Effect::Deny,
+        }
+    }
+}
+
+impl DecisionMapping {
+    /// Maps a decision to allow/deny, recording the original outcome in
+    /// `raw_decision`. Allow and deny decisions pass through unchanged.
+    pub fn apply(&self, mut decision: PolicyDecision) -> PolicyDecision {
+        let mapped = match decision.decision {
+            Decision::Allow | Decision::Deny => return decision,
+            Decision::NotApplicable => {
+                decision.is_default = true;
+                self.not_applicable
+            }
+            _ => self.indeterminate,
+        };
+        decision.raw_decision = Some(decision.decision);
+        decision.decision = mapped.into();
+        decision
+    }
+}
+
 /// A complete policy decision with metadata.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PolicyDecision {
@@ -38,12 +163,25 @@ pub struct PolicyDecision {
     /// Whether this is a default decision (no matching rules).
     pub is_default: bool,
 
+    /// The not-applicable or indeterminate outcome this decision was mapped
+    /// from by a `DecisionMapping`, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_decision: Option<Decision>,
+
     /// Time taken to evaluate (in microseconds).
     pub evaluation_time_us: Option<u64>,
 
+    /// Obligations the enforcement point must fulfil.
+    #[serde(default)]
+    pub obligations: Vec<Obligation>,
+
+    /// Advice the enforcement point may act on.
+    #[serde(default)]
+    pub advice: Vec<Obligation>,
+
     /// Additional metadata about the decision.
     #[serde(default)]
-    pub metadata: std::collections::HashMap<String, serde_json::Value>,
+    pub metadata: std::collections::BTreeMap<String, serde_json::Value>,
 }
 
 impl PolicyDecision {
@@ -55,8 +193,11 @@ impl PolicyDecision {
             rule_id: None,
             policy_id: None,
             is_default: false,
+            raw_decision: None,
             evaluation_time_us: None,
-            metadata: std::collections::HashMap::new(),
+            obligations: Vec::new(),
+            advice: Vec::new(),
+            metadata: std::collections::BTreeMap::new(),
         }
     }
 
@@ -68,8 +209,11 @@ impl PolicyDecision {
             rule_id: None,
             policy_id: None,
             is_default: false,
+            raw_decision: None,
             evaluation_time_us: None,
-            metadata: std::collections::HashMap::new(),
+            obligations: Vec::new(),
+            advice: Vec::new(),
+            metadata: std::collections::BTreeMap::new(),
         }
     }
 
@@ -81,8 +225,11 @@ impl PolicyDecision {
             rule_id: None,
             policy_id: None,
             is_default: true,
+            raw_decision: None,
             evaluation_time_us: None,
-            metadata: std::collections::HashMap::new(),
+            obligations: Vec::new(),
+            advice: Vec::new(),
+            metadata: std::collections::BTreeMap::new(),
         }
     }
 
@@ -94,8 +241,44 @@ impl PolicyDecision {
             rule_id: None,
             policy_id: None,
             is_default: true,
+            raw_decision: None,
             evaluation_time_us: None,
-            metadata: std::collections::HashMap::new(),
+            obligations: Vec::new(),
+            advice: Vec::new(),
+            metadata: std::collections::BTreeMap::new(),
+        }
+    }
+
+    /// Creates a not-applicable decision.
+    pub fn not_applicable() -> Self {
+        Self {
+            decision: Decision::NotApplicable,
+            reason: "No applicable rules".to_string(),
+            rule_id: None,
+            policy_id: None,
+            is_default: false,
+            raw_decision: None,
+            evaluation_time_us: None,
+            obligations: Vec::new(),
+            advice: Vec::new(),
+            metadata: std::collections::BTreeMap::new(),
+        }
+    }
+
+    /// Creates an indeterminate decision of the given kind.
+    pub fn indeterminate(decision: Decision, reason: impl Into<String>) -> Self {
+        debug_assert!(decision.is_indeterminate());
+        Self {
+            decision,
+            reason: reason.into(),
+            rule_id: None,
+            policy_id: None,
+            is_default: false,
+            raw_decision: None,
+            evaluation_time_us: None,
+            obligations: Vec::new(),
+            advice: Vec::new(),
+            metadata: std::collections::BTreeMap::new(),
         }
     }
 
@@ -117,6 +300,18 @@ impl PolicyDecision {
         self
     }
 
+    /// Adds an obligation.
+    pub fn with_obligation(mut self, obligation: Obligation) -> Self {
+        self.obligations.push(obligation);
+        self
+    }
+
+    /// Adds advice.
+    pub fn with_advice(mut self, advice: Obligation) -> Self {
+        self.advice.push(advice);
+        self
+    }
+
     /// Adds metadata.
     pub fn with_metadata(mut self, key: impl Into<String>, value: serde_json::Value) -> Self {
         self.metadata.insert(key.into(), value);
@@ -132,6 +327,16 @@ impl PolicyDecision {
     pub fn is_denied(&self) -> bool {
         matches!(self.decision, Decision::Deny)
     }
+
+    /// Returns true if the decision is not applicable.
+    pub fn is_not_applicable(&self) -> bool {
+        matches!(self.decision, Decision::NotApplicable)
+    }
+
+    /// Returns true if the decision is indeterminate.
+    pub fn is_indeterminate(&self) -> bool {
+        self.decision.is_indeterminate()
+    }
 }
 
 #[cfg(test)]
@@ -162,4 +367,50 @@ mod tests {
         assert!(deny.is_denied());
         assert!(deny.is_default);
     }
+
+    #[test]
+    fn test_combine_deny_overrides() {
+        use Decision::*;
+        let alg = CombiningAlgorithm::DenyOverrides;
+
+        assert_eq!(Decision::combine(alg, &[Allow, Deny]), (Deny, Some(1)));
+        assert_eq!(Decision::combine(alg, &[NotApplicable, Allow]), (Allow, Some(1)));
+        assert_eq!(Decision::combine(alg, &[IndeterminateP, Allow]), (Allow, Some(1)));
+        assert_eq!(Decision::combine(alg, &[Allow, IndeterminateD]), (IndeterminateDP, Some(1)));
+        assert_eq!(Decision::combine(alg, &[IndeterminateD]), (IndeterminateD, Some(0)));
+        assert_eq!(Decision::combine(alg, &[NotApplicable, IndeterminateP]), (IndeterminateP, Some(1)));
+        assert_eq!(Decision::combine(alg, &[NotApplicable]), (NotApplicable, None));
+    }
+
+    #[test]
+    fn test_combine_first_applicable() {
+        use Decision::*;
+        let alg = CombiningAlgorithm::FirstApplicable;
+
+        assert_eq!(Decision::combine(alg, &[NotApplicable, IndeterminateD, Allow]), (IndeterminateD, Some(1)));
+        assert_eq!(Decision::combine(alg, &[]), (NotApplicable, None));
+    }
+
+    #[test]
+    fn test_decision_mapping() {
+        let mapping = DecisionMapping {
+            not_applicable: Effect::Allow,
+            indeterminate: Effect::Deny,
+        };
+
+        let na = mapping.apply(PolicyDecision::not_applicable());
+        assert!(na.is_allowed());
+        assert!(na.is_default);
+        assert_eq!(na.raw_decision, Some(Decision::NotApplicable));
+
+        let ind = mapping.apply(PolicyDecision::indeterminate(Decision::IndeterminateDP, "boom"));
+        assert!(ind.is_denied());
+        assert_eq!(ind.raw_decision, Some(Decision::IndeterminateDP));
+
+        let allow = mapping.apply(PolicyDecision::allow("ok"));
+        assert_eq!(allow.raw_decision, None);
+
+        let json = serde_json::to_string(&Decision::IndeterminateDP).unwrap();
+        assert_eq!(json, "\"indeterminate_dp\"");
+    }
 }
@@ -1,17 +1,68 @@
 //! Policy evaluation engine.
 
-use crate::context::EvaluationContext;
-use crate::decision::{Decision, PolicyDecision};
+use crate::clock::{format_now, Clock, SystemClock};
+use crate::context::{ContextAt, EvaluationContext};
+use crate::decision::{Decision, DecisionMapping, PolicyDecision};
 use crate::error::{PolicyError, Result};
-use crate::policy::Policy;
-use crate::types::{CombiningAlgorithm, Condition, ConditionOperator, Effect, Rule};
-use regex::Regex;
-use std::time::Instant;
+use crate::expression::{ExpressionCache, ExpressionLimits};
+use crate::glob::GlobCache;
+use crate::index::{PolicySetIndex, RuleIndex};
+use crate::network::NetworkRegistry;
+use crate::pattern::{RegexCache, RegexLimits};
+use crate::policy::{Policy, PolicySet};
+use crate::rbac::{RoleDefinition, RoleRegistry};
+use crate::registry::TypeRegistry;
+use crate::target::Target;
+use crate::temporal::{self, WindowCache};
+use crate::text;
+use crate::trace::{
+    CombiningTrace, ConditionTrace, EvaluationTrace, ExprTrace, PolicySetTrace, PolicyTrace, RuleTrace,
+};
+use crate::types::{CombiningAlgorithm, Condition, ConditionExpr, ConditionOperator, Effect, Obligation, Rule};
+use chrono::{DateTime, Utc};
+use std::borrow::Cow;
+use std::sync::Arc;
 
 /// The policy evaluator.
 #[derive(Debug)]
 pub struct PolicyEvaluator {
     policies: Vec<Policy>,
+    policy_sets: Vec<PolicySet>,
+    combining_algorithm: CombiningAlgorithm,
+    mapping: DecisionMapping,
+    /// Rule indexes, parallel to `policies` and `policy_sets`. Empty when
+    /// indexing is disabled.
+    policy_indexes: Vec<RuleIndex>,
+    policy_set_indexes: Vec<PolicySetIndex>,
+    indexed: bool,
+    /// Compiled `matches` patterns of the loaded policies.
+    regexes: RegexCache,
+    /// Compiled `glob` patterns of the loaded policies.
+    globs: GlobCache,
+    /// Compiled `day_of_week_in` and `time_of_day_between` windows of the
+    /// loaded policies.
+    windows: WindowCache,
+    /// Compiled `expr` and `value_expr` expressions of the loaded policies.
+    expressions: ExpressionCache,
+    /// Named network lists for `ip_in_cidr` conditions.
+    networks: NetworkRegistry,
+    /// Role hierarchy and permission sets for `role_at_least` and
+    /// `has_permission` conditions.
+    roles: RoleRegistry,
+    /// Resource and action types declared beyond the well-known ones.
+    types: TypeRegistry,
+    clock: Arc<dyn Clock>,
+    trust_request_time: bool,
+    deterministic: bool,
+    normalize_unicode: bool,
+    require_agreement: bool,
+}
+
+/// The outcome of evaluating a single applicable rule.
+struct RuleResult<'a> {
+    rule: &'a Rule,
+    decision: Decision,
+    error: Option<String>,
 }
 
 impl PolicyEvaluator {
@@ -19,168 +70,909 @@ impl PolicyEvaluator {
     pub fn new() -> Self {
         Self {
             policies: Vec::new(),
+            policy_sets: Vec::new(),
+            combining_algorithm: CombiningAlgorithm::DenyOverrides,
+            mapping: DecisionMapping::default(),
+            policy_indexes: Vec::new(),
+            policy_set_indexes: Vec::new(),
+            indexed: true,
+            regexes: RegexCache::default(),
+            globs: GlobCache::default(),
+            windows: WindowCache::default(),
+            expressions: ExpressionCache::default(),
+            networks: NetworkRegistry::new(),
+            roles: RoleRegistry::new(),
+            types: TypeRegistry::new(),
+            clock: Arc::new(SystemClock),
+            trust_request_time: false,
+            deterministic: false,
+            normalize_unicode: false,
+            require_agreement: false,
+        }
+    }
+
+    /// Sets the algorithm for combining the decisions of top-level policies
+    /// and policy sets (deny-overrides by default).
+    pub fn with_combining_algorithm(mut self, algorithm: CombiningAlgorithm) -> Self {
+        self.combining_algorithm = algorithm;
+        self
+    }
+
+    /// Sets how not-applicable and indeterminate results are mapped to
+    /// allow/deny by `evaluate`.
+    pub fn with_decision_mapping(mut self, mapping: DecisionMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    /// Sets the limits `matches` patterns are compiled with, recompiling the
+    /// patterns of already loaded policies.
+    ///
+    /// Fails if a loaded pattern does not compile within the new limits.
+    pub fn with_regex_limits(mut self, limits: RegexLimits) -> Result<Self> {
+        self.regexes = RegexCache::new(limits);
+        for policy in &self.policies {
+            self.regexes.add_policy(policy)?;
+        }
+        for policy_set in &self.policy_sets {
+            self.regexes.add_policy_set(policy_set)?;
+        }
+        Ok(self)
+    }
+
+    /// Sets the limits condition expressions are compiled and evaluated
+    /// with, recompiling the expressions of already loaded policies.
+    ///
+    /// Fails if a loaded expression does not compile within the new limits.
+    pub fn with_expression_limits(mut self, limits: ExpressionLimits) -> Result<Self> {
+        self.expressions = ExpressionCache::new(limits);
+        for policy in &self.policies {
+            self.expressions.add_policy(policy)?;
+        }
+        for policy_set in &self.policy_sets {
+            self.expressions.add_policy_set(policy_set)?;
         }
+        Ok(self)
+    }
+
+    /// Sets the clock used for `environment.now` and evaluation timing.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Evaluates requests that carry `environment.now` at that time instead
+    /// of the clock's. Only for callers that set the time themselves (e.g.
+    /// replaying recorded requests): a request can otherwise choose the time
+    /// that `valid_until`, `within_last` and agreement expiry are checked at.
+    pub fn trust_request_time(mut self) -> Self {
+        self.trust_request_time = true;
+        self
+    }
+
+    /// Omits `evaluation_time_us` from decisions, so identical inputs (and
+    /// clock readings) produce byte-identical decisions.
+    pub fn deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    /// NFC-normalizes the strings on both sides of every condition before
+    /// comparing them, so canonically equivalent strings compare equal.
+    /// `matches` patterns are used as written.
+    pub fn normalize_unicode(mut self) -> Self {
+        self.normalize_unicode = true;
+        self
+    }
+
+    /// Denies every request that is not made under an active agreement of
+    /// the request's tenant (and, if the resource names one, that
+    /// agreement), before evaluating any policy.
+    pub fn require_agreement(mut self) -> Self {
+        self.require_agreement = true;
+        self
+    }
+
+    /// Disables rule indexing, so `evaluate` visits every rule of every
+    /// applicable policy. Decisions are the same either way.
+    pub fn without_rule_index(mut self) -> Self {
+        self.indexed = false;
+        self.policy_indexes.clear();
+        self.policy_set_indexes.clear();
+        self
+    }
+
+    /// Defines a named network list for `ip_in_cidr` conditions.
+    pub fn define_network(&mut self, name: &str, cidrs: Vec<String>) -> Result<()> {
+        self.networks.define(name, &cidrs)
+    }
+
+    /// Declares a role for `role_at_least` and `has_permission` conditions.
+    pub fn define_role(&mut self, name: &str, definition: RoleDefinition) -> Result<()> {
+        self.roles.define_role(name, definition)
     }
 
-    /// Adds a policy to the evaluator.
+    /// Declares a permission set of action-name globs.
+    pub fn define_permission_set(&mut self, name: &str, patterns: Vec<String>) -> Result<()> {
+        self.roles.define_permission_set(name, patterns)
+    }
+
+    /// Declares a resource type that loaded policies may refer to.
+    pub fn declare_resource_type(&mut self, name: &str) -> Result<()> {
+        self.types.declare_resource_type(name)
+    }
+
+    /// Declares an action type that loaded policies may refer to.
+    pub fn declare_action_type(&mut self, name: &str) -> Result<()> {
+        self.types.declare_action_type(name)
+    }
+
+    /// Adds a policy to the evaluator, compiling its rule index, regexes,
+    /// globs, time windows and expressions and defining its network lists, roles, permission sets and
+    /// types.
+    ///
+    /// A pattern or expression exceeding the evaluator's limits is not
+    /// cached, and network lists or roles conflicting with existing ones are
+    /// not defined; the conditions using them are indeterminate. Use `load_policy_yaml` to
+    /// reject such policies instead.
     pub fn add_policy(&mut self, policy: Policy) {
+        let _ = self.regexes.add_policy(&policy);
+        let _ = self.globs.add_policy(&policy);
+        let _ = self.windows.add_policy(&policy);
+        let _ = self.expressions.add_policy(&policy);
+        let _ = self.networks.add_policy(&policy);
+        let _ = self.roles.add_policy(&policy);
+        let _ = self.types.add_policy(&policy);
+        if self.indexed {
+            self.policy_indexes.push(RuleIndex::build(&policy));
+        }
         self.policies.push(policy);
     }
 
     /// Loads a policy from YAML.
     pub fn load_policy_yaml(&mut self, yaml: &str) -> Result<()> {
         let policy = Policy::from_yaml(yaml)?;
+        self.networks.check_policy(&policy)?;
+        self.roles.check_policy(&policy)?;
+        self.types.check_policy(&policy)?;
+        self.expressions.add_policy(&policy)?;
+        self.regexes.add_policy(&policy)?;
+        self.globs.add_policy(&policy)?;
+        self.windows.add_policy(&policy)?;
+        self.networks.add_policy(&policy)?;
+        self.roles.add_policy(&policy)?;
+        self.types.add_policy(&policy)?;
         self.add_policy(policy);
         Ok(())
     }
 
+    /// Adds a policy set to the evaluator, compiling its rule indexes and
+    /// regexes, globs, time windows and expressions (see `add_policy`).
+    pub fn add_policy_set(&mut self, policy_set: PolicySet) {
+        let _ = self.regexes.add_policy_set(&policy_set);
+        let _ = self.globs.add_policy_set(&policy_set);
+        let _ = self.windows.add_policy_set(&policy_set);
+        let _ = self.expressions.add_policy_set(&policy_set);
+        let _ = self.networks.add_policy_set(&policy_set);
+        let _ = self.roles.add_policy_set(&policy_set);
+        let _ = self.types.add_policy_set(&policy_set);
+        if self.indexed {
+            self.policy_set_indexes.push(PolicySetIndex::build(&policy_set));
+        }
+        self.policy_sets.push(policy_set);
+    }
+
+    /// Loads a policy set from YAML.
+    pub fn load_policy_set_yaml(&mut self, yaml: &str) -> Result<()> {
+        let policy_set = PolicySet::from_yaml(yaml)?;
+        self.networks.check_policy_set(&policy_set)?;
+        self.roles.check_policy_set(&policy_set)?;
+        self.types.check_policy_set(&policy_set)?;
+        self.expressions.add_policy_set(&policy_set)?;
+        self.regexes.add_policy_set(&policy_set)?;
+        self.globs.add_policy_set(&policy_set)?;
+        self.windows.add_policy_set(&policy_set)?;
+        self.networks.add_policy_set(&policy_set)?;
+        self.roles.add_policy_set(&policy_set)?;
+        self.types.add_policy_set(&policy_set)?;
+        self.add_policy_set(policy_set);
+        Ok(())
+    }
+
+    /// Returns the number of loaded top-level policies.
+    pub fn policy_count(&self) -> usize {
+        self.policies.len()
+    }
+
+    /// Returns the number of loaded top-level policy sets.
+    pub fn policy_set_count(&self) -> usize {
+        self.policy_sets.len()
+    }
+
     /// Evaluates all policies against the context.
+    ///
+    /// Not-applicable and indeterminate outcomes are mapped to allow/deny by
+    /// the evaluator's `DecisionMapping`; use `evaluate_raw` to observe them.
     pub fn evaluate(&self, context: &EvaluationContext) -> Result<PolicyDecision> {
-        let start = Instant::now();
+        let start = self.start_timer();
+        let now = self.request_now(context);
+        let decision = self.mapping.apply(self.decide(&ContextAt::new(context, &now))?);
+        Ok(self.with_timing(decision, start))
+    }
+
+    /// Evaluates all policies against the context without mapping
+    /// not-applicable or indeterminate outcomes.
+    pub fn evaluate_raw(&self, context: &EvaluationContext) -> Result<PolicyDecision> {
+        let start = self.start_timer();
+        let now = self.request_now(context);
+        let decision = self.decide(&ContextAt::new(context, &now))?;
+        Ok(self.with_timing(decision, start))
+    }
+
+    /// Returns the time to evaluate the request at: the clock's, unless the
+    /// evaluator trusts the request's own `environment.now`.
+    fn request_now<'c>(&self, context: &'c EvaluationContext) -> Cow<'c, str> {
+        match &context.environment.now {
+            Some(now) if self.trust_request_time => Cow::Borrowed(now),
+            _ => Cow::Owned(format_now(self.clock.now())),
+        }
+    }
+
+    /// Reads the clock's monotonic time, unless in deterministic mode.
+    fn start_timer(&self) -> Option<u64> {
+        if self.deterministic {
+            return None;
+        }
+        self.clock.monotonic_micros()
+    }
+
+    /// Records the time elapsed since `start`, if it could be measured.
+    fn with_timing(&self, decision: PolicyDecision, start: Option<u64>) -> PolicyDecision {
+        match (start, self.clock.monotonic_micros()) {
+            (Some(start), Some(end)) => decision.with_evaluation_time(end.saturating_sub(start)),
+            _ => decision,
+        }
+    }
 
+    /// Produces the combined decision of all policies.
+    fn decide(&self, context: &ContextAt) -> Result<PolicyDecision> {
         // Validate context
         context.validate()?;
 
+        if let Some(decision) = self.agreement_decision(context) {
+            return Ok(decision);
+        }
+
         // If no policies, deny by default
-        if self.policies.is_empty() {
-            return Ok(PolicyDecision::default_deny()
-                .with_evaluation_time(start.elapsed().as_micros() as u64));
+        if self.policies.is_empty() && self.policy_sets.is_empty() {
+            return Ok(PolicyDecision::default_deny());
         }
 
-        // Evaluate each policy
+        // Evaluate each policy, then each policy set
         let mut decisions: Vec<PolicyDecision> = Vec::new();
 
-        for policy in &self.policies {
-            let decision = self.evaluate_policy(policy, context)?;
+        for (i, policy) in self.policies.iter().enumerate() {
+            let decision = self.evaluate_policy(policy, self.policy_indexes.get(i), context)?;
             decisions.push(decision);
         }
+        for (i, policy_set) in self.policy_sets.iter().enumerate() {
+            decisions.push(self.evaluate_policy_set(policy_set, self.policy_set_indexes.get(i), context)?);
+        }
+
+        let (decision, _) = self.combine_decisions(&decisions, self.combining_algorithm);
+        Ok(decision)
+    }
+
+    /// Evaluates all policies and records a full trace of the evaluation.
+    ///
+    /// Unlike `evaluate`, every condition is visited (no short-circuiting) so
+    /// the trace shows each compared value. The decision is identical to the
+    /// one `evaluate` returns; the policy and combining traces show the
+    /// unmapped outcomes.
+    pub fn evaluate_with_trace(&self, context: &EvaluationContext) -> Result<EvaluationTrace> {
+        let start = self.start_timer();
+        let now = self.request_now(context);
+        let context = &ContextAt::new(context, &now);
+
+        context.validate()?;
+
+        if let Some(decision) = self.agreement_decision(context) {
+            let combining = CombiningTrace {
+                algorithm: self.combining_algorithm,
+                candidates: Vec::new(),
+                selected: None,
+                decision: decision.decision,
+                reason: decision.reason.clone(),
+            };
+            return Ok(EvaluationTrace {
+                decision: self.with_timing(decision, start),
+                policies: Vec::new(),
+                policy_sets: Vec::new(),
+                combining,
+            });
+        }
+
+        let mut policy_traces = Vec::new();
+        for policy in &self.policies {
+            policy_traces.push(self.trace_policy(policy, context)?);
+        }
+        let mut policy_set_traces = Vec::new();
+        for policy_set in &self.policy_sets {
+            policy_set_traces.push(self.trace_policy_set(policy_set, context)?);
+        }
+
+        let (decision, combining) = if policy_traces.is_empty() && policy_set_traces.is_empty() {
+            let decision = PolicyDecision::default_deny();
+            let combining = CombiningTrace {
+                algorithm: self.combining_algorithm,
+                candidates: Vec::new(),
+                selected: None,
+                decision: decision.decision,
+                reason: decision.reason.clone(),
+            };
+            (decision, combining)
+        } else {
+            self.trace_combine(&policy_traces, &policy_set_traces, self.combining_algorithm)
+        };
+
+        Ok(EvaluationTrace {
+            decision: self.with_timing(self.mapping.apply(decision), start),
+            policies: policy_traces,
+            policy_sets: policy_set_traces,
+            combining,
+        })
+    }
+
+    /// Returns a deny decision if agreements are required and none
+    /// authorizes the request.
+    fn agreement_decision(&self, context: &ContextAt) -> Option<PolicyDecision> {
+        if !self.require_agreement {
+            return None;
+        }
+        let now = context
+            .now()
+            .and_then(|now| DateTime::parse_from_rfc3339(now).ok())
+            .map_or_else(|| self.clock.now(), |now| now.with_timezone(&Utc));
+        let reason = match &context.agreement {
+            Some(agreement) => agreement.authorizes(context, now).err()?,
+            None => "No agreement authorizes the request".to_string(),
+        };
+        Some(PolicyDecision::deny(reason))
+    }
+
+    /// Evaluates a policy set: its target, then its members.
+    fn evaluate_policy_set(
+        &self,
+        policy_set: &PolicySet,
+        index: Option<&PolicySetIndex>,
+        context: &ContextAt,
+    ) -> Result<PolicyDecision> {
+        if let Some(decision) = self.policy_set_target_decision(policy_set, context) {
+            return Ok(decision);
+        }
+
+        let mut decisions: Vec<PolicyDecision> = Vec::new();
+        for (i, policy) in policy_set.policies.iter().enumerate() {
+            let policy_index = index.and_then(|index| index.policies.get(i));
+            decisions.push(self.evaluate_policy(policy, policy_index, context)?);
+        }
+        for (i, nested) in policy_set.policy_sets.iter().enumerate() {
+            let nested_index = index.and_then(|index| index.policy_sets.get(i));
+            decisions.push(self.evaluate_policy_set(nested, nested_index, context)?);
+        }
+
+        let (decision, _) = self.combine_decisions(&decisions, policy_set.combining_algorithm);
+        Ok(self.with_policy_set_obligations(policy_set, decision))
+    }
+
+    /// Evaluates a policy set, recording a trace of every member.
+    fn trace_policy_set(&self, policy_set: &PolicySet, context: &ContextAt) -> Result<PolicySetTrace> {
+        if let Some(decision) = self.policy_set_target_decision(policy_set, context) {
+            return Ok(PolicySetTrace {
+                policy_set_id: policy_set.id.clone(),
+                target_matched: false,
+                policies: Vec::new(),
+                policy_sets: Vec::new(),
+                combining: None,
+                decision,
+            });
+        }
+
+        let mut policy_traces = Vec::new();
+        for policy in &policy_set.policies {
+            policy_traces.push(self.trace_policy(policy, context)?);
+        }
+        let mut policy_set_traces = Vec::new();
+        for nested in &policy_set.policy_sets {
+            policy_set_traces.push(self.trace_policy_set(nested, context)?);
+        }
+
+        let (decision, combining) =
+            self.trace_combine(&policy_traces, &policy_set_traces, policy_set.combining_algorithm);
+
+        Ok(PolicySetTrace {
+            policy_set_id: policy_set.id.clone(),
+            target_matched: true,
+            policies: policy_traces,
+            policy_sets: policy_set_traces,
+            combining: Some(combining),
+            decision: self.with_policy_set_obligations(policy_set, decision),
+        })
+    }
+
+    /// Combines traced members, recording the combining step.
+    fn trace_combine(
+        &self,
+        policy_traces: &[PolicyTrace],
+        policy_set_traces: &[PolicySetTrace],
+        algorithm: CombiningAlgorithm,
+    ) -> (PolicyDecision, CombiningTrace) {
+        let members: Vec<(&str, &PolicyDecision)> = policy_traces
+            .iter()
+            .map(|t| (t.policy_id.as_str(), &t.decision))
+            .chain(policy_set_traces.iter().map(|t| (t.policy_set_id.as_str(), &t.decision)))
+            .collect();
+        let decisions: Vec<PolicyDecision> = members.iter().map(|(_, d)| (*d).clone()).collect();
+        let (decision, selected) = self.combine_decisions(&decisions, algorithm);
+
+        let combining = CombiningTrace {
+            algorithm,
+            candidates: members
+                .iter()
+                .filter(|(_, d)| !d.is_not_applicable())
+                .map(|(id, _)| id.to_string())
+                .collect(),
+            selected: selected.map(|i| members[i].0.to_string()),
+            decision: decision.decision,
+            reason: decision.reason.clone(),
+        };
+        (decision, combining)
+    }
+
+    /// Returns the decision for a policy set whose target does not match
+    /// (not applicable) or cannot be evaluated (indeterminate), or `None` if
+    /// its members should be evaluated.
+    fn policy_set_target_decision(&self, policy_set: &PolicySet, context: &ContextAt) -> Option<PolicyDecision> {
+        let owner = format!("policy set '{}'", policy_set.id);
+        self.target_decision(policy_set.target.as_ref(), &owner, context)
+            .map(|d| d.with_metadata("policy_set_id", serde_json::json!(policy_set.id)))
+    }
+
+    /// Returns the decision for a policy whose target does not match or
+    /// cannot be evaluated, or `None` if its rules should be evaluated.
+    fn policy_target_decision(&self, policy: &Policy, context: &ContextAt) -> Option<PolicyDecision> {
+        let owner = format!("policy '{}'", policy.id);
+        self.target_decision(policy.target.as_ref(), &owner, context)
+            .map(|d| d.with_policy_id(&policy.id))
+    }
 
-        // Combine decisions (use deny-overrides by default across policies)
-        let final_decision = self.combine_decisions(&decisions, CombiningAlgorithm::DenyOverrides);
+    /// Checks an optional target, returning the not-applicable or
+    /// indeterminate decision that short-circuits its owner, if any.
+    fn target_decision(&self, target: Option<&Target>, owner: &str, context: &ContextAt) -> Option<PolicyDecision> {
+        match self.evaluate_target(target?, context) {
+            Ok(true) => None,
+            Ok(false) => {
+                let mut decision = PolicyDecision::not_applicable();
+                decision.reason = format!("Target of {} does not match", owner);
+                Some(decision)
+            }
+            Err(e) => Some(PolicyDecision::indeterminate(
+                Decision::IndeterminateDP,
+                format!("Target of {} could not be evaluated: {}", owner, e),
+            )),
+        }
+    }
+
+    /// Appends the policy set's own obligations and advice that match the decision.
+    fn with_policy_set_obligations(&self, policy_set: &PolicySet, mut decision: PolicyDecision) -> PolicyDecision {
+        let fulfilled = |o: &&Obligation| Decision::from(o.fulfill_on) == decision.decision;
+        let obligations: Vec<Obligation> = policy_set.obligations.iter().filter(fulfilled).cloned().collect();
+        let advice: Vec<Obligation> = policy_set.advice.iter().filter(fulfilled).cloned().collect();
+        decision.obligations.extend(obligations);
+        decision.advice.extend(advice);
+        decision
+    }
 
-        Ok(final_decision.with_evaluation_time(start.elapsed().as_micros() as u64))
+    /// Evaluates a target: its attribute lists, then its conditions.
+    fn evaluate_target(&self, target: &Target, context: &ContextAt) -> Result<bool> {
+        if !target.matches_attributes(context) {
+            return Ok(false);
+        }
+        self.evaluate_all_of(&target.conditions, context)
     }
 
     /// Evaluates a single policy.
-    fn evaluate_policy(&self, policy: &Policy, context: &EvaluationContext) -> Result<PolicyDecision> {
-        let sorted_rules = policy.sorted_rules();
-        let mut matched_decisions: Vec<(Effect, &Rule)> = Vec::new();
+    ///
+    /// With an index only the candidate rules are visited; the others are
+    /// certainly not applicable, so the decision is unchanged.
+    fn evaluate_policy(
+        &self,
+        policy: &Policy,
+        index: Option<&RuleIndex>,
+        context: &ContextAt,
+    ) -> Result<PolicyDecision> {
+        if let Some(decision) = self.policy_target_decision(policy, context) {
+            return Ok(decision);
+        }
 
-        for rule in sorted_rules {
-            if self.evaluate_rule(rule, context)? {
-                matched_decisions.push((rule.effect, rule));
+        let rules = match index {
+            Some(index) => index.candidates(policy, context, self.normalize_unicode),
+            None => policy.sorted_rules(),
+        };
+        let mut results: Vec<RuleResult> = Vec::new();
+
+        for rule in rules {
+            match self.evaluate_rule(rule, context) {
+                Ok(true) => results.push(RuleResult {
+                    rule,
+                    decision: rule.effect.into(),
+                    error: None,
+                }),
+                Ok(false) => {}
+                Err(e) => results.push(RuleResult {
+                    rule,
+                    decision: Decision::indeterminate_for(rule.effect),
+                    error: Some(e.to_string()),
+                }),
             }
         }
 
-        if matched_decisions.is_empty() {
-            // No rules matched, use default effect
-            let decision = if policy.default_effect == Effect::Allow {
-                PolicyDecision::default_allow()
-            } else {
-                PolicyDecision::default_deny()
-            };
-            return Ok(decision.with_policy_id(&policy.id));
+        Ok(self.combine_rules(policy, &results))
+    }
+
+    /// Evaluates a single policy, recording a trace of every rule.
+    fn trace_policy(&self, policy: &Policy, context: &ContextAt) -> Result<PolicyTrace> {
+        if let Some(decision) = self.policy_target_decision(policy, context) {
+            return Ok(PolicyTrace {
+                policy_id: policy.id.clone(),
+                target_matched: false,
+                rules: Vec::new(),
+                combining: None,
+                decision,
+            });
         }
 
-        // Apply combining algorithm
-        let decision = match policy.combining_algorithm {
-            CombiningAlgorithm::FirstApplicable => {
-                let (effect, rule) = &matched_decisions[0];
-                let dec = if *effect == Effect::Allow {
-                    PolicyDecision::allow(format!("Rule '{}' matched", rule.id))
-                } else {
-                    PolicyDecision::deny(format!("Rule '{}' matched", rule.id))
-                };
-                dec.with_rule_id(&rule.id)
-            }
-
-            CombiningAlgorithm::DenyOverrides => {
-                // If any rule denies, deny
-                for (effect, rule) in &matched_decisions {
-                    if *effect == Effect::Deny {
-                        return Ok(PolicyDecision::deny(format!("Rule '{}' denies", rule.id))
-                            .with_rule_id(&rule.id)
-                            .with_policy_id(&policy.id));
-                    }
-                }
-                // All rules allow
-                let (_, rule) = &matched_decisions[0];
-                PolicyDecision::allow("All matching rules allow")
-                    .with_rule_id(&rule.id)
-            }
-
-            CombiningAlgorithm::AllowOverrides => {
-                // If any rule allows, allow
-                for (effect, rule) in &matched_decisions {
-                    if *effect == Effect::Allow {
-                        return Ok(PolicyDecision::allow(format!("Rule '{}' allows", rule.id))
-                            .with_rule_id(&rule.id)
-                            .with_policy_id(&policy.id));
-                    }
-                }
-                // All rules deny
-                let (_, rule) = &matched_decisions[0];
-                PolicyDecision::deny("All matching rules deny")
-                    .with_rule_id(&rule.id)
-            }
-
-            CombiningAlgorithm::UnanimousAllow => {
-                // All rules must allow
-                for (effect, rule) in &matched_decisions {
-                    if *effect == Effect::Deny {
-                        return Ok(PolicyDecision::deny(format!("Rule '{}' denies (unanimous allow required)", rule.id))
-                            .with_rule_id(&rule.id)
-                            .with_policy_id(&policy.id));
-                    }
-                }
-                let (_, rule) = &matched_decisions[0];
-                PolicyDecision::allow("All rules unanimously allow")
-                    .with_rule_id(&rule.id)
-            }
-
-            CombiningAlgorithm::UnanimousDeny => {
-                // All rules must deny
-                for (effect, rule) in &matched_decisions {
-                    if *effect == Effect::Allow {
-                        return Ok(PolicyDecision::allow(format!("Rule '{}' allows (unanimous deny required)", rule.id))
-                            .with_rule_id(&rule.id)
-                            .with_policy_id(&policy.id));
-                    }
-                }
-                let (_, rule) = &matched_decisions[0];
-                PolicyDecision::deny("All rules unanimously deny")
-                    .with_rule_id(&rule.id)
+        let mut rule_traces = Vec::new();
+        let mut results: Vec<RuleResult> = Vec::new();
+
+        for rule in policy.sorted_rules() {
+            let trace = self.trace_rule(rule, context);
+            if trace.decision != Decision::NotApplicable {
+                results.push(RuleResult {
+                    rule,
+                    decision: trace.decision,
+                    error: trace.error.clone(),
+                });
+            }
+            rule_traces.push(trace);
+        }
+
+        let decision = self.combine_rules(policy, &results);
+        let combining = CombiningTrace {
+            algorithm: policy.combining_algorithm,
+            candidates: results.iter().map(|r| r.rule.id.clone()).collect(),
+            selected: decision.rule_id.clone(),
+            decision: decision.decision,
+            reason: decision.reason.clone(),
+        };
+
+        Ok(PolicyTrace {
+            policy_id: policy.id.clone(),
+            target_matched: true,
+            rules: rule_traces,
+            combining: Some(combining),
+            decision,
+        })
+    }
+
+    /// Applies the policy's combining algorithm to its applicable rules and
+    /// attaches the obligations and advice of everything that contributed.
+    fn combine_rules(&self, policy: &Policy, results: &[RuleResult]) -> PolicyDecision {
+        let mut decision = self.select_rule_decision(policy, results);
+
+        // Rules contribute when their effect agrees with the decision; under
+        // first-applicable only the first applicable rule was considered.
+        let contributing: Vec<&Rule> = match policy.combining_algorithm {
+            _ if decision.is_default || decision.decision.effect().is_none() => Vec::new(),
+            CombiningAlgorithm::FirstApplicable => vec![results[0].rule],
+            _ => results
+                .iter()
+                .filter(|r| r.decision == decision.decision)
+                .map(|r| r.rule)
+                .collect(),
+        };
+
+        let fulfilled = |o: &&Obligation| Decision::from(o.fulfill_on) == decision.decision;
+        let obligations: Vec<Obligation> = contributing
+            .iter()
+            .flat_map(|r| &r.obligations)
+            .chain(&policy.obligations)
+            .filter(fulfilled)
+            .cloned()
+            .collect();
+        let advice: Vec<Obligation> = contributing
+            .iter()
+            .flat_map(|r| &r.advice)
+            .chain(&policy.advice)
+            .filter(fulfilled)
+            .cloned()
+            .collect();
+
+        decision.obligations = obligations;
+        decision.advice = advice;
+        decision
+    }
+
+    /// Selects the policy decision from its applicable rules.
+    fn select_rule_decision(&self, policy: &Policy, results: &[RuleResult]) -> PolicyDecision {
+        let decisions: Vec<Decision> = results.iter().map(|r| r.decision).collect();
+        let (combined, selected) = Decision::combine(policy.combining_algorithm, &decisions);
+
+        let Some(index) = selected else {
+            // No rules applied, use default effect
+            let decision = match policy.default_effect {
+                Some(Effect::Allow) => PolicyDecision::default_allow(),
+                Some(Effect::Deny) => PolicyDecision::default_deny(),
+                None => PolicyDecision::not_applicable(),
+            };
+            return decision.with_policy_id(&policy.id);
+        };
+        let result = &results[index];
+        let rule = result.rule;
+
+        let decision = match (policy.combining_algorithm, combined) {
+            (_, d) if d.is_indeterminate() => PolicyDecision::indeterminate(
+                d,
+                format!(
+                    "Rule '{}' could not be evaluated: {}",
+                    rule.id,
+                    result.error.as_deref().unwrap_or("unknown error")
+                ),
+            ),
+
+            (CombiningAlgorithm::FirstApplicable, Decision::Allow) => {
+                PolicyDecision::allow(format!("Rule '{}' matched", rule.id))
+            }
+            (CombiningAlgorithm::FirstApplicable, _) => {
+                PolicyDecision::deny(format!("Rule '{}' matched", rule.id))
+            }
+
+            (CombiningAlgorithm::DenyOverrides, Decision::Deny) => {
+                PolicyDecision::deny(format!("Rule '{}' denies", rule.id))
+            }
+            (CombiningAlgorithm::DenyOverrides, _) => PolicyDecision::allow("All matching rules allow"),
+
+            (CombiningAlgorithm::AllowOverrides, Decision::Allow) => {
+                PolicyDecision::allow(format!("Rule '{}' allows", rule.id))
+            }
+            (CombiningAlgorithm::AllowOverrides, _) => PolicyDecision::deny("All matching rules deny"),
+
+            (CombiningAlgorithm::UnanimousAllow, Decision::Deny) => {
+                PolicyDecision::deny(format!("Rule '{}' denies (unanimous allow required)", rule.id))
             }
+            (CombiningAlgorithm::UnanimousAllow, _) => PolicyDecision::allow("All rules unanimously allow"),
+
+            (CombiningAlgorithm::UnanimousDeny, Decision::Allow) => {
+                PolicyDecision::allow(format!("Rule '{}' allows (unanimous deny required)", rule.id))
+            }
+            (CombiningAlgorithm::UnanimousDeny, _) => PolicyDecision::deny("All rules unanimously deny"),
         };
 
-        Ok(decision.with_policy_id(&policy.id))
+        decision.with_rule_id(&rule.id).with_policy_id(&policy.id)
     }
 
     /// Evaluates a single rule against the context.
-    fn evaluate_rule(&self, rule: &Rule, context: &EvaluationContext) -> Result<bool> {
+    ///
+    /// Returns an error if the rule could not be evaluated, which makes the
+    /// rule indeterminate rather than aborting the evaluation.
+    fn evaluate_rule(&self, rule: &Rule, context: &ContextAt) -> Result<bool> {
+        // Validity and target are checked first so non-applicable rules skip their conditions
+        if !self.rule_applies(rule, context)? {
+            return Ok(false);
+        }
+
         // All conditions must match
-        for condition in &rule.conditions {
-            if !self.evaluate_condition(condition, context)? {
+        self.evaluate_all_of(&rule.conditions, context)
+    }
+
+    /// Checks a rule's validity period and target against the request.
+    fn rule_applies(&self, rule: &Rule, context: &ContextAt) -> Result<bool> {
+        if rule.valid_from.is_some() || rule.valid_until.is_some() {
+            let now = temporal::request_time(context)?;
+            if rule.valid_from.is_some_and(|from| now < from) || rule.valid_until.is_some_and(|until| now >= until) {
                 return Ok(false);
             }
         }
-        Ok(true)
+
+        match &rule.target {
+            Some(target) => self.evaluate_target(target, context),
+            None => Ok(true),
+        }
+    }
+
+    /// Evaluates a condition expression tree, short-circuiting where possible.
+    ///
+    /// Errors follow XACML's three-valued logic: a false branch decides an
+    /// `all_of` and a true branch decides an `any_of` even if another branch
+    /// failed to evaluate.
+    fn evaluate_expr(&self, expr: &ConditionExpr, context: &ContextAt) -> Result<bool> {
+        match expr {
+            ConditionExpr::AllOf { all_of } => self.evaluate_all_of(all_of, context),
+            ConditionExpr::AnyOf { any_of } => {
+                let mut first_error = None;
+                for sub in any_of {
+                    match self.evaluate_expr(sub, context) {
+                        Ok(true) => return Ok(true),
+                        Ok(false) => {}
+                        Err(e) => {
+                            first_error.get_or_insert(e);
+                        }
+                    }
+                }
+                first_error.map_or(Ok(false), Err)
+            }
+            ConditionExpr::Not { not } => Ok(!self.evaluate_expr(not, context)?),
+            ConditionExpr::Condition(condition) => self.evaluate_condition(condition, context),
+        }
+    }
+
+    /// Evaluates a conjunction of condition expressions.
+    fn evaluate_all_of(&self, exprs: &[ConditionExpr], context: &ContextAt) -> Result<bool> {
+        let mut first_error = None;
+        for sub in exprs {
+            match self.evaluate_expr(sub, context) {
+                Ok(true) => {}
+                Ok(false) => return Ok(false),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        first_error.map_or(Ok(true), Err)
     }
 
     /// Evaluates a single condition.
-    fn evaluate_condition(&self, condition: &Condition, context: &EvaluationContext) -> Result<bool> {
-        let field_value = context.get_value(&condition.field);
+    fn evaluate_condition(&self, condition: &Condition, context: &ContextAt) -> Result<bool> {
+        let left = self.resolve_left(condition, context)?;
 
         match condition.operator {
-            ConditionOperator::Exists => Ok(field_value.is_some()),
-            ConditionOperator::NotExists => Ok(field_value.is_none()),
+            ConditionOperator::Exists => Ok(left.is_some()),
+            ConditionOperator::NotExists => Ok(left.is_none()),
             _ => {
-                let field_value = field_value.ok_or_else(|| {
-                    PolicyError::ConditionError(format!("Field '{}' not found", condition.field))
+                let left = left.ok_or_else(|| {
+                    PolicyError::ConditionError(format!("Field '{}' not found", condition.subject()))
+                })?;
+                let right = self.resolve_right(condition, context)?.ok_or_else(|| {
+                    let source = condition.value_field.as_deref().or(condition.value_expr.as_deref());
+                    PolicyError::ConditionError(format!("Field '{}' not found", source.unwrap_or_default()))
                 })?;
+                if self.normalize_unicode {
+                    let left = text::nfc_value(&left);
+                    let right = match condition.operator {
+                        ConditionOperator::Matches => right,
+                        _ => Cow::Owned(text::nfc_value(&right).into_owned()),
+                    };
+                    return self.evaluate_operator(&condition.operator, &left, &right, context);
+                }
+                self.evaluate_operator(&condition.operator, &left, &right, context)
+            }
+        }
+    }
+
+    /// Resolves the left-hand side of a condition, or `None` if it is missing.
+    fn resolve_left(&self, condition: &Condition, context: &ContextAt) -> Result<Option<serde_json::Value>> {
+        match &condition.expr {
+            Some(expr) => self.expressions.get(expr)?.evaluate_at(context),
+            None => Ok(context.get_value(&condition.field)),
+        }
+    }
+
+    /// Resolves the right-hand side of a condition, or `None` if it is missing.
+    fn resolve_right<'c>(
+        &self,
+        condition: &'c Condition,
+        context: &ContextAt,
+    ) -> Result<Option<Cow<'c, serde_json::Value>>> {
+        match (&condition.value_field, &condition.value_expr) {
+            (Some(value_field), _) => Ok(context.get_value(value_field).map(Cow::Owned)),
+            (None, Some(value_expr)) => Ok(self.expressions.get(value_expr)?.evaluate_at(context)?.map(Cow::Owned)),
+            (None, None) => Ok(Some(Cow::Borrowed(&condition.value))),
+        }
+    }
+
+    /// Evaluates a rule, recording a trace of every condition.
+    fn trace_rule(&self, rule: &Rule, context: &ContextAt) -> RuleTrace {
+        let applies = self.rule_applies(rule, context);
+        if let Ok(false) | Err(_) = &applies {
+            let error = applies.err().map(|e| e.to_string());
+            return RuleTrace {
+                rule_id: rule.id.clone(),
+                effect: rule.effect,
+                priority: rule.priority,
+                target_matched: false,
+                matched: false,
+                decision: match error {
+                    Some(_) => Decision::indeterminate_for(rule.effect),
+                    None => Decision::NotApplicable,
+                },
+                error,
+                conditions: Vec::new(),
+            };
+        }
+
+        let conditions: Vec<ExprTrace> = rule
+            .conditions
+            .iter()
+            .map(|expr| self.trace_expr(expr, context))
+            .collect();
+        let (matched, error) = fold_all_of(&conditions);
+        let decision = match (matched, &error) {
+            (_, Some(_)) => Decision::indeterminate_for(rule.effect),
+            (true, None) => rule.effect.into(),
+            (false, None) => Decision::NotApplicable,
+        };
 
-                self.evaluate_operator(&condition.operator, &field_value, &condition.value)
+        RuleTrace {
+            rule_id: rule.id.clone(),
+            effect: rule.effect,
+            priority: rule.priority,
+            target_matched: true,
+            matched,
+            decision,
+            error,
+            conditions,
+        }
+    }
+
+    /// Evaluates a condition expression without short-circuiting.
+    ///
+    /// Outcomes are folded with the same three-valued logic as
+    /// `evaluate_expr`, so the result never depends on visiting order.
+    fn trace_expr(&self, expr: &ConditionExpr, context: &ContextAt) -> ExprTrace {
+        match expr {
+            ConditionExpr::AllOf { all_of } => {
+                let children: Vec<ExprTrace> =
+                    all_of.iter().map(|sub| self.trace_expr(sub, context)).collect();
+                let (matched, error) = fold_all_of(&children);
+                ExprTrace::AllOf { matched, error, children }
+            }
+            ConditionExpr::AnyOf { any_of } => {
+                let children: Vec<ExprTrace> =
+                    any_of.iter().map(|sub| self.trace_expr(sub, context)).collect();
+                let (matched, error) = fold_any_of(&children);
+                ExprTrace::AnyOf { matched, error, children }
             }
+            ConditionExpr::Not { not } => {
+                let child = self.trace_expr(not, context);
+                let error = child.error().map(str::to_string);
+                let matched = error.is_none() && !child.matched();
+                ExprTrace::Not { matched, error, child: Box::new(child) }
+            }
+            ConditionExpr::Condition(condition) => {
+                ExprTrace::Condition(self.trace_condition(condition, context))
+            }
+        }
+    }
+
+    /// Evaluates a single condition, recording the resolved operands.
+    fn trace_condition(&self, condition: &Condition, context: &ContextAt) -> ConditionTrace {
+        let left = self.resolve_left(condition, context).ok().flatten();
+        let right = match condition.operator {
+            ConditionOperator::Exists | ConditionOperator::NotExists => None,
+            _ => self.resolve_right(condition, context).ok().flatten().map(Cow::into_owned),
+        };
+        let result = self.evaluate_condition(condition, context);
+
+        ConditionTrace {
+            field: condition.field.clone(),
+            expr: condition.expr.clone(),
+            operator: condition.operator,
+            value_field: condition.value_field.clone(),
+            value_expr: condition.value_expr.clone(),
+            left,
+            right,
+            matched: *result.as_ref().unwrap_or(&false),
+            error: result.err().map(|e| e.to_string()),
         }
     }
 
@@ -190,6 +982,7 @@ impl PolicyEvaluator {
         operator: &ConditionOperator,
         left: &serde_json::Value,
         right: &serde_json::Value,
+        context: &ContextAt,
     ) -> Result<bool> {
         match operator {
             ConditionOperator::Equals => Ok(left == right),
@@ -206,7 +999,7 @@ impl PolicyEvaluator {
             }
 
             ConditionOperator::NotContains => {
-                let contains = self.evaluate_operator(&ConditionOperator::Contains, left, right)?;
+                let contains = self.evaluate_operator(&ConditionOperator::Contains, left, right, context)?;
                 Ok(!contains)
             }
 
@@ -228,13 +1021,21 @@ impl PolicyEvaluator {
 
             ConditionOperator::Matches => {
                 if let (Some(left_str), Some(pattern)) = (left.as_str(), right.as_str()) {
-                    let regex = Regex::new(pattern)?;
+                    let regex = self.regexes.get(pattern)?;
                     Ok(regex.is_match(left_str))
                 } else {
                     Ok(false)
                 }
             }
 
+            ConditionOperator::Glob => {
+                if let (Some(left_str), Some(pattern)) = (left.as_str(), right.as_str()) {
+                    Ok(self.globs.get(pattern)?.matches(left_str))
+                } else {
+                    Ok(false)
+                }
+            }
+
             ConditionOperator::In => {
                 if let Some(right_arr) = right.as_array() {
                     Ok(right_arr.contains(left))
@@ -244,7 +1045,7 @@ impl PolicyEvaluator {
             }
 
             ConditionOperator::NotIn => {
-                let is_in = self.evaluate_operator(&ConditionOperator::In, left, right)?;
+                let is_in = self.evaluate_operator(&ConditionOperator::In, left, right, context)?;
                 Ok(!is_in)
             }
 
@@ -264,6 +1065,46 @@ impl PolicyEvaluator {
                 self.compare_numbers(left, right, |l, r| l <= r)
             }
 
+            ConditionOperator::Before
+            | ConditionOperator::After
+            | ConditionOperator::WithinLast
+            | ConditionOperator::OlderThan
+            | ConditionOperator::DayOfWeekIn
+            | ConditionOperator::TimeOfDayBetween => temporal::evaluate(*operator, left, right, &self.windows, context),
+
+            ConditionOperator::IpInCidr => self.networks.contains(left, right),
+            ConditionOperator::IpNotInCidr => Ok(!self.networks.contains(left, right)?),
+
+            ConditionOperator::RoleAtLeast | ConditionOperator::HasPermission => {
+                self.roles.evaluate(*operator, left, right)
+            }
+
+            ConditionOperator::Intersects => self.compare_sets(left, right, |l, r| l.iter().any(|v| r.contains(v))),
+            ConditionOperator::Disjoint => self.compare_sets(left, right, |l, r| !l.iter().any(|v| r.contains(v))),
+            ConditionOperator::SubsetOf => self.compare_sets(left, right, |l, r| l.iter().all(|v| r.contains(v))),
+            ConditionOperator::SupersetOf => self.compare_sets(left, right, |l, r| r.iter().all(|v| l.contains(v))),
+
+            ConditionOperator::CountEquals => self.compare_count(left, right, |l, r| l == r),
+            ConditionOperator::CountAtLeast => self.compare_count(left, right, |l, r| l >= r),
+            ConditionOperator::CountAtMost => self.compare_count(left, right, |l, r| l <= r),
+
+            ConditionOperator::EqualsIgnoreCase => Ok(text::equals_ignore_case(left, right)),
+            ConditionOperator::ContainsIgnoreCase => match (left, right.as_str()) {
+                (serde_json::Value::String(left_str), Some(right_str)) => {
+                    Ok(text::fold(left_str).contains(&text::fold(right_str)))
+                }
+                (serde_json::Value::Array(left_arr), _) => {
+                    Ok(left_arr.iter().any(|item| text::equals_ignore_case(item, right)))
+                }
+                _ => Ok(false),
+            },
+            ConditionOperator::StartsWithIgnoreCase => Ok(self.compare_folded(left, right, |l, r| l.starts_with(r))),
+            ConditionOperator::EndsWithIgnoreCase => Ok(self.compare_folded(left, right, |l, r| l.ends_with(r))),
+            ConditionOperator::InIgnoreCase => match right.as_array() {
+                Some(right_arr) => Ok(right_arr.iter().any(|item| text::equals_ignore_case(left, item))),
+                None => Ok(false),
+            },
+
             ConditionOperator::Exists | ConditionOperator::NotExists => {
                 // These are handled earlier
                 Ok(false)
@@ -271,6 +1112,36 @@ impl PolicyEvaluator {
         }
     }
 
+    /// Compares two arrays as sets.
+    fn compare_sets<F>(&self, left: &serde_json::Value, right: &serde_json::Value, cmp: F) -> Result<bool>
+    where
+        F: Fn(&[serde_json::Value], &[serde_json::Value]) -> bool,
+    {
+        let left_arr = left.as_array().ok_or_else(|| {
+            PolicyError::ConditionError("Left value is not an array".to_string())
+        })?;
+        let right_arr = right.as_array().ok_or_else(|| {
+            PolicyError::ConditionError("Right value is not an array".to_string())
+        })?;
+
+        Ok(cmp(left_arr, right_arr))
+    }
+
+    /// Compares the length of an array with a count.
+    fn compare_count<F>(&self, left: &serde_json::Value, right: &serde_json::Value, cmp: F) -> Result<bool>
+    where
+        F: Fn(u64, u64) -> bool,
+    {
+        let len = left.as_array().ok_or_else(|| {
+            PolicyError::ConditionError("Left value is not an array".to_string())
+        })?.len() as u64;
+        let count = right.as_u64().ok_or_else(|| {
+            PolicyError::ConditionError("Count is not a non-negative integer".to_string())
+        })?;
+
+        Ok(cmp(len, count))
+    }
+
     /// Compares two numeric values.
     fn compare_numbers<F>(&self, left: &serde_json::Value, right: &serde_json::Value, cmp: F) -> Result<bool>
     where
@@ -286,64 +1157,64 @@ impl PolicyEvaluator {
         Ok(cmp(left_num, right_num))
     }
 
-    /// Combines multiple policy decisions.
-    fn combine_decisions(&self, decisions: &[PolicyDecision], algorithm: CombiningAlgorithm) -> PolicyDecision {
-        if decisions.is_empty() {
-            return PolicyDecision::default_deny();
+    /// Compares two strings after case folding; false if either is not a string.
+    fn compare_folded<F>(&self, left: &serde_json::Value, right: &serde_json::Value, cmp: F) -> bool
+    where
+        F: Fn(&str, &str) -> bool,
+    {
+        match (left.as_str(), right.as_str()) {
+            (Some(left_str), Some(right_str)) => cmp(&text::fold(left_str), &text::fold(right_str)),
+            _ => false,
         }
+    }
 
-        match algorithm {
-            CombiningAlgorithm::FirstApplicable => {
-                decisions[0].clone()
-            }
-
-            CombiningAlgorithm::DenyOverrides => {
-                for decision in decisions {
-                    if decision.is_denied() {
-                        return decision.clone();
-                    }
-                }
-                decisions.iter()
-                    .find(|d| d.is_allowed())
-                    .cloned()
-                    .unwrap_or_else(PolicyDecision::default_deny)
-            }
+    /// Combines multiple policy decisions, merging the obligations and advice
+    /// of every policy that agrees with the combined decision. Also returns
+    /// the index of the decision that determined the outcome.
+    fn combine_decisions(
+        &self,
+        decisions: &[PolicyDecision],
+        algorithm: CombiningAlgorithm,
+    ) -> (PolicyDecision, Option<usize>) {
+        let outcomes: Vec<Decision> = decisions.iter().map(|d| d.decision).collect();
+        let (combined, selected) = Decision::combine(algorithm, &outcomes);
 
-            CombiningAlgorithm::AllowOverrides => {
-                for decision in decisions {
-                    if decision.is_allowed() {
-                        return decision.clone();
-                    }
-                }
-                decisions.iter()
-                    .find(|d| d.is_denied())
-                    .cloned()
-                    .unwrap_or_else(PolicyDecision::default_deny)
-            }
+        let Some(index) = selected else {
+            return (PolicyDecision::not_applicable(), None);
+        };
+        let mut decision = decisions[index].clone();
+        decision.decision = combined;
 
-            CombiningAlgorithm::UnanimousAllow => {
-                if decisions.iter().all(|d| d.is_allowed()) {
-                    decisions[0].clone()
-                } else {
-                    decisions.iter()
-                        .find(|d| d.is_denied())
-                        .cloned()
-                        .unwrap_or_else(PolicyDecision::default_deny)
-                }
-            }
+        let contributing: Vec<&PolicyDecision> = match algorithm {
+            _ if combined.effect().is_none() => Vec::new(),
+            CombiningAlgorithm::FirstApplicable => vec![&decisions[index]],
+            _ => decisions.iter().filter(|d| d.decision == combined).collect(),
+        };
+        decision.obligations = contributing.iter().flat_map(|d| d.obligations.clone()).collect();
+        decision.advice = contributing.iter().flat_map(|d| d.advice.clone()).collect();
+        (decision, selected)
+    }
+}
 
-            CombiningAlgorithm::UnanimousDeny => {
-                if decisions.iter().all(|d| d.is_denied()) {
-                    decisions[0].clone()
-                } else {
-                    decisions.iter()
-                        .find(|d| d.is_allowed())
-                        .cloned()
-                        .unwrap_or_else(PolicyDecision::default_allow)
-                }
-            }
-        }
+/// Folds traced children with `all_of` semantics: any false child decides,
+/// otherwise the first error, otherwise true.
+fn fold_all_of(children: &[ExprTrace]) -> (bool, Option<String>) {
+    if children.iter().any(|c| c.error().is_none() && !c.matched()) {
+        return (false, None);
+    }
+    match children.iter().find_map(|c| c.error()) {
+        Some(error) => (false, Some(error.to_string())),
+        None => (true, None),
+    }
+}
+
+/// Folds traced children with `any_of` semantics: any true child decides,
+/// otherwise the first error, otherwise false.
+fn fold_any_of(children: &[ExprTrace]) -> (bool, Option<String>) {
+    if children.iter().any(|c| c.matched()) {
+        return (true, None);
     }
+    (false, children.iter().find_map(|c| c.error()).map(str::to_string))
 }
 
 impl Default for PolicyEvaluator {
@@ -355,6 +1226,7 @@ impl Default for PolicyEvaluator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::agreement::{Agreement, AgreementStatus, AgreementType};
     use crate::types::{Action, ActionType, Identity, Resource, ResourceType, Role, Tenant, TenantType};
 
     fn create_test_context(role: Role) -> EvaluationContext {
@@ -450,4 +1322,1288 @@ default_effect: deny
         let decision = evaluator.evaluate(&ctx).unwrap();
         assert!(decision.is_denied());
     }
+
+    #[test]
+    fn test_condition_tree() {
+        let policy_yaml = r#"
+id: tree-policy
+version: "1.0.0"
+name: Tree Policy
+rules:
+  - id: admin-or-developer
+    effect: allow
+    conditions:
+      - field: action.action_type
+        operator: equals
+        value: write
+      - any_of:
+          - field: role
+            operator: equals
+            value: admin
+          - all_of:
+              - field: identity.groups
+                operator: contains
+                value: developers
+              - not:
+                  field: role
+                  operator: equals
+                  value: guest
+    priority: 10
+default_effect: deny
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        // Admin matches the first branch
+        let decision = evaluator.evaluate(&create_test_context(Role::Admin)).unwrap();
+        assert!(decision.is_allowed());
+
+        // Member matches through the nested all_of
+        let decision = evaluator.evaluate(&create_test_context(Role::Member)).unwrap();
+        assert!(decision.is_allowed());
+
+        // Guest is excluded by the not
+        let decision = evaluator.evaluate(&create_test_context(Role::Guest)).unwrap();
+        assert!(decision.is_denied());
+    }
+
+    #[test]
+    fn test_field_to_field_comparison() {
+        let policy_yaml = r#"
+id: owner-policy
+version: "1.0.0"
+name: Owner Policy
+rules:
+  - id: owner-can-edit
+    effect: allow
+    conditions:
+      - field: resource.owner_id
+        operator: equals
+        value_field: identity.user_id
+    priority: 10
+default_effect: deny
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let mut ctx = create_test_context(Role::Member);
+        ctx.resource.owner_id = Some("u:test".to_string());
+        assert!(evaluator.evaluate(&ctx).unwrap().is_allowed());
+
+        ctx.resource.owner_id = Some("u:someone-else".to_string());
+        assert!(evaluator.evaluate(&ctx).unwrap().is_denied());
+    }
+
+    #[test]
+    fn test_obligations_collected() {
+        let policy_yaml = r#"
+id: obligation-policy
+version: "1.0.0"
+name: Obligation Policy
+combining_algorithm: deny_overrides
+rules:
+  - id: allow-members
+    effect: allow
+    conditions:
+      - field: role
+        operator: in
+        value: [member, guest]
+    priority: 10
+    obligations:
+      - id: redact-body
+        fulfill_on: allow
+      - id: never-on-allow
+        fulfill_on: deny
+    advice:
+      - id: rate-limit
+        fulfill_on: allow
+        attributes:
+          bucket: messages
+  - id: deny-guests
+    effect: deny
+    conditions:
+      - field: role
+        operator: equals
+        value: guest
+    priority: 20
+    obligations:
+      - id: audit-denial
+        fulfill_on: deny
+obligations:
+  - id: emit-receipt
+    fulfill_on: allow
+default_effect: deny
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let decision = evaluator.evaluate(&create_test_context(Role::Member)).unwrap();
+        assert!(decision.is_allowed());
+        let ids: Vec<&str> = decision.obligations.iter().map(|o| o.id.as_str()).collect();
+        assert_eq!(ids, vec!["redact-body", "emit-receipt"]);
+        assert_eq!(decision.advice[0].attributes["bucket"], "messages");
+
+        // The allow rule also matches for guests, but it did not win
+        let decision = evaluator.evaluate(&create_test_context(Role::Guest)).unwrap();
+        assert!(decision.is_denied());
+        let ids: Vec<&str> = decision.obligations.iter().map(|o| o.id.as_str()).collect();
+        assert_eq!(ids, vec!["audit-denial"]);
+        assert!(decision.advice.is_empty());
+
+        // Obligations are part of the JSON output
+        let json = serde_json::to_value(&decision).unwrap();
+        assert_eq!(json["obligations"][0]["id"], "audit-denial");
+    }
+
+    #[test]
+    fn test_missing_field_is_indeterminate() {
+        let policy_yaml = r#"
+id: indeterminate-policy
+version: "1.0.0"
+name: Indeterminate Policy
+rules:
+  - id: allow-members
+    effect: allow
+    conditions:
+      - field: role
+        operator: equals
+        value: member
+    priority: 10
+  - id: deny-foreign-owners
+    effect: deny
+    conditions:
+      - field: resource.owner_id
+        operator: not_equals
+        value_field: identity.user_id
+    priority: 20
+default_effect: deny
+"#;
+        let other_yaml = r#"
+id: other-policy
+version: "1.0.0"
+name: Other Policy
+rules:
+  - id: allow-all
+    effect: allow
+    conditions: []
+    priority: 1
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+        evaluator.load_policy_yaml(other_yaml).unwrap();
+
+        // owner_id is missing: the deny rule is Indeterminate{D}, which
+        // combined with the allowing member rule gives Indeterminate{DP}
+        // instead of aborting every policy.
+        let ctx = create_test_context(Role::Member);
+        let raw = evaluator.evaluate_raw(&ctx).unwrap();
+        assert_eq!(raw.decision, Decision::IndeterminateDP);
+        assert_eq!(raw.rule_id.as_deref(), Some("deny-foreign-owners"));
+        assert!(raw.reason.contains("resource.owner_id"));
+
+        // The PEP mapping turns it into a deny by default
+        let decision = evaluator.evaluate(&ctx).unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(decision.raw_decision, Some(Decision::IndeterminateDP));
+
+        let trace = evaluator.evaluate_with_trace(&ctx).unwrap();
+        assert_eq!(trace.decision.decision, decision.decision);
+        assert_eq!(trace.policies[0].rules[0].decision, Decision::IndeterminateD);
+        assert_eq!(trace.combining.decision, Decision::IndeterminateDP);
+
+        let mut permissive = PolicyEvaluator::new().with_decision_mapping(DecisionMapping {
+            not_applicable: Effect::Deny,
+            indeterminate: Effect::Allow,
+        });
+        permissive.load_policy_yaml(policy_yaml).unwrap();
+        assert!(permissive.evaluate(&ctx).unwrap().is_allowed());
+    }
+
+    #[test]
+    fn test_not_applicable_policy() {
+        let policy_yaml = r#"
+id: na-policy
+version: "1.0.0"
+name: Not Applicable Policy
+rules:
+  - id: deny-guests
+    effect: deny
+    conditions:
+      - field: role
+        operator: equals
+        value: guest
+    priority: 10
+default_effect: null
+"#;
+        let allow_yaml = r#"
+id: allow-policy
+version: "1.0.0"
+name: Allow Policy
+rules:
+  - id: allow-members
+    effect: allow
+    conditions:
+      - field: role
+        operator: equals
+        value: member
+    priority: 10
+default_effect: null
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        // Nothing applies to a member
+        let ctx = create_test_context(Role::Member);
+        assert!(evaluator.evaluate_raw(&ctx).unwrap().is_not_applicable());
+        let decision = evaluator.evaluate(&ctx).unwrap();
+        assert!(decision.is_denied());
+        assert!(decision.is_default);
+
+        // A not-applicable policy does not veto another policy's allow
+        evaluator.load_policy_yaml(allow_yaml).unwrap();
+        let decision = evaluator.evaluate(&ctx).unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(decision.policy_id.as_deref(), Some("allow-policy"));
+    }
+
+    #[test]
+    fn test_configurable_cross_policy_combining() {
+        let allow_yaml = r#"
+id: allow-policy
+version: "1.0.0"
+name: Allow Policy
+rules:
+  - id: allow-all
+    effect: allow
+    conditions: []
+    priority: 1
+"#;
+        let deny_yaml = r#"
+id: deny-policy
+version: "1.0.0"
+name: Deny Policy
+rules:
+  - id: deny-all
+    effect: deny
+    conditions: []
+    priority: 1
+"#;
+
+        let ctx = create_test_context(Role::Member);
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(deny_yaml).unwrap();
+        evaluator.load_policy_yaml(allow_yaml).unwrap();
+        assert!(evaluator.evaluate(&ctx).unwrap().is_denied());
+
+        let mut evaluator = PolicyEvaluator::new().with_combining_algorithm(CombiningAlgorithm::AllowOverrides);
+        evaluator.load_policy_yaml(deny_yaml).unwrap();
+        evaluator.load_policy_yaml(allow_yaml).unwrap();
+        let decision = evaluator.evaluate(&ctx).unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(decision.policy_id.as_deref(), Some("allow-policy"));
+    }
+
+    #[test]
+    fn test_policy_sets() {
+        let set_yaml = r#"
+id: platform
+version: "1.0.0"
+name: Platform
+combining_algorithm: first_applicable
+policies:
+  - id: platform-guards
+    version: "1.0.0"
+    name: Platform Guards
+    rules:
+      - id: deny-guests
+        effect: deny
+        conditions:
+          - field: role
+            operator: equals
+            value: guest
+        priority: 10
+    default_effect: null
+policy_sets:
+  - id: rooms
+    version: "1.0.0"
+    name: Rooms
+    target:
+      resource_types: [room]
+    combining_algorithm: allow_overrides
+    obligations:
+      - id: room-audit
+        fulfill_on: allow
+    policies:
+      - id: room-policy
+        version: "1.0.0"
+        name: Room Policy
+        rules:
+          - id: allow-messenger
+            effect: allow
+            conditions:
+              - field: action.action_name
+                operator: starts_with
+                value: messenger.
+            priority: 10
+  - id: fallback
+    version: "1.0.0"
+    name: Fallback
+    policies:
+      - id: fallback-policy
+        version: "1.0.0"
+        name: Fallback Policy
+        rules: []
+        default_effect: deny
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_set_yaml(set_yaml).unwrap();
+        assert_eq!(evaluator.policy_set_count(), 1);
+
+        // Guards are not applicable to members; the room set allows
+        let ctx = create_test_context(Role::Member);
+        let decision = evaluator.evaluate(&ctx).unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(decision.policy_id.as_deref(), Some("room-policy"));
+        assert_eq!(decision.obligations[0].id, "room-audit");
+
+        // Platform guards win under first-applicable
+        let ctx = create_test_context(Role::Guest);
+        let decision = evaluator.evaluate(&ctx).unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(decision.rule_id.as_deref(), Some("deny-guests"));
+
+        // The room set's target excludes documents, so the fallback denies
+        let mut ctx = create_test_context(Role::Member);
+        ctx.resource.resource_type = ResourceType::Document;
+        let decision = evaluator.evaluate(&ctx).unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(decision.policy_id.as_deref(), Some("fallback-policy"));
+
+        let trace = evaluator.evaluate_with_trace(&ctx).unwrap();
+        let platform = &trace.policy_sets[0];
+        assert!(!platform.policy_sets[0].target_matched);
+        assert_eq!(
+            platform.combining.as_ref().unwrap().selected.as_deref(),
+            Some("fallback")
+        );
+        assert_eq!(trace.combining.selected.as_deref(), Some("platform"));
+    }
+
+    #[test]
+    fn test_policy_and_rule_targets() {
+        let policy_yaml = r#"
+id: room-policy
+version: "1.0.0"
+name: Room Policy
+target:
+  resource_types: [room]
+rules:
+  - id: member-write
+    effect: allow
+    target:
+      action_types: [write]
+      roles: [member, admin, owner]
+    conditions:
+      - field: attributes.missing
+        operator: equals
+        value: never-evaluated-for-guests
+    priority: 10
+  - id: member-send
+    effect: allow
+    target:
+      action_names: ["messenger.*"]
+    conditions: []
+    priority: 5
+default_effect: deny
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        // The guest is outside member-write's target, so its failing
+        // condition is never evaluated and member-send allows
+        let ctx = create_test_context(Role::Guest);
+        let decision = evaluator.evaluate_raw(&ctx).unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(decision.rule_id.as_deref(), Some("member-send"));
+
+        let trace = evaluator.evaluate_with_trace(&ctx).unwrap();
+        let rule = &trace.policies[0].rules[0];
+        assert!(!rule.target_matched);
+        assert!(rule.conditions.is_empty());
+        assert_eq!(rule.decision, Decision::NotApplicable);
+
+        // A document request is outside the policy target: not applicable,
+        // not the policy's default deny
+        let mut ctx = create_test_context(Role::Member);
+        ctx.resource.resource_type = ResourceType::Document;
+        assert!(evaluator.evaluate_raw(&ctx).unwrap().is_not_applicable());
+
+        let trace = evaluator.evaluate_with_trace(&ctx).unwrap();
+        let policy = &trace.policies[0];
+        assert!(!policy.target_matched);
+        assert!(policy.rules.is_empty());
+        assert_eq!(policy.decision.policy_id.as_deref(), Some("room-policy"));
+        assert!(policy.decision.reason.contains("Target of policy 'room-policy'"));
+    }
+
+    #[test]
+    fn test_core_policy() {
+        let policy = Policy::from_yaml(include_str!("../../../policies/ubl_core_v1.yaml")).unwrap();
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.add_policy(policy);
+
+        // Members can send messages in rooms
+        let decision = evaluator.evaluate(&create_test_context(Role::Member)).unwrap();
+        assert!(decision.is_allowed());
+
+        // Guests cannot write
+        let decision = evaluator.evaluate(&create_test_context(Role::Guest)).unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(decision.rule_id.as_deref(), Some("deny-guest-write"));
+
+        // Members can read receipts but not delete them
+        let mut ctx = create_test_context(Role::Member);
+        ctx.resource.resource_type = ResourceType::Receipt;
+        ctx.action = Action {
+            action_type: ActionType::Read,
+            action_name: "receipts.get".to_string(),
+        };
+        assert!(evaluator.evaluate(&ctx).unwrap().is_allowed());
+        ctx.action.action_type = ActionType::Delete;
+        assert!(evaluator.evaluate(&ctx).unwrap().is_denied());
+
+        // Members can use office tools
+        let mut ctx = create_test_context(Role::Member);
+        ctx.resource.resource_type = ResourceType::Tool;
+        ctx.action = Action {
+            action_type: ActionType::Execute,
+            action_name: "office.llm.complete".to_string(),
+        };
+        assert!(evaluator.evaluate(&ctx).unwrap().is_allowed());
+    }
+
+    #[test]
+    fn test_rule_index_matches_interpreter() {
+        let core = include_str!("../../../policies/ubl_core_v1.yaml");
+        let mut indexed = PolicyEvaluator::new();
+        indexed.load_policy_yaml(core).unwrap();
+        let mut interpreted = PolicyEvaluator::new().without_rule_index();
+        interpreted.load_policy_yaml(core).unwrap();
+
+        let resource_types = [
+            ResourceType::Tenant,
+            ResourceType::Room,
+            ResourceType::Message,
+            ResourceType::Workspace,
+            ResourceType::Document,
+            ResourceType::Tool,
+            ResourceType::Receipt,
+        ];
+        let action_types = [
+            ActionType::Read,
+            ActionType::Write,
+            ActionType::Create,
+            ActionType::Delete,
+            ActionType::Execute,
+            ActionType::Admin,
+        ];
+        let action_names = ["messenger.send", "office.llm.complete", "office", "receipts.get"];
+        let roles = [None, Some(Role::Guest), Some(Role::Member), Some(Role::Admin), Some(Role::Owner)];
+
+        for resource_type in &resource_types {
+            for action_type in &action_types {
+                for action_name in action_names {
+                    for role in &roles {
+                        for is_service in [false, true] {
+                            let mut ctx = create_test_context(Role::Member);
+                            ctx.role = role.clone();
+                            ctx.identity.is_service = is_service;
+                            ctx.resource.resource_type = resource_type.clone();
+                            ctx.action = Action {
+                                action_type: action_type.clone(),
+                                action_name: action_name.to_string(),
+                            };
+
+                            let mut expected = interpreted.evaluate_raw(&ctx).unwrap();
+                            let mut actual = indexed.evaluate_raw(&ctx).unwrap();
+                            expected.evaluation_time_us = None;
+                            actual.evaluation_time_us = None;
+                            assert_eq!(
+                                serde_json::to_string(&actual).unwrap(),
+                                serde_json::to_string(&expected).unwrap()
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_regex_limits() {
+        let policy_yaml = r#"
+id: regex-policy
+version: "1.0.0"
+name: Regex Policy
+rules:
+  - id: example-users
+    effect: allow
+    conditions:
+      - field: identity.email
+        operator: matches
+        value: "^[a-z]+@example\\.com$"
+    priority: 10
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+        assert!(evaluator.evaluate(&create_test_context(Role::Member)).unwrap().is_allowed());
+
+        // Stricter limits reject the pattern at load time
+        let limits = RegexLimits {
+            max_pattern_len: 8,
+            ..RegexLimits::default()
+        };
+        let mut strict = PolicyEvaluator::new().with_regex_limits(limits).unwrap();
+        assert!(strict.load_policy_yaml(policy_yaml).is_err());
+        assert_eq!(strict.policy_count(), 0);
+
+        // Added directly, the pattern makes its rule indeterminate
+        strict.add_policy(Policy::from_yaml(policy_yaml).unwrap());
+        let decision = strict.evaluate_raw(&create_test_context(Role::Member)).unwrap();
+        assert_eq!(decision.decision, Decision::IndeterminateP);
+
+        // Tightening the limits of a loaded evaluator rejects the pattern
+        assert!(evaluator.with_regex_limits(limits).is_err());
+
+        // Raised limits admit a pattern over the default length
+        let long_yaml = policy_yaml.replace("^[a-z]+@", &format!("^(?:{})?[a-z]+@", "x".repeat(2000)));
+        assert!(PolicyEvaluator::new().load_policy_yaml(&long_yaml).is_err());
+        let limits = RegexLimits {
+            max_pattern_len: 4096,
+            ..RegexLimits::default()
+        };
+        let mut relaxed = PolicyEvaluator::new().with_regex_limits(limits).unwrap();
+        relaxed.load_policy_yaml(&long_yaml).unwrap();
+        assert!(relaxed.evaluate(&create_test_context(Role::Member)).unwrap().is_allowed());
+    }
+
+    #[test]
+    fn test_clock_and_deterministic_mode() {
+        let policy_yaml = r#"
+id: clock-policy
+version: "1.0.0"
+name: Clock Policy
+rules:
+  - id: new-year
+    effect: allow
+    conditions:
+      - field: environment.now
+        operator: starts_with
+        value: "2026-01-01T"
+    priority: 10
+    obligations:
+      - id: audit
+        fulfill_on: allow
+        attributes:
+          level: high
+          channel: receipts
+"#;
+
+        let clock = crate::clock::FixedClock::from_unix_millis(1_767_225_600_000).unwrap();
+        let mut evaluator = PolicyEvaluator::new().with_clock(clock).deterministic();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let ctx = create_test_context(Role::Member);
+        let first = evaluator.evaluate(&ctx).unwrap();
+        assert!(first.is_allowed());
+        assert_eq!(first.evaluation_time_us, None);
+        assert_eq!(
+            serde_json::to_string(&first).unwrap(),
+            serde_json::to_string(&evaluator.evaluate(&ctx).unwrap()).unwrap()
+        );
+
+        // A caller-supplied time is ignored unless the evaluator trusts it
+        let mut ctx = create_test_context(Role::Member);
+        ctx.environment.now = Some("2026-06-01T00:00:00.000Z".to_string());
+        assert!(evaluator.evaluate(&ctx).unwrap().is_allowed());
+        let mut trusting = PolicyEvaluator::new().with_clock(clock).trust_request_time();
+        trusting.load_policy_yaml(policy_yaml).unwrap();
+        assert!(trusting.evaluate(&ctx).unwrap().is_denied());
+        assert!(trusting.evaluate(&create_test_context(Role::Member)).unwrap().is_allowed());
+
+        // The system clock measures evaluation time natively
+        let mut timed = PolicyEvaluator::new();
+        timed.load_policy_yaml(policy_yaml).unwrap();
+        assert!(timed.evaluate(&ctx).unwrap().evaluation_time_us.is_some());
+    }
+
+    #[test]
+    fn test_time_windows() {
+        let policy_yaml = r#"
+id: time-policy
+version: "1.0.0"
+name: Time Policy
+default_effect: deny
+rules:
+  - id: business-hours-write
+    effect: allow
+    conditions:
+      - field: environment.now
+        operator: day_of_week_in
+        value: { days: [mon, tue, wed, thu, fri], timezone_field: attributes.timezone }
+      - field: environment.now
+        operator: time_of_day_between
+        value: { start: "09:00", end: "17:00", timezone_field: attributes.timezone }
+    priority: 10
+  - id: launch-promo
+    effect: allow
+    conditions: []
+    priority: 5
+    valid_from: "2026-03-01T00:00:00Z"
+    valid_until: "2026-03-08T00:00:00Z"
+"#;
+
+        let evaluator_at = |now: &str| {
+            let now = chrono::DateTime::parse_from_rfc3339(now).unwrap().with_timezone(&chrono::Utc);
+            let mut evaluator = PolicyEvaluator::new().with_clock(crate::clock::FixedClock::new(now));
+            evaluator.load_policy_yaml(policy_yaml).unwrap();
+            evaluator
+        };
+        let ctx = create_test_context(Role::Member).with_attribute("timezone", serde_json::json!("Asia/Tokyo"));
+
+        // Monday 01:00 UTC is 10:00 in Tokyo
+        let decision = evaluator_at("2026-02-02T01:00:00Z").evaluate(&ctx).unwrap();
+        assert_eq!(decision.rule_id.as_deref(), Some("business-hours-write"));
+
+        // Monday 10:00 UTC is 19:00 in Tokyo
+        assert!(evaluator_at("2026-02-02T10:00:00Z").evaluate(&ctx).unwrap().is_denied());
+
+        // The promo rule applies only within its validity period
+        let decision = evaluator_at("2026-03-07T23:00:00Z").evaluate(&ctx).unwrap();
+        assert_eq!(decision.rule_id.as_deref(), Some("launch-promo"));
+        let trace = evaluator_at("2026-03-08T00:00:00Z").evaluate_with_trace(&ctx).unwrap();
+        assert!(trace.decision.is_denied());
+        assert!(!trace.policies[0].rules[1].target_matched);
+
+        // Timezone lookups that fail make the rule indeterminate
+        let decision = evaluator_at("2026-02-02T01:00:00Z")
+            .evaluate_raw(&create_test_context(Role::Member))
+            .unwrap();
+        assert_eq!(decision.decision, Decision::IndeterminateP);
+    }
+
+    #[test]
+    fn test_ip_in_cidr() {
+        let policy_yaml = r#"
+id: network-policy
+version: "1.0.0"
+name: Network Policy
+default_effect: deny
+networks:
+  corporate: [10.0.0.0/8, "2001:db8:1200::/40"]
+rules:
+  - id: admin-from-corporate
+    effect: allow
+    conditions:
+      - field: environment.ip_address
+        operator: ip_in_cidr
+        value: [corporate, 192.0.2.10]
+    priority: 10
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let with_ip = |ip: &str| {
+            let mut ctx = create_test_context(Role::Admin);
+            ctx.environment.ip_address = Some(ip.to_string());
+            ctx
+        };
+        assert!(evaluator.evaluate(&with_ip("10.20.30.40")).unwrap().is_allowed());
+        assert!(evaluator.evaluate(&with_ip("2001:db8:1234::7")).unwrap().is_allowed());
+        assert!(evaluator.evaluate(&with_ip("192.0.2.10")).unwrap().is_allowed());
+        assert!(evaluator.evaluate(&with_ip("198.51.100.1")).unwrap().is_denied());
+
+        let decision = evaluator.evaluate_raw(&with_ip("not-an-ip")).unwrap();
+        assert_eq!(decision.decision, Decision::IndeterminateP);
+
+        // Network names must resolve, and cannot be redefined differently
+        let mut evaluator = PolicyEvaluator::new();
+        assert!(evaluator
+            .load_policy_yaml(&policy_yaml.replace("  corporate: [10.0.0.0/8, \"2001:db8:1200::/40\"]\n", "  vpn: [10.8.0.0/16]\n"))
+            .is_err());
+        evaluator.define_network("corporate", vec!["172.16.0.0/12".to_string()]).unwrap();
+        assert!(evaluator.load_policy_yaml(policy_yaml).is_err());
+        assert_eq!(evaluator.policy_count(), 0);
+
+        // Literal CIDRs are validated at load
+        assert!(Policy::from_yaml(&policy_yaml.replace("192.0.2.10", "192.0.2.0/40")).is_err());
+    }
+
+    #[test]
+    fn test_case_insensitive_operators() {
+        let policy_yaml = r#"
+id: domain-policy
+version: "1.0.0"
+name: Domain Policy
+rules:
+  - id: allow-example
+    effect: allow
+    conditions:
+      - field: identity.email_domain
+        operator: equals_ignore_case
+        value: example.com
+      - field: action.action_name
+        operator: starts_with_ignore_case
+        value: messenger.
+      - field: identity.groups
+        operator: contains_ignore_case
+        value: DEVELOPERS
+    priority: 10
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let mut context = create_test_context(Role::Member);
+        context.identity.email_domain = "Example.COM".to_string();
+        context.action.action_name = "Messenger.Send".to_string();
+        assert!(evaluator.evaluate(&context).unwrap().is_allowed());
+
+        context.identity.email_domain = "example.co".to_string();
+        assert!(evaluator.evaluate(&context).unwrap().is_denied());
+
+        // Byte-exact operators only match canonically equivalent strings
+        // when normalization is enabled.
+        let exact_yaml = r#"
+id: name-policy
+version: "1.0.0"
+name: Name Policy
+rules:
+  - id: allow-cafe
+    effect: allow
+    conditions:
+      - field: attributes.venue
+        operator: in
+        value: ["caf\u00e9"]
+    priority: 10
+"#;
+        let context = create_test_context(Role::Member).with_attribute("venue", serde_json::json!("cafe\u{0301}"));
+        let mut exact = PolicyEvaluator::new();
+        exact.load_policy_yaml(exact_yaml).unwrap();
+        assert!(exact.evaluate(&context).unwrap().is_denied());
+
+        let mut normalized = PolicyEvaluator::new().normalize_unicode();
+        normalized.load_policy_yaml(exact_yaml).unwrap();
+        assert!(normalized.evaluate(&context).unwrap().is_allowed());
+
+        // The rule index looks up normalized action names too.
+        let action_yaml = exact_yaml.replace("attributes.venue", "action.action_name").replace("caf\\u00e9", "caf\\u00e9.send");
+        let mut context = create_test_context(Role::Member);
+        context.action.action_name = "cafe\u{0301}.send".to_string();
+        let mut indexed = PolicyEvaluator::new().normalize_unicode();
+        indexed.load_policy_yaml(&action_yaml).unwrap();
+        assert!(indexed.evaluate(&context).unwrap().is_allowed());
+        let mut unindexed = PolicyEvaluator::new().normalize_unicode().without_rule_index();
+        unindexed.load_policy_yaml(&action_yaml).unwrap();
+        assert!(unindexed.evaluate(&context).unwrap().is_allowed());
+    }
+
+    #[test]
+    fn test_glob_operator() {
+        let policy_yaml = r#"
+id: project-policy
+version: "1.0.0"
+name: Project Policy
+rules:
+  - id: allow-project-rooms
+    effect: allow
+    conditions:
+      - field: resource.resource_id
+        operator: glob
+        value: "r:project-*"
+      - field: action.action_name
+        operator: glob
+        value: "messenger.**"
+    priority: 10
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let mut context = create_test_context(Role::Member);
+        context.resource.resource_id = "r:project-apollo".to_string();
+        assert!(evaluator.evaluate(&context).unwrap().is_allowed());
+
+        context.resource.resource_id = "r:project-apollo:archive".to_string();
+        assert!(evaluator.evaluate(&context).unwrap().is_denied());
+
+        assert!(evaluator.load_policy_yaml(&policy_yaml.replace("r:project-*", "r:***")).is_err());
+    }
+
+    #[test]
+    fn test_role_hierarchy_and_permissions() {
+        let policy_yaml = r#"
+id: rbac-policy
+version: "1.0.0"
+name: RBAC Policy
+roles:
+  member:
+    permissions: [room.write]
+  moderator:
+    inherits: [member]
+    permissions: [room.moderate]
+permission_sets:
+  room.write: [messenger.send, messenger.edit]
+  room.moderate: ["messenger.**"]
+rules:
+  - id: allow-permitted-actions
+    effect: allow
+    conditions:
+      - field: role
+        operator: has_permission
+        value_field: action.action_name
+    priority: 10
+  - id: deny-pins-below-moderator
+    effect: deny
+    conditions:
+      - field: action.action_name
+        operator: equals
+        value: messenger.pin
+      - not:
+          field: role
+          operator: role_at_least
+          value: moderator
+    priority: 20
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let with_action = |role: Role, action_name: &str| {
+            let mut context = create_test_context(role);
+            context.action.action_name = action_name.to_string();
+            context
+        };
+        let moderator = Role::parse("moderator");
+        assert_eq!(moderator, Role::Custom("moderator".to_string()));
+
+        assert!(evaluator.evaluate(&with_action(Role::Member, "messenger.send")).unwrap().is_allowed());
+        assert!(evaluator.evaluate(&with_action(Role::Owner, "messenger.edit")).unwrap().is_allowed());
+        assert!(evaluator.evaluate(&with_action(Role::Guest, "messenger.send")).unwrap().is_denied());
+        assert!(evaluator.evaluate(&with_action(Role::Member, "messenger.pin")).unwrap().is_denied());
+        assert!(evaluator.evaluate(&with_action(moderator.clone(), "messenger.pin")).unwrap().is_allowed());
+        // Admins inherit member, not moderator
+        assert!(evaluator.evaluate(&with_action(Role::Admin, "messenger.pin")).unwrap().is_denied());
+
+        let cyclic = policy_yaml.replace("inherits: [member]", "inherits: [member, moderator]");
+        assert!(PolicyEvaluator::new().load_policy_yaml(&cyclic).is_err());
+        let unknown = policy_yaml.replace("value: moderator", "value: supervisor");
+        assert!(PolicyEvaluator::new().load_policy_yaml(&unknown).is_err());
+        let conflicting = policy_yaml.replace("id: rbac-policy", "id: other").replace("[room.moderate]", "[room.write]");
+        assert!(evaluator.load_policy_yaml(&conflicting).is_err());
+    }
+
+    #[test]
+    fn test_declared_types() {
+        let policy_yaml = r#"
+id: wallet-policy
+version: "1.0.0"
+name: Wallet Policy
+types:
+  resource_types: [wallet]
+  action_types: [transfer]
+rules:
+  - id: owners-transfer
+    effect: allow
+    target:
+      resource_types: [wallet]
+      action_types: [transfer]
+      roles: [owner]
+    conditions: []
+    priority: 10
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        // Types the engine doesn't know still deserialize
+        let mut json = serde_json::to_value(create_test_context(Role::Owner)).unwrap();
+        json["resource"]["resource_type"] = "wallet".into();
+        json["action"]["action_type"] = "transfer".into();
+        let mut context: EvaluationContext = serde_json::from_value(json).unwrap();
+        assert!(evaluator.evaluate(&context).unwrap().is_allowed());
+        context.resource.resource_type = ResourceType::Room;
+        assert!(evaluator.evaluate(&context).unwrap().is_denied());
+
+        let typo = policy_yaml.replace("resource_types: [wallet]\n      action_types", "resource_types: [walet]\n      action_types");
+        assert!(PolicyEvaluator::new().load_policy_yaml(&typo).is_err());
+        let undeclared = policy_yaml.replace("  action_types: [transfer]\nrules", "rules");
+        assert!(PolicyEvaluator::new().load_policy_yaml(&undeclared).is_err());
+
+        let mut declared = PolicyEvaluator::new();
+        declared.declare_action_type("transfer").unwrap();
+        assert!(declared.load_policy_yaml(&undeclared).is_ok());
+    }
+
+    #[test]
+    fn test_agreement_conditions() {
+        let policy_yaml = r#"
+id: agreement-policy
+version: "1.0.0"
+name: Agreement Policy
+rules:
+  - id: send-under-agreement
+    effect: allow
+    conditions:
+      - field: agreement.status
+        operator: equals
+        value: active
+      - field: agreement.capabilities
+        operator: contains
+        value: send_messages
+    priority: 10
+"#;
+
+        let agreement = Agreement::new("a:room:r:general", AgreementType::RoomGovernance, "t:example.com")
+            .with_status(AgreementStatus::Active)
+            .with_party("u:test", Some("member".to_string()))
+            .with_capabilities(vec!["send_messages".to_string(), "read_history".to_string()])
+            .with_expires_at("2030-01-01T00:00:00Z".parse().unwrap());
+        let at = |now: &str, agreement: Option<Agreement>| {
+            let mut context = create_test_context(Role::Member);
+            context.agreement = agreement;
+            context.resource.agreement_id = Some("a:room:r:general".to_string());
+            context.environment.now = Some(now.to_string());
+            context
+        };
+        let before = "2029-06-01T00:00:00Z";
+
+        let mut evaluator = PolicyEvaluator::new().trust_request_time();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+        assert!(evaluator.evaluate(&at(before, Some(agreement.clone()))).unwrap().is_allowed());
+        // Past expires_at, agreement.status reads as expired
+        assert!(evaluator.evaluate(&at("2030-06-01T00:00:00Z", Some(agreement.clone()))).unwrap().is_denied());
+        let suspended = agreement.clone().with_status(AgreementStatus::Suspended);
+        assert!(evaluator.evaluate(&at(before, Some(suspended.clone()))).unwrap().is_denied());
+        let mut no_send = agreement.clone();
+        no_send.capabilities.retain(|c| c != "send_messages");
+        assert!(evaluator.evaluate(&at(before, Some(no_send))).unwrap().is_denied());
+
+        // Without the requirement, a policy that doesn't check agreements
+        // allows requests made under none
+        let allow_all = r#"
+id: allow-all
+version: "1.0.0"
+name: Allow All
+rules:
+  - id: allow
+    effect: allow
+    conditions: []
+    priority: 10
+"#;
+        let mut open = PolicyEvaluator::new().trust_request_time();
+        open.load_policy_yaml(allow_all).unwrap();
+        assert!(open.evaluate(&at(before, None)).unwrap().is_allowed());
+
+        let mut strict = PolicyEvaluator::new().require_agreement().trust_request_time();
+        strict.load_policy_yaml(allow_all).unwrap();
+        assert!(strict.evaluate(&at(before, Some(agreement.clone()))).unwrap().is_allowed());
+        let decision = strict.evaluate(&at(before, None)).unwrap();
+        assert!(decision.is_denied());
+        assert_eq!(decision.reason, "No agreement authorizes the request");
+        assert!(strict.evaluate(&at(before, Some(suspended))).unwrap().is_denied());
+        let mut other_room = at(before, Some(agreement.clone()));
+        other_room.resource.agreement_id = Some("a:room:r:random".to_string());
+        assert!(strict.evaluate(&other_room).unwrap().is_denied());
+        let trace = strict.evaluate_with_trace(&at(before, None)).unwrap();
+        assert!(trace.decision.is_denied());
+        assert!(trace.policies.is_empty());
+
+        // Without trusting request times, an expired agreement stays expired
+        // whatever time the request claims
+        let after = crate::clock::FixedClock::new("2030-06-01T00:00:00Z".parse().unwrap());
+        let mut clocked = PolicyEvaluator::new().require_agreement().with_clock(after);
+        clocked.load_policy_yaml(allow_all).unwrap();
+        let decision = clocked.evaluate(&at(before, Some(agreement.clone()))).unwrap();
+        assert_eq!(decision.reason, "Agreement 'a:room:r:general' is expired");
+    }
+
+    #[test]
+    fn test_set_operators() {
+        let policy_yaml = r#"
+id: set-policy
+version: "1.0.0"
+name: Set Policy
+default_effect: deny
+rules:
+  - id: finance-or-legal
+    effect: allow
+    conditions:
+      - field: identity.groups
+        operator: intersects
+        value: [finance, legal]
+    priority: 30
+  - id: required-groups
+    effect: allow
+    conditions:
+      - field: identity.groups
+        operator: superset_of
+        value_field: attributes.required_groups
+      - field: attributes.required_groups
+        operator: count_at_least
+        value: 1
+    priority: 20
+  - id: no-contractors
+    effect: deny
+    conditions:
+      - not:
+          field: identity.groups
+          operator: disjoint
+          value: [contractors]
+    priority: 10
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let with_groups = |groups: &[&str], required: serde_json::Value| {
+            let mut ctx = create_test_context(Role::Member).with_attribute("required_groups", required);
+            ctx.identity.groups = groups.iter().map(|g| g.to_string()).collect();
+            ctx
+        };
+
+        let decision = evaluator.evaluate(&with_groups(&["legal"], serde_json::json!([]))).unwrap();
+        assert_eq!(decision.rule_id.as_deref(), Some("finance-or-legal"));
+
+        let ctx = with_groups(&["developers", "oncall"], serde_json::json!(["oncall", "developers"]));
+        assert_eq!(evaluator.evaluate(&ctx).unwrap().rule_id.as_deref(), Some("required-groups"));
+
+        let ctx = with_groups(&["developers"], serde_json::json!(["oncall", "developers"]));
+        assert!(evaluator.evaluate(&ctx).unwrap().is_denied());
+
+        let ctx = with_groups(&["legal", "contractors"], serde_json::json!([]));
+        assert_eq!(evaluator.evaluate(&ctx).unwrap().rule_id.as_deref(), Some("no-contractors"));
+
+        // A non-array operand makes the rule indeterminate
+        let decision = evaluator
+            .evaluate_raw(&with_groups(&["developers"], serde_json::json!("oncall")))
+            .unwrap();
+        assert_eq!(decision.decision, Decision::IndeterminateP);
+
+        // Literal operands are checked at load
+        assert!(Policy::from_yaml(&policy_yaml.replace("value: [finance, legal]", "value: finance")).is_err());
+        assert!(Policy::from_yaml(&policy_yaml.replace("value: 1", "value: -1")).is_err());
+    }
+
+    #[test]
+    fn test_nested_attribute_paths() {
+        let policy_yaml = r#"
+id: tool-policy
+version: "1.0.0"
+name: Tool Policy
+rules:
+  - id: deny-system-paths
+    effect: deny
+    conditions:
+      - field: attributes.tool.args.paths[*]
+        operator: intersects
+        value: [/etc/passwd, /etc/shadow]
+    priority: 20
+  - id: allow-tool
+    effect: allow
+    conditions:
+      - field: attributes.tool.name
+        operator: equals
+        value: fs.read
+    priority: 10
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let with_paths = |paths: serde_json::Value| {
+            create_test_context(Role::Member)
+                .with_attribute("tool", serde_json::json!({ "name": "fs.read", "args": { "paths": paths } }))
+        };
+        assert!(evaluator.evaluate(&with_paths(serde_json::json!(["/tmp/a"]))).unwrap().is_allowed());
+        assert!(evaluator
+            .evaluate(&with_paths(serde_json::json!(["/tmp/a", "/etc/shadow"])))
+            .unwrap()
+            .is_denied());
+
+        let trace = evaluator.evaluate_with_trace(&with_paths(serde_json::json!(["/tmp/a"]))).unwrap();
+        match &trace.policies[0].rules[0].conditions[0] {
+            ExprTrace::Condition(condition) => assert_eq!(condition.left, Some(serde_json::json!(["/tmp/a"]))),
+            other => panic!("unexpected trace {:?}", other),
+        }
+
+        assert!(Policy::from_yaml(&policy_yaml.replace("attributes.tool.name", "attributes..name")).is_err());
+    }
+
+    #[test]
+    fn test_computed_conditions() {
+        let policy_yaml = r#"
+id: quota-policy
+version: "1.0.0"
+name: Quota Policy
+rules:
+  - id: allow-within-quota
+    effect: allow
+    conditions:
+      - expr: "lower(identity.email_domain)"
+        operator: equals
+        value: example.com
+      - expr: "split(action.action_name, '.')[0]"
+        operator: equals
+        value: messenger
+      - expr: "attributes.quota.used + len(attributes.body)"
+        operator: less_than_or_equal
+        value_expr: "attributes.quota.limit"
+    priority: 10
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let with_usage = |used: i64| {
+            let mut context = create_test_context(Role::Member)
+                .with_attribute("quota", serde_json::json!({ "used": used, "limit": 100 }))
+                .with_attribute("body", serde_json::json!("hello"));
+            context.identity.email_domain = "Example.COM".to_string();
+            context
+        };
+        assert!(evaluator.evaluate(&with_usage(95)).unwrap().is_allowed());
+        assert!(evaluator.evaluate(&with_usage(96)).unwrap().is_denied());
+
+        let trace = evaluator.evaluate_with_trace(&with_usage(96)).unwrap();
+        match &trace.policies[0].rules[0].conditions[2] {
+            ExprTrace::Condition(condition) => {
+                assert_eq!(condition.left, Some(serde_json::json!(101)));
+                assert_eq!(condition.right, Some(serde_json::json!(100)));
+            }
+            other => panic!("unexpected trace {:?}", other),
+        }
+
+        // A missing operand makes the condition indeterminate, like a missing field.
+        let raw = evaluator.evaluate_raw(&create_test_context(Role::Member)).unwrap();
+        assert!(matches!(raw.decision, Decision::IndeterminateP));
+
+        assert!(evaluator
+            .load_policy_yaml(&policy_yaml.replace("lower(identity.email_domain)", "lower(1)"))
+            .is_err());
+        assert!(evaluator
+            .load_policy_yaml(&policy_yaml.replace("lower(identity.email_domain)", "lower(identity.email_domain"))
+            .is_err());
+
+        // Tightening the limits of a loaded evaluator rejects the expressions
+        let limits = ExpressionLimits {
+            max_source_len: 16,
+            ..ExpressionLimits::default()
+        };
+        assert!(evaluator.with_expression_limits(limits).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_with_trace() {
+        let policy_yaml = r#"
+id: trace-policy
+version: "1.0.0"
+name: Trace Policy
+rules:
+  - id: allow-members
+    effect: allow
+    conditions:
+      - field: role
+        operator: in
+        value: [member, admin]
+    priority: 10
+  - id: deny-guests-or-services
+    effect: deny
+    conditions:
+      - any_of:
+          - field: role
+            operator: equals
+            value: guest
+          - field: identity.is_service
+            operator: equals
+            value: true
+    priority: 20
+default_effect: deny
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        let ctx = create_test_context(Role::Guest);
+        let trace = evaluator.evaluate_with_trace(&ctx).unwrap();
+        let decision = evaluator.evaluate(&ctx).unwrap();
+        assert_eq!(trace.decision.decision, decision.decision);
+        assert_eq!(trace.decision.rule_id.as_deref(), Some("deny-guests-or-services"));
+
+        let policy = &trace.policies[0];
+        // Rules appear in priority order
+        assert_eq!(policy.rules[0].rule_id, "deny-guests-or-services");
+        assert!(policy.rules[0].matched);
+        assert!(!policy.rules[1].matched);
+        assert_eq!(policy.combining.as_ref().unwrap().candidates, vec!["deny-guests-or-services"]);
+
+        // Both any_of branches are traced even though the first one matched
+        match &policy.rules[0].conditions[0] {
+            ExprTrace::AnyOf { children, .. } => {
+                assert_eq!(children.len(), 2);
+                match &children[1] {
+                    ExprTrace::Condition(c) => {
+                        assert_eq!(c.left, Some(serde_json::json!(false)));
+                        assert!(!c.matched);
+                    }
+                    other => panic!("unexpected trace {:?}", other),
+                }
+            }
+            other => panic!("unexpected trace {:?}", other),
+        }
+
+        assert_eq!(trace.combining.selected.as_deref(), Some("trace-policy"));
+        assert!(trace.to_json().unwrap().contains("\"type\":\"any_of\""));
+    }
+
+    #[test]
+    fn test_trace_ignores_errors_in_skipped_branches() {
+        let policy_yaml = r#"
+id: skip-policy
+version: "1.0.0"
+name: Skip Policy
+rules:
+  - id: member-or-owner
+    effect: allow
+    conditions:
+      - any_of:
+          - field: role
+            operator: equals
+            value: member
+          - field: resource.owner_id
+            operator: equals
+            value_field: identity.user_id
+    priority: 10
+default_effect: deny
+"#;
+
+        let mut evaluator = PolicyEvaluator::new();
+        evaluator.load_policy_yaml(policy_yaml).unwrap();
+
+        // owner_id is missing, but the first branch already matched
+        let ctx = create_test_context(Role::Member);
+        assert!(evaluator.evaluate(&ctx).unwrap().is_allowed());
+        let trace = evaluator.evaluate_with_trace(&ctx).unwrap();
+        assert!(trace.decision.is_allowed());
+    }
 }
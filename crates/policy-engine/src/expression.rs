@@ -0,0 +1,1147 @@
+//! Computed values for conditions.
+//!
+//! A condition's `expr` (left-hand side) or `value_expr` (right-hand side)
+//! may compute its operand from context paths:
+//!
+//! ```text
+//! lower(identity.email_domain)
+//! len(attributes.body)
+//! split(action.action_name, '.')[0]
+//! attributes.quota.used + attributes.request.tokens
+//! ```
+//!
+//! Expressions are parsed and type-checked when a policy is loaded. They
+//! have no side effects, loops or access to the clock, so the same context
+//! always yields the same value. The limits below bound the size of an
+//! expression and of every value it produces, which bounds the cost of
+//! evaluating it.
+//!
+//! Numbers are 64-bit floats; integral results are returned as integers. A
+//! missing path makes the whole expression missing (like a missing `field`),
+//! except inside `coalesce`, which returns its first present, non-null
+//! argument.
+
+use crate::context::{ContextAt, EvaluationContext, PathSegment};
+use crate::error::{PolicyError, Result};
+use crate::policy::{Policy, PolicySet};
+use crate::types::ConditionExpr;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Limits applied when compiling and evaluating expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpressionLimits {
+    /// Maximum expression length in bytes.
+    pub max_source_len: usize,
+
+    /// Maximum number of syntax nodes.
+    pub max_nodes: usize,
+
+    /// Maximum nesting depth of calls, operators and parentheses.
+    pub max_depth: usize,
+
+    /// Maximum length of a string (in bytes) or list produced during
+    /// evaluation.
+    pub max_value_len: usize,
+}
+
+impl Default for ExpressionLimits {
+    fn default() -> Self {
+        Self {
+            max_source_len: 1024,
+            max_nodes: 128,
+            max_depth: 16,
+            max_value_len: 64 * 1024,
+        }
+    }
+}
+
+impl ExpressionLimits {
+    /// Parses and type-checks an expression within these limits.
+    pub fn compile(&self, source: &str) -> Result<Expression> {
+        if source.len() > self.max_source_len {
+            return Err(PolicyError::ValidationError(format!(
+                "Expression is {} bytes, exceeding the limit of {}",
+                source.len(),
+                self.max_source_len
+            )));
+        }
+
+        let invalid = |message: String| PolicyError::ValidationError(format!("Invalid expression '{}': {}", source, message));
+        let tokens = tokenize(source).map_err(invalid)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            nodes: 0,
+            depth: 0,
+            limits: self,
+        };
+        let root = parser.expression().map_err(invalid)?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(format!("unexpected {}", token)));
+        }
+        check(&root).map_err(invalid)?;
+
+        Ok(Expression {
+            source: source.to_string(),
+            root,
+            max_value_len: self.max_value_len,
+        })
+    }
+}
+
+/// A compiled expression.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    source: String,
+    root: Node,
+    max_value_len: usize,
+}
+
+impl Expression {
+    /// Returns the source text the expression was compiled from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluates the expression, returning `None` if a path it depends on is
+    /// missing.
+    pub fn evaluate(&self, context: &EvaluationContext) -> Result<Option<Value>> {
+        self.evaluate_at(&context.into())
+    }
+
+    /// Evaluates the expression against a context read at an evaluation time.
+    pub fn evaluate_at(&self, context: &ContextAt) -> Result<Option<Value>> {
+        self.eval(&self.root, context)
+            .map_err(|message| PolicyError::ConditionError(format!("Expression '{}': {}", self.source, message)))
+    }
+
+    fn eval(&self, node: &Node, context: &ContextAt) -> std::result::Result<Option<Value>, String> {
+        match node {
+            Node::Literal(value) => Ok(Some(value.clone())),
+            Node::Path(path) => Ok(context.get_value(path)),
+            Node::Negate(operand) => match self.eval(operand, context)? {
+                Some(value) => number(-as_number(&value, "-")?).map(Some),
+                None => Ok(None),
+            },
+            Node::Binary(op, left, right) => {
+                let (Some(left), Some(right)) = (self.eval(left, context)?, self.eval(right, context)?) else {
+                    return Ok(None);
+                };
+                op.apply(as_number(&left, op.symbol())?, as_number(&right, op.symbol())?)
+                    .map(Some)
+            }
+            Node::Index(base, index) => {
+                let (Some(base), Some(index)) = (self.eval(base, context)?, self.eval(index, context)?) else {
+                    return Ok(None);
+                };
+                match (&base, &index) {
+                    (Value::Array(items), Value::Number(_)) => {
+                        let i = as_integer(&index, "[]")?;
+                        let i = if i < 0 { items.len() as i64 + i } else { i };
+                        Ok(usize::try_from(i).ok().and_then(|i| items.get(i)).cloned())
+                    }
+                    (Value::Object(map), Value::String(key)) => Ok(map.get(key).cloned()),
+                    _ => Err(format!("cannot index {} with {}", describe(&base), describe(&index))),
+                }
+            }
+            Node::Call(Function::Coalesce, args) => {
+                for arg in args {
+                    match self.eval(arg, context)? {
+                        Some(value) if !value.is_null() => return Ok(Some(value)),
+                        _ => {}
+                    }
+                }
+                Ok(None)
+            }
+            Node::Call(function, args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    match self.eval(arg, context)? {
+                        Some(value) => values.push(value),
+                        None => return Ok(None),
+                    }
+                }
+                self.call(*function, &values).map(Some)
+            }
+        }
+    }
+
+    fn call(&self, function: Function, args: &[Value]) -> std::result::Result<Value, String> {
+        let name = function.name();
+        let string = |i: usize| as_string(&args[i], name);
+        let num = |i: usize| as_number(&args[i], name);
+
+        match function {
+            Function::Lower => self.string(string(0)?.to_lowercase()),
+            Function::Upper => self.string(string(0)?.to_uppercase()),
+            Function::Fold => self.string(crate::text::fold(string(0)?)),
+            Function::Nfc => self.string(crate::text::nfc(string(0)?).into_owned()),
+            Function::Trim => self.string(string(0)?.trim().to_string()),
+            Function::Len => match &args[0] {
+                Value::String(s) => Ok(Value::from(s.chars().count())),
+                Value::Array(items) => Ok(Value::from(items.len())),
+                Value::Object(map) => Ok(Value::from(map.len())),
+                other => Err(type_error(name, "a string, array or object", other)),
+            },
+            Function::Concat => {
+                let parts = (0..args.len()).map(string).collect::<std::result::Result<Vec<_>, _>>()?;
+                self.check_len(parts.iter().map(|s| s.len()).sum())?;
+                Ok(Value::String(parts.concat()))
+            }
+            Function::Split => {
+                let (text, separator) = (string(0)?, string(1)?);
+                if separator.is_empty() {
+                    return Err("split() separator must not be empty".to_string());
+                }
+                let parts: Vec<Value> = text
+                    .split(separator)
+                    .take(self.max_value_len + 1)
+                    .map(|part| Value::String(part.to_string()))
+                    .collect();
+                self.check_len(parts.len())?;
+                Ok(Value::Array(parts))
+            }
+            Function::Join => {
+                let items = match &args[0] {
+                    Value::Array(items) => items,
+                    other => return Err(type_error(name, "an array", other)),
+                };
+                let separator = string(1)?;
+                let parts = items
+                    .iter()
+                    .map(|item| as_string(item, name))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                let len = parts.iter().map(|s| s.len()).sum::<usize>() + separator.len() * parts.len().saturating_sub(1);
+                self.check_len(len)?;
+                Ok(Value::String(parts.join(separator)))
+            }
+            Function::Replace => {
+                let (text, from, to) = (string(0)?, string(1)?, string(2)?);
+                if from.is_empty() {
+                    return Err("replace() pattern must not be empty".to_string());
+                }
+                let count = text.matches(from).count();
+                self.check_len(text.len() - count * from.len() + count * to.len())?;
+                Ok(Value::String(text.replace(from, to)))
+            }
+            Function::Substr => {
+                let text = string(0)?;
+                let start = usize::try_from(as_integer(&args[1], name)?)
+                    .map_err(|_| "substr() start must not be negative".to_string())?;
+                let chars = text.chars().skip(start);
+                let result: String = match args.get(2) {
+                    Some(len) => {
+                        let len = usize::try_from(as_integer(len, name)?)
+                            .map_err(|_| "substr() length must not be negative".to_string())?;
+                        chars.take(len).collect()
+                    }
+                    None => chars.collect(),
+                };
+                Ok(Value::String(result))
+            }
+            Function::Abs => number(num(0)?.abs()),
+            Function::Floor => number(num(0)?.floor()),
+            Function::Ceil => number(num(0)?.ceil()),
+            Function::Round => number(num(0)?.round()),
+            Function::Min | Function::Max => {
+                let mut result = num(0)?;
+                for i in 1..args.len() {
+                    let n = num(i)?;
+                    result = if function == Function::Min { result.min(n) } else { result.max(n) };
+                }
+                number(result)
+            }
+            Function::String => match &args[0] {
+                Value::String(s) => Ok(Value::String(s.clone())),
+                Value::Null | Value::Bool(_) | Value::Number(_) => Ok(Value::String(args[0].to_string())),
+                other => Err(type_error(name, "a scalar", other)),
+            },
+            Function::Number => match &args[0] {
+                Value::Number(_) => Ok(args[0].clone()),
+                Value::String(s) => s
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|_| format!("number() cannot parse '{}'", s))
+                    .and_then(number),
+                other => Err(type_error(name, "a number or string", other)),
+            },
+            Function::Coalesce => unreachable!("coalesce is evaluated lazily"),
+        }
+    }
+
+    fn string(&self, s: String) -> std::result::Result<Value, String> {
+        self.check_len(s.len())?;
+        Ok(Value::String(s))
+    }
+
+    fn check_len(&self, len: usize) -> std::result::Result<(), String> {
+        if len > self.max_value_len {
+            return Err(format!("value length {} exceeds the limit of {}", len, self.max_value_len));
+        }
+        Ok(())
+    }
+}
+
+/// Expressions compiled from the conditions of loaded policies.
+#[derive(Debug, Clone, Default)]
+pub struct ExpressionCache {
+    limits: ExpressionLimits,
+    compiled: HashMap<String, Expression>,
+}
+
+impl ExpressionCache {
+    /// Creates an empty cache with the given limits.
+    pub fn new(limits: ExpressionLimits) -> Self {
+        Self {
+            limits,
+            compiled: HashMap::new(),
+        }
+    }
+
+    /// Returns the limits expressions are compiled with.
+    pub fn limits(&self) -> &ExpressionLimits {
+        &self.limits
+    }
+
+    /// Compiles every expression in a policy.
+    ///
+    /// Nothing is added if any expression fails to compile.
+    pub fn add_policy(&mut self, policy: &Policy) -> Result<()> {
+        self.add_exprs(&policy.condition_exprs())
+    }
+
+    /// Compiles every expression in a policy set.
+    ///
+    /// Nothing is added if any expression fails to compile.
+    pub fn add_policy_set(&mut self, policy_set: &PolicySet) -> Result<()> {
+        self.add_exprs(&policy_set.condition_exprs())
+    }
+
+    /// Returns the compiled expression for a source text, compiling it
+    /// within the limits if it was not seen at load time.
+    pub fn get(&self, source: &str) -> Result<Cow<'_, Expression>> {
+        match self.compiled.get(source) {
+            Some(expression) => Ok(Cow::Borrowed(expression)),
+            None => match self.limits.compile(source) {
+                Ok(expression) => Ok(Cow::Owned(expression)),
+                Err(PolicyError::ValidationError(message)) => Err(PolicyError::ConditionError(message)),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    /// Returns the number of compiled expressions.
+    pub fn len(&self) -> usize {
+        self.compiled.len()
+    }
+
+    /// Returns true if no expressions have been compiled.
+    pub fn is_empty(&self) -> bool {
+        self.compiled.is_empty()
+    }
+
+    fn add_exprs(&mut self, exprs: &[&ConditionExpr]) -> Result<()> {
+        let mut compiled = Vec::new();
+        for expr in exprs {
+            for condition in expr.leaf_conditions() {
+                for source in [&condition.expr, &condition.value_expr].into_iter().flatten() {
+                    if !self.compiled.contains_key(source) {
+                        compiled.push((source.clone(), self.limits.compile(source)?));
+                    }
+                }
+            }
+        }
+        self.compiled.extend(compiled);
+        Ok(())
+    }
+}
+
+/// Validates an expression against the default limits.
+pub(crate) fn validate_expression(source: &str, owner: &str) -> Result<()> {
+    match ExpressionLimits::default().compile(source) {
+        Ok(_) => Ok(()),
+        Err(PolicyError::ValidationError(message)) => {
+            Err(PolicyError::ValidationError(format!("{} in {}", message, owner)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(Value),
+    Str(String),
+    Dot,
+    Comma,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Number(n) => write!(f, "'{}'", n),
+            Token::Str(s) => write!(f, "string '{}'", s),
+            Token::Dot => write!(f, "'.'"),
+            Token::Comma => write!(f, "','"),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::LBracket => write!(f, "'['"),
+            Token::RBracket => write!(f, "']'"),
+            Token::Plus => write!(f, "'+'"),
+            Token::Minus => write!(f, "'-'"),
+            Token::Star => write!(f, "'*'"),
+            Token::Slash => write!(f, "'/'"),
+            Token::Percent => write!(f, "'%'"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '.' => Token::Dot,
+            ',' => Token::Comma,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
+            '\'' | '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(escaped @ ('\\' | '\'' | '"')) => s.push(escaped),
+                            Some(other) => return Err(format!("unknown escape '\\{}'", other)),
+                            None => return Err("unterminated string".to_string()),
+                        },
+                        Some(other) => s.push(other),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                Token::Str(s)
+            }
+            '0'..='9' => {
+                let mut text = c.to_string();
+                let mut fraction = false;
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_digit() {
+                        text.push(next);
+                    } else if next == '.' && !fraction && text_continues_with_digit(&chars) {
+                        fraction = true;
+                        text.push(next);
+                    } else {
+                        break;
+                    }
+                    chars.next();
+                }
+                let value = match text.parse::<u64>() {
+                    Ok(n) if !fraction => Value::from(n),
+                    _ => text.parse::<f64>().map_err(|_| format!("invalid number '{}'", text)).and_then(number)?,
+                };
+                Token::Number(value)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some(&next) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                    name.push(next);
+                    chars.next();
+                }
+                Token::Ident(name)
+            }
+            other => return Err(format!("unexpected character '{}'", other)),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Returns true if the character after the next one is a digit, i.e. a `.`
+/// at the head of `chars` starts a fraction rather than a path segment.
+fn text_continues_with_digit(chars: &std::iter::Peekable<std::str::Chars<'_>>) -> bool {
+    chars.clone().nth(1).is_some_and(|c| c.is_ascii_digit())
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(Value),
+    Path(String),
+    Negate(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+    Index(Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+        }
+    }
+
+    fn apply(self, left: f64, right: f64) -> std::result::Result<Value, String> {
+        if matches!(self, BinaryOp::Div | BinaryOp::Rem) && right == 0.0 {
+            return Err("division by zero".to_string());
+        }
+        number(match self {
+            BinaryOp::Add => left + right,
+            BinaryOp::Sub => left - right,
+            BinaryOp::Mul => left * right,
+            BinaryOp::Div => left / right,
+            BinaryOp::Rem => left % right,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    Lower,
+    Upper,
+    Fold,
+    Nfc,
+    Trim,
+    Len,
+    Concat,
+    Split,
+    Join,
+    Replace,
+    Substr,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Min,
+    Max,
+    String,
+    Number,
+    Coalesce,
+}
+
+/// Accepted argument kinds and the result kind of a function. The last
+/// parameter repeats when the function is variadic; an empty kind list
+/// accepts any value.
+struct Signature {
+    params: &'static [&'static [Kind]],
+    required: usize,
+    variadic: bool,
+    result: Kind,
+}
+
+const ANY: &[Kind] = &[];
+const STRING: &[Kind] = &[Kind::String];
+const NUMBER: &[Kind] = &[Kind::Number];
+
+impl Function {
+    const ALL: [Function; 20] = [
+        Function::Lower,
+        Function::Upper,
+        Function::Fold,
+        Function::Nfc,
+        Function::Trim,
+        Function::Len,
+        Function::Concat,
+        Function::Split,
+        Function::Join,
+        Function::Replace,
+        Function::Substr,
+        Function::Abs,
+        Function::Floor,
+        Function::Ceil,
+        Function::Round,
+        Function::Min,
+        Function::Max,
+        Function::String,
+        Function::Number,
+        Function::Coalesce,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Function::Lower => "lower",
+            Function::Upper => "upper",
+            Function::Fold => "fold",
+            Function::Nfc => "nfc",
+            Function::Trim => "trim",
+            Function::Len => "len",
+            Function::Concat => "concat",
+            Function::Split => "split",
+            Function::Join => "join",
+            Function::Replace => "replace",
+            Function::Substr => "substr",
+            Function::Abs => "abs",
+            Function::Floor => "floor",
+            Function::Ceil => "ceil",
+            Function::Round => "round",
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::String => "string",
+            Function::Number => "number",
+            Function::Coalesce => "coalesce",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    fn signature(self) -> Signature {
+        let (params, required, variadic, result): (&'static [&'static [Kind]], usize, bool, Kind) = match self {
+            Function::Lower | Function::Upper | Function::Fold | Function::Nfc | Function::Trim => {
+                (&[STRING], 1, false, Kind::String)
+            }
+            Function::Len => (&[&[Kind::String, Kind::List, Kind::Object]], 1, false, Kind::Number),
+            Function::Concat => (&[STRING], 1, true, Kind::String),
+            Function::Split => (&[STRING, STRING], 2, false, Kind::List),
+            Function::Join => (&[&[Kind::List], STRING], 2, false, Kind::String),
+            Function::Replace => (&[STRING, STRING, STRING], 3, false, Kind::String),
+            Function::Substr => (&[STRING, NUMBER, NUMBER], 2, false, Kind::String),
+            Function::Abs | Function::Floor | Function::Ceil | Function::Round => (&[NUMBER], 1, false, Kind::Number),
+            Function::Min | Function::Max => (&[NUMBER], 2, true, Kind::Number),
+            Function::String => (&[&[Kind::Null, Kind::Bool, Kind::Number, Kind::String]], 1, false, Kind::String),
+            Function::Number => (&[&[Kind::Number, Kind::String]], 1, false, Kind::Number),
+            Function::Coalesce => (&[ANY], 2, true, Kind::Any),
+        };
+        Signature { params, required, variadic, result }
+    }
+}
+
+/// The statically known kind of a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Any,
+    Null,
+    Bool,
+    Number,
+    String,
+    List,
+    Object,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Any => "any value",
+            Kind::Null => "null",
+            Kind::Bool => "boolean",
+            Kind::Number => "number",
+            Kind::String => "string",
+            Kind::List => "array",
+            Kind::Object => "object",
+        }
+    }
+
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::Null => Kind::Null,
+            Value::Bool(_) => Kind::Bool,
+            Value::Number(_) => Kind::Number,
+            Value::String(_) => Kind::String,
+            Value::Array(_) => Kind::List,
+            Value::Object(_) => Kind::Object,
+        }
+    }
+
+    /// The kind of a context path, where the context schema fixes it.
+    fn of_path(path: &str) -> Self {
+        match path {
+            "role"
+            | "identity.user_id"
+            | "identity.email"
+            | "identity.email_domain"
+            | "tenant.tenant_id"
+            | "tenant.tenant_type"
+            | "resource.resource_type"
+            | "resource.resource_id"
+            | "resource.owner_id"
+            | "resource.agreement_id"
+            | "action.action_type"
+            | "action.action_name"
+            | "environment.now"
+            | "environment.timestamp"
+            | "environment.request_id"
+            | "environment.ip_address"
+            | "environment.user_agent"
+            | "agreement.id"
+            | "agreement.type"
+            | "agreement.tenant_id"
+            | "agreement.status"
+            | "agreement.created_by"
+            | "agreement.created_at"
+            | "agreement.expires_at" => Kind::String,
+            "identity.groups" | "agreement.capabilities" | "agreement.parties" | "agreement.party_ids" => Kind::List,
+            "identity.is_service" => Kind::Bool,
+            "identity" | "tenant" | "resource" | "action" | "environment" | "agreement" | "agreement.metadata" => {
+                Kind::Object
+            }
+            _ => Kind::Any,
+        }
+    }
+
+    fn accepts(allowed: &[Kind], kind: Kind) -> bool {
+        allowed.is_empty() || kind == Kind::Any || allowed.contains(&kind)
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    nodes: usize,
+    depth: usize,
+    limits: &'a ExpressionLimits,
+}
+
+type ParseResult<T> = std::result::Result<T, String>;
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset)
+    }
+
+    fn next(&mut self) -> ParseResult<Token> {
+        let token = self.peek().cloned().ok_or_else(|| "unexpected end of expression".to_string())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> ParseResult<()> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected {}, found {}", expected, token)),
+        }
+    }
+
+    fn node(&mut self, node: Node) -> ParseResult<Node> {
+        self.nodes += 1;
+        if self.nodes > self.limits.max_nodes {
+            return Err(format!("more than {} nodes", self.limits.max_nodes));
+        }
+        Ok(node)
+    }
+
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        self.depth += 1;
+        if self.depth > self.limits.max_depth {
+            return Err(format!("nested deeper than {}", self.limits.max_depth));
+        }
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// `term (('+' | '-') term)*`
+    fn expression(&mut self) -> ParseResult<Node> {
+        self.nested(|p| {
+            let mut left = p.term()?;
+            loop {
+                let op = match p.peek() {
+                    Some(Token::Plus) => BinaryOp::Add,
+                    Some(Token::Minus) => BinaryOp::Sub,
+                    _ => return Ok(left),
+                };
+                p.pos += 1;
+                let right = p.term()?;
+                left = p.node(Node::Binary(op, Box::new(left), Box::new(right)))?;
+            }
+        })
+    }
+
+    /// `unary (('*' | '/' | '%') unary)*`
+    fn term(&mut self) -> ParseResult<Node> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => BinaryOp::Mul,
+                Some(Token::Slash) => BinaryOp::Div,
+                Some(Token::Percent) => BinaryOp::Rem,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.unary()?;
+            left = self.node(Node::Binary(op, Box::new(left), Box::new(right)))?;
+        }
+    }
+
+    /// `'-' unary | postfix`
+    fn unary(&mut self) -> ParseResult<Node> {
+        if self.peek() == Some(&Token::Minus) {
+            self.pos += 1;
+            let operand = self.nested(|p| p.unary())?;
+            return self.node(Node::Negate(Box::new(operand)));
+        }
+        self.postfix()
+    }
+
+    /// `primary ('.' ident | '[' expression ']')*`
+    fn postfix(&mut self) -> ParseResult<Node> {
+        let mut base = self.primary()?;
+        loop {
+            let index = match (self.peek(), self.peek_at(1)) {
+                (Some(Token::Dot), Some(Token::Ident(key))) => {
+                    let key = Node::Literal(Value::String(key.clone()));
+                    self.pos += 2;
+                    self.node(key)?
+                }
+                (Some(Token::LBracket), _) => {
+                    self.pos += 1;
+                    let index = self.expression()?;
+                    self.expect(Token::RBracket)?;
+                    index
+                }
+                _ => return Ok(base),
+            };
+            base = self.node(Node::Index(Box::new(base), Box::new(index)))?;
+        }
+    }
+
+    /// A literal, a parenthesized expression, a call or a context path.
+    fn primary(&mut self) -> ParseResult<Node> {
+        let node = match self.next()? {
+            Token::Number(n) => Node::Literal(n),
+            Token::Str(s) => Node::Literal(Value::String(s)),
+            Token::LParen => {
+                let inner = self.expression()?;
+                self.expect(Token::RParen)?;
+                return Ok(inner);
+            }
+            Token::Ident(name) if self.peek() == Some(&Token::LParen) => {
+                let function = Function::from_name(&name).ok_or_else(|| format!("unknown function '{}'", name))?;
+                self.pos += 1;
+                let mut args = Vec::new();
+                if self.peek() != Some(&Token::RParen) {
+                    loop {
+                        args.push(self.expression()?);
+                        match self.next()? {
+                            Token::Comma => continue,
+                            Token::RParen => break,
+                            token => return Err(format!("expected ',' or ')', found {}", token)),
+                        }
+                    }
+                } else {
+                    self.pos += 1;
+                }
+                Node::Call(function, args)
+            }
+            Token::Ident(name) => match name.as_str() {
+                "true" => Node::Literal(Value::Bool(true)),
+                "false" => Node::Literal(Value::Bool(false)),
+                "null" => Node::Literal(Value::Null),
+                _ => Node::Path(self.path(name)?),
+            },
+            token => return Err(format!("unexpected {}", token)),
+        };
+        self.node(node)
+    }
+
+    /// Reads the literal segments of a context path starting at `root`.
+    /// Computed indexes are left to `postfix`.
+    fn path(&mut self, root: String) -> ParseResult<String> {
+        let mut segments = vec![PathSegment::Key(root)];
+        loop {
+            let (segment, len) = match (self.peek(), self.peek_at(1), self.peek_at(2)) {
+                (Some(Token::Dot), Some(Token::Ident(key)), _) => (PathSegment::Key(key.clone()), 2),
+                (Some(Token::Dot), Some(Token::Star), _) => (PathSegment::Wildcard, 2),
+                (Some(Token::LBracket), Some(Token::Star), Some(Token::RBracket)) => (PathSegment::Wildcard, 3),
+                (Some(Token::LBracket), Some(Token::Str(key)), Some(Token::RBracket)) => {
+                    (PathSegment::Key(key.clone()), 3)
+                }
+                (Some(Token::LBracket), Some(Token::Number(Value::Number(n))), Some(Token::RBracket))
+                    if n.is_u64() =>
+                {
+                    (PathSegment::Index(n.as_u64().unwrap_or_default() as usize), 3)
+                }
+                _ => break,
+            };
+            segments.push(segment);
+            self.pos += len;
+        }
+
+        let path = render_path(&segments);
+        if !EvaluationContext::is_known_path(&path) {
+            return Err(format!("unknown context path '{}'", path));
+        }
+        Ok(path)
+    }
+}
+
+/// Renders path segments in the syntax `EvaluationContext::get_value` reads.
+fn render_path(segments: &[PathSegment]) -> String {
+    let mut path = String::new();
+    for segment in segments {
+        match segment {
+            PathSegment::Key(key) if is_identifier(key) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+            }
+            PathSegment::Key(key) => {
+                path.push_str("[\"");
+                for c in key.chars() {
+                    if c == '"' || c == '\\' {
+                        path.push('\\');
+                    }
+                    path.push(c);
+                }
+                path.push_str("\"]");
+            }
+            PathSegment::Index(i) => path.push_str(&format!("[{}]", i)),
+            PathSegment::Wildcard => path.push_str("[*]"),
+        }
+    }
+    path
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Type-checks a node, returning the kind of value it produces.
+fn check(node: &Node) -> std::result::Result<Kind, String> {
+    match node {
+        Node::Literal(value) => Ok(Kind::of(value)),
+        Node::Path(path) => Ok(Kind::of_path(path)),
+        Node::Negate(operand) => {
+            expect_kind(check(operand)?, NUMBER, "-")?;
+            Ok(Kind::Number)
+        }
+        Node::Binary(op, left, right) => {
+            expect_kind(check(left)?, NUMBER, op.symbol())?;
+            expect_kind(check(right)?, NUMBER, op.symbol())?;
+            Ok(Kind::Number)
+        }
+        Node::Index(base, index) => {
+            let (base, index) = (check(base)?, check(index)?);
+            let valid = match base {
+                Kind::List => Kind::accepts(NUMBER, index),
+                Kind::Object => Kind::accepts(STRING, index),
+                Kind::Any => Kind::accepts(&[Kind::Number, Kind::String], index),
+                _ => false,
+            };
+            if !valid {
+                return Err(format!("cannot index {} with {}", base.name(), index.name()));
+            }
+            Ok(Kind::Any)
+        }
+        Node::Call(function, args) => {
+            let signature = function.signature();
+            let max = if signature.variadic { usize::MAX } else { signature.params.len() };
+            if args.len() < signature.required || args.len() > max {
+                return Err(format!(
+                    "{}() takes {} argument{}, got {}",
+                    function.name(),
+                    match (signature.variadic, signature.required == max) {
+                        (true, _) => format!("at least {}", signature.required),
+                        (false, true) => signature.required.to_string(),
+                        (false, false) => format!("{} to {}", signature.required, max),
+                    },
+                    if signature.required == 1 && !signature.variadic { "" } else { "s" },
+                    args.len()
+                ));
+            }
+            for (i, arg) in args.iter().enumerate() {
+                let allowed = signature.params[i.min(signature.params.len() - 1)];
+                expect_kind(check(arg)?, allowed, function.name())?;
+            }
+            Ok(signature.result)
+        }
+    }
+}
+
+fn expect_kind(kind: Kind, allowed: &[Kind], operator: &str) -> std::result::Result<(), String> {
+    if Kind::accepts(allowed, kind) {
+        return Ok(());
+    }
+    let expected: Vec<&str> = allowed.iter().map(|k| k.name()).collect();
+    Err(format!("{} expects {}, got {}", operator, expected.join(" or "), kind.name()))
+}
+
+fn describe(value: &Value) -> &'static str {
+    Kind::of(value).name()
+}
+
+fn type_error(function: &str, expected: &str, value: &Value) -> String {
+    format!("{}() expects {}, got {}", function, expected, describe(value))
+}
+
+fn as_string<'v>(value: &'v Value, function: &str) -> std::result::Result<&'v str, String> {
+    value.as_str().ok_or_else(|| type_error(function, "a string", value))
+}
+
+fn as_number(value: &Value, operator: &str) -> std::result::Result<f64, String> {
+    value
+        .as_f64()
+        .ok_or_else(|| format!("{} expects a number, got {}", operator, describe(value)))
+}
+
+fn as_integer(value: &Value, operator: &str) -> std::result::Result<i64, String> {
+    let n = as_number(value, operator)?;
+    if n.fract() != 0.0 || n.abs() > MAX_SAFE_INTEGER {
+        return Err(format!("{} expects an integer, got {}", operator, n));
+    }
+    Ok(n as i64)
+}
+
+/// The largest integer a 64-bit float represents exactly.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// Converts a computed number to JSON, as an integer when it is integral.
+fn number(n: f64) -> std::result::Result<Value, String> {
+    if !n.is_finite() {
+        return Err("result is not a finite number".to_string());
+    }
+    if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER {
+        return Ok(Value::from(n as i64));
+    }
+    Ok(Value::from(n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::*;
+
+    fn context() -> EvaluationContext {
+        EvaluationContext::new(
+            Identity {
+                user_id: "u1".to_string(),
+                email: "Ana@Example.COM".to_string(),
+                email_domain: "Example.COM".to_string(),
+                groups: vec!["eng".to_string()],
+                is_service: false,
+            },
+            Tenant {
+                tenant_id: "t1".to_string(),
+                tenant_type: TenantType::Customer,
+            },
+            Resource {
+                resource_type: ResourceType::Room,
+                resource_id: "c1".to_string(),
+                owner_id: None,
+                agreement_id: None,
+            },
+            Action {
+                action_type: ActionType::Read,
+                action_name: "messenger.conversation.read".to_string(),
+            },
+        )
+        .with_attribute("quota", serde_json::json!({ "used": 90, "limit": 100 }))
+        .with_attribute("body", serde_json::json!("héllo"))
+    }
+
+    fn eval(source: &str) -> Option<Value> {
+        ExpressionLimits::default().compile(source).unwrap().evaluate(&context()).unwrap()
+    }
+
+    #[test]
+    fn test_evaluate() {
+        use serde_json::json;
+
+        assert_eq!(eval("lower(identity.email_domain)"), Some(json!("example.com")));
+        assert_eq!(eval("fold('Straße')"), Some(json!("strasse")));
+        assert_eq!(eval("len(attributes.body)"), Some(json!(5)));
+        assert_eq!(eval("split(action.action_name, '.')[0]"), Some(json!("messenger")));
+        assert_eq!(eval("split(action.action_name, '.')[-1]"), Some(json!("read")));
+        assert_eq!(eval("attributes.quota.limit - attributes.quota.used * 2 / 3"), Some(json!(40)));
+        assert_eq!(eval("(1 + 2) * -attributes.quota.used % 7"), Some(json!(-4)));
+        assert_eq!(eval("10 / 4"), Some(json!(2.5)));
+        assert_eq!(eval("attributes['quota'].used"), Some(json!(90)));
+        assert_eq!(eval("concat(identity.user_id, '@', tenant.tenant_id)"), Some(json!("u1@t1")));
+        assert_eq!(eval("max(1, attributes.quota.used, 3)"), Some(json!(90)));
+        assert_eq!(eval("number('1.5') + 1"), Some(json!(2.5)));
+        assert_eq!(eval("string(attributes.quota.used)"), Some(json!("90")));
+        assert_eq!(eval("substr(action.action_name, 10, 4)"), Some(json!("conv")));
+
+        assert_eq!(eval("lower(attributes.missing)"), None);
+        assert_eq!(eval("split(action.action_name, '.')[9]"), None);
+        assert_eq!(eval("coalesce(attributes.missing, 'default')"), Some(json!("default")));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let limits = ExpressionLimits::default();
+        for source in [
+            "lower(",
+            "lower(1)",
+            "len()",
+            "unknown(identity.user_id)",
+            "identity.user_id + 1",
+            "bogus.path",
+            "split(action.action_name)",
+            "'a' 'b'",
+            "split(identity.user_id, '.')['x']",
+        ] {
+            assert!(limits.compile(source).is_err(), "{} should not compile", source);
+        }
+
+        assert!(limits.compile(&format!("{}1{}", "(".repeat(20), ")".repeat(20))).is_err());
+        let tight = ExpressionLimits {
+            max_nodes: 4,
+            ..ExpressionLimits::default()
+        };
+        assert!(tight.compile("1 + 2 + 3").is_err());
+    }
+
+    #[test]
+    fn test_runtime_errors_and_bounds() {
+        let context = context();
+        let limits = ExpressionLimits {
+            max_value_len: 16,
+            ..ExpressionLimits::default()
+        };
+
+        for source in [
+            "1 / 0",
+            "number(attributes.body)",
+            "attributes.body * 2",
+            "replace(action.action_name, '.', '................')",
+            "concat(action.action_name, action.action_name)",
+        ] {
+            let expression = limits.compile(source).unwrap();
+            assert!(expression.evaluate(&context).is_err(), "{} should fail", source);
+        }
+    }
+}
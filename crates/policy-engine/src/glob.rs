@@ -0,0 +1,323 @@
+//! Glob patterns for the `glob` operator.
+//!
+//! Patterns match identifiers made of segments separated by `.` or `:`,
+//! such as action names (`office.docs.read`) and resource IDs
+//! (`r:project-42`):
+//!
+//! - `*` matches any run of characters within one segment;
+//! - `**` matches any run of characters, separators included. As a whole
+//!   segment followed by a separator (`office.**.read`) it also matches zero
+//!   segments, so that pattern matches `office.read`;
+//! - every other character matches itself.
+//!
+//! Matching runs in time proportional to the pattern length times the input
+//! length, with no backtracking, so unlike `matches` there is no pattern
+//! that is expensive to evaluate. Literal patterns are compiled once, when
+//! a policy is loaded (see `GlobCache`).
+
+use crate::error::{PolicyError, Result};
+use crate::policy::{Policy, PolicySet};
+use crate::text;
+use crate::types::{ConditionExpr, ConditionOperator};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Characters that separate identifier segments.
+const SEPARATORS: [u8; 2] = [b'.', b':'];
+
+fn is_separator(byte: u8) -> bool {
+    SEPARATORS.contains(&byte)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(String),
+    /// `*`: characters up to the next separator.
+    Star,
+    /// `**`: any characters.
+    AnyDepth,
+    /// `**` plus its trailing separator, as a whole segment: zero or more
+    /// complete segments.
+    AnySegments,
+}
+
+/// A compiled glob pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    /// Compiles a pattern.
+    pub fn new(pattern: &str) -> Result<Self> {
+        if pattern.is_empty() {
+            return Err(PolicyError::ValidationError("Glob pattern must not be empty".to_string()));
+        }
+
+        let bytes = pattern.as_bytes();
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] != b'*' {
+                let next = pattern[i..].chars().next().map_or(1, char::len_utf8);
+                literal.push_str(&pattern[i..i + next]);
+                i += next;
+                continue;
+            }
+
+            let stars = bytes[i..].iter().take_while(|&&b| b == b'*').count();
+            if stars > 2 {
+                return Err(PolicyError::ValidationError(format!(
+                    "Invalid glob pattern '{}': use at most two consecutive '*'",
+                    pattern
+                )));
+            }
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+            }
+
+            let whole_segment = (i == 0 || is_separator(bytes[i - 1]))
+                && bytes.get(i + stars).copied().is_some_and(is_separator);
+            match stars {
+                1 => {
+                    tokens.push(Token::Star);
+                    i += 1;
+                }
+                _ if whole_segment => {
+                    tokens.push(Token::AnySegments);
+                    i += 3;
+                }
+                _ => {
+                    tokens.push(Token::AnyDepth);
+                    i += 2;
+                }
+            }
+        }
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+
+        Ok(Self { tokens })
+    }
+
+    /// Returns true if the pattern matches the whole of `text`.
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.as_bytes();
+        let n = text.len();
+
+        // reachable[i]: the tokens so far can match exactly text[..i].
+        let mut reachable = vec![false; n + 1];
+        reachable[0] = true;
+        for token in &self.tokens {
+            let mut next = vec![false; n + 1];
+            match token {
+                Token::Literal(literal) => {
+                    let literal = literal.as_bytes();
+                    for i in 0..=n {
+                        if reachable[i] && text[i..].starts_with(literal) {
+                            next[i + literal.len()] = true;
+                        }
+                    }
+                }
+                Token::Star => {
+                    let mut open = false;
+                    for i in 0..=n {
+                        open |= reachable[i];
+                        next[i] = open;
+                        if i < n && is_separator(text[i]) {
+                            open = false;
+                        }
+                    }
+                }
+                Token::AnyDepth => {
+                    let mut open = false;
+                    for i in 0..=n {
+                        open |= reachable[i];
+                        next[i] = open;
+                    }
+                }
+                Token::AnySegments => {
+                    let mut open = false;
+                    for i in 0..=n {
+                        open |= reachable[i];
+                        next[i] = reachable[i] || (open && i > 0 && is_separator(text[i - 1]));
+                    }
+                }
+            }
+            if !next.contains(&true) {
+                return false;
+            }
+            reachable = next;
+        }
+        reachable[n]
+    }
+
+    /// Returns the literal text every match starts with.
+    pub fn literal_prefix(&self) -> &str {
+        match self.tokens.first() {
+            Some(Token::Literal(literal)) => literal,
+            _ => "",
+        }
+    }
+
+    /// Returns true if the pattern has no wildcards.
+    pub fn is_literal(&self) -> bool {
+        matches!(self.tokens.as_slice(), [Token::Literal(_)])
+    }
+}
+
+/// Globs compiled from the `glob` conditions of loaded policies.
+#[derive(Debug, Clone, Default)]
+pub struct GlobCache {
+    compiled: HashMap<String, Glob>,
+}
+
+impl GlobCache {
+    /// Compiles every literal `glob` pattern in a policy.
+    ///
+    /// Nothing is added if any pattern fails to compile.
+    pub fn add_policy(&mut self, policy: &Policy) -> Result<()> {
+        self.add_exprs(&policy.condition_exprs())
+    }
+
+    /// Compiles every literal `glob` pattern in a policy set.
+    ///
+    /// Nothing is added if any pattern fails to compile.
+    pub fn add_policy_set(&mut self, policy_set: &PolicySet) -> Result<()> {
+        self.add_exprs(&policy_set.condition_exprs())
+    }
+
+    /// Returns the glob for a pattern, compiling it if it was not seen at
+    /// load time (e.g. a pattern read from the context).
+    pub fn get(&self, pattern: &str) -> Result<Cow<'_, Glob>> {
+        match self.compiled.get(pattern) {
+            Some(glob) => Ok(Cow::Borrowed(glob)),
+            None => match Glob::new(pattern) {
+                Ok(glob) => Ok(Cow::Owned(glob)),
+                Err(PolicyError::ValidationError(message)) => Err(PolicyError::ConditionError(message)),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    /// Returns the number of compiled patterns.
+    pub fn len(&self) -> usize {
+        self.compiled.len()
+    }
+
+    /// Returns true if no patterns have been compiled.
+    pub fn is_empty(&self) -> bool {
+        self.compiled.is_empty()
+    }
+
+    fn add_exprs(&mut self, exprs: &[&ConditionExpr]) -> Result<()> {
+        let mut compiled = Vec::new();
+        for expr in exprs {
+            for condition in expr.leaf_conditions() {
+                if condition.operator != ConditionOperator::Glob || !condition.has_literal_value() {
+                    continue;
+                }
+                let pattern = condition.value.as_str().ok_or_else(|| {
+                    PolicyError::ValidationError("The glob operator requires a string pattern".to_string())
+                })?;
+                let mut patterns = vec![pattern.to_string()];
+                // Evaluators that normalize Unicode look patterns up in NFC.
+                if let Cow::Owned(normalized) = text::nfc(pattern) {
+                    patterns.push(normalized);
+                }
+                for pattern in patterns {
+                    if !self.compiled.contains_key(&pattern) {
+                        let glob = Glob::new(&pattern)?;
+                        compiled.push((pattern, glob));
+                    }
+                }
+            }
+        }
+        self.compiled.extend(compiled);
+        Ok(())
+    }
+}
+
+/// Validates the literal operand of a `glob` condition.
+pub(crate) fn validate_operand(value: &serde_json::Value, owner: &str) -> Result<()> {
+    let pattern = value.as_str().ok_or_else(|| {
+        PolicyError::ValidationError(format!("The glob operator requires a string pattern in {}", owner))
+    })?;
+    match Glob::new(pattern) {
+        Ok(_) => Ok(()),
+        Err(PolicyError::ValidationError(message)) => {
+            Err(PolicyError::ValidationError(format!("{} in {}", message, owner)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str) -> Glob {
+        Glob::new(pattern).unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(glob("office.*.read").matches("office.docs.read"));
+        assert!(!glob("office.*.read").matches("office.docs.v2.read"));
+        assert!(!glob("office.*.read").matches("office.read"));
+        assert!(glob("office.**.read").matches("office.docs.v2.read"));
+        assert!(glob("office.**.read").matches("office.read"));
+        assert!(!glob("office.**.read").matches("office.docsread"));
+        assert!(glob("messenger.**").matches("messenger.conversation.send"));
+        assert!(!glob("messenger.**").matches("messengers.send"));
+        assert!(glob("r:project-*").matches("r:project-42"));
+        assert!(!glob("r:project-*").matches("r:project-42:files"));
+        assert!(glob("r:*:files").matches("r:project-42:files"));
+        assert!(glob("*").matches(""));
+        assert!(glob("a**b").matches("a.x:y.b"));
+        assert!(glob("**.read").matches("read"));
+        assert!(glob("**").matches("anything.at:all"));
+        assert!(glob("exact.name").matches("exact.name"));
+        assert!(!glob("exact.name").matches("exact.names"));
+        assert!(glob("ünï*.cödé").matches("ünïcode.cödé"));
+    }
+
+    #[test]
+    fn test_cache() {
+        let policy = Policy::from_yaml(
+            r#"
+id: glob-policy
+version: "1.0.0"
+name: Glob Policy
+rules:
+  - id: projects
+    effect: allow
+    conditions:
+      - field: resource.resource_id
+        operator: glob
+        value: "r:project-*"
+    priority: 10
+"#,
+        )
+        .unwrap();
+
+        let mut cache = GlobCache::default();
+        cache.add_policy(&policy).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(matches!(cache.get("r:project-*").unwrap(), Cow::Borrowed(_)));
+        assert!(matches!(cache.get("r:*").unwrap(), Cow::Owned(_)));
+        assert!(matches!(cache.get("a.***"), Err(PolicyError::ConditionError(_))));
+    }
+
+    #[test]
+    fn test_compile() {
+        assert!(Glob::new("").is_err());
+        assert!(Glob::new("a.***").is_err());
+
+        assert_eq!(glob("office.*.read").literal_prefix(), "office.");
+        assert_eq!(glob("**.read").literal_prefix(), "");
+        assert!(glob("office.read").is_literal());
+        assert!(!glob("office.*").is_literal());
+    }
+}
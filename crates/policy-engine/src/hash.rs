@@ -1,6 +1,5 @@
 //! Hashing utilities for the policy engine.
 
-use crate::error::{PolicyError, Result};
 use sha2::{Digest, Sha256};
 
 /// Computes SHA-256 hash of data and returns hex string.
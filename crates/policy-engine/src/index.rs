@@ -0,0 +1,515 @@
+//! Rule indexing for high-throughput evaluation.
+//!
+//! A `RuleIndex` is compiled once per policy when it is loaded. It keeps the
+//! rules in evaluation (priority) order and, for each of resource type,
+//! action type, action name and role, records which rules can possibly apply
+//! to a given request value. At evaluation time the candidate rules are the
+//! intersection of those sets, so rules that are certainly not applicable are
+//! never visited.
+//!
+//! Keys come from rule targets and from top-level guard conditions such as
+//! `resource.resource_type equals room`; a `glob` guard on the action name
+//! is indexed by its literal prefix. The candidate set is always a
+//! superset of the applicable rules: every candidate is still evaluated in
+//! full, so decisions are identical to evaluating every rule.
+//!
+//! Keys are indexed both as written and in NFC, so that an evaluator that
+//! NFC-normalizes the strings it compares can look up the request's values
+//! in NFC too (see `PolicyEvaluator::normalize_unicode`).
+
+use crate::context::EvaluationContext;
+use crate::glob::Glob;
+use crate::policy::{Policy, PolicySet};
+use crate::text;
+use crate::types::{Condition, ConditionExpr, ConditionOperator, Rule};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// A fixed-size set of rule positions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    fn insert(&mut self, position: usize) {
+        self.words[position / 64] |= 1 << (position % 64);
+    }
+
+    fn union_with(&mut self, other: &BitSet) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    fn intersect_with(&mut self, other: &BitSet) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= other;
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(i * 64 + bit)
+            })
+        })
+    }
+}
+
+/// How a rule constrains one indexed attribute.
+enum Constraint {
+    /// The rule may apply to any value.
+    Any,
+    /// The rule only applies to these values. `strict` constraints (from
+    /// targets) also exclude requests where the attribute is missing;
+    /// constraints from conditions do not, since a missing attribute makes
+    /// the condition indeterminate rather than false.
+    OneOf { values: Vec<String>, strict: bool },
+}
+
+/// Candidate sets for one indexed attribute.
+#[derive(Debug, Clone)]
+struct Dimension {
+    keyed: HashMap<String, BitSet>,
+    /// Rules that apply regardless of the value.
+    any: BitSet,
+    /// Rules still possible when the attribute is missing.
+    if_missing: BitSet,
+}
+
+impl Dimension {
+    fn new(len: usize) -> Self {
+        Self {
+            keyed: HashMap::new(),
+            any: BitSet::new(len),
+            if_missing: BitSet::new(len),
+        }
+    }
+
+    fn add(&mut self, position: usize, constraint: Constraint, len: usize) {
+        match constraint {
+            Constraint::Any => {
+                self.any.insert(position);
+                self.if_missing.insert(position);
+            }
+            Constraint::OneOf { values, strict } => {
+                for value in values.iter().flat_map(|value| key_forms(value)) {
+                    self.keyed
+                        .entry(value)
+                        .or_insert_with(|| BitSet::new(len))
+                        .insert(position);
+                }
+                if !strict {
+                    self.if_missing.insert(position);
+                }
+            }
+        }
+    }
+
+    /// Returns the rules that can apply when the attribute has any of `keys`.
+    fn candidates<'a>(&self, keys: impl Iterator<Item = &'a str>) -> BitSet {
+        let mut set = self.any.clone();
+        for key in keys {
+            if let Some(bits) = self.keyed.get(key) {
+                set.union_with(bits);
+            }
+        }
+        set
+    }
+}
+
+/// The compiled index for a single policy.
+#[derive(Debug, Clone)]
+pub struct RuleIndex {
+    /// Rule indices in evaluation order (priority descending, stable).
+    order: Vec<usize>,
+    resource_types: Dimension,
+    action_types: Dimension,
+    /// Exact action names.
+    action_names: Dimension,
+    /// Action name prefixes (from `prefix*` patterns, `starts_with` and
+    /// `glob`).
+    action_prefixes: HashMap<String, BitSet>,
+    roles: Dimension,
+}
+
+impl RuleIndex {
+    /// Compiles the index for a policy.
+    pub fn build(policy: &Policy) -> Self {
+        let mut order: Vec<usize> = (0..policy.rules.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(policy.rules[i].priority));
+
+        let len = order.len();
+        let mut index = Self {
+            order,
+            resource_types: Dimension::new(len),
+            action_types: Dimension::new(len),
+            action_names: Dimension::new(len),
+            action_prefixes: HashMap::new(),
+            roles: Dimension::new(len),
+        };
+
+        for position in 0..len {
+            let rule = &policy.rules[index.order[position]];
+            index.resource_types.add(position, resource_type_constraint(rule), len);
+            index.action_types.add(position, action_type_constraint(rule), len);
+            index.roles.add(position, role_constraint(rule), len);
+
+            match action_name_constraint(rule) {
+                None => index.action_names.add(position, Constraint::Any, len),
+                Some(patterns) => {
+                    for pattern in patterns {
+                        match pattern {
+                            NamePattern::Exact(name) => index.action_names.add(
+                                position,
+                                Constraint::OneOf { values: vec![name], strict: true },
+                                len,
+                            ),
+                            NamePattern::Prefix(prefix) => {
+                                for prefix in key_forms(&prefix) {
+                                    index
+                                        .action_prefixes
+                                        .entry(prefix)
+                                        .or_insert_with(|| BitSet::new(len))
+                                        .insert(position);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        index
+    }
+
+    /// Returns the number of indexed rules.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Returns true if the policy has no rules.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Returns the rules that may apply to the request, in evaluation order.
+    ///
+    /// With `normalize`, the request's values are also looked up in NFC.
+    pub fn candidates<'p>(&self, policy: &'p Policy, context: &EvaluationContext, normalize: bool) -> Vec<&'p Rule> {
+        // The NFC form of a value, when normalizing and it differs.
+        let normalized = |value: &str| match text::nfc(value) {
+            Cow::Owned(normalized) if normalize => Some(normalized),
+            _ => None,
+        };
+        let resource_type = context.resource.resource_type.as_str();
+        let resource_type_nfc = normalized(resource_type);
+        let action_type = context.action.action_type.as_str();
+        let action_type_nfc = normalized(action_type);
+        let action_name = context.action.action_name.as_str();
+        let action_name_nfc = normalized(action_name);
+
+        let mut set = self
+            .resource_types
+            .candidates(std::iter::once(resource_type).chain(resource_type_nfc.as_deref()));
+        set.intersect_with(
+            &self
+                .action_types
+                .candidates(std::iter::once(action_type).chain(action_type_nfc.as_deref())),
+        );
+
+        let mut names = self
+            .action_names
+            .candidates(std::iter::once(action_name).chain(action_name_nfc.as_deref()));
+        for action_name in std::iter::once(action_name).chain(action_name_nfc.as_deref()) {
+            for (end, _) in action_name.char_indices().chain(std::iter::once((action_name.len(), ' '))) {
+                if let Some(bits) = self.action_prefixes.get(&action_name[..end]) {
+                    names.union_with(bits);
+                }
+            }
+        }
+        set.intersect_with(&names);
+
+        match &context.role {
+            Some(role) => {
+                let role_nfc = normalized(role.as_str());
+                set.intersect_with(&self.roles.candidates(std::iter::once(role.as_str()).chain(role_nfc.as_deref())))
+            }
+            None => set.intersect_with(&self.roles.if_missing),
+        }
+
+        set.iter().map(|position| &policy.rules[self.order[position]]).collect()
+    }
+}
+
+/// Compiled indexes for the policies of a policy set, mirroring its shape.
+#[derive(Debug, Clone)]
+pub struct PolicySetIndex {
+    pub policies: Vec<RuleIndex>,
+    pub policy_sets: Vec<PolicySetIndex>,
+}
+
+impl PolicySetIndex {
+    /// Compiles indexes for every policy in the set.
+    pub fn build(policy_set: &PolicySet) -> Self {
+        Self {
+            policies: policy_set.policies.iter().map(RuleIndex::build).collect(),
+            policy_sets: policy_set.policy_sets.iter().map(PolicySetIndex::build).collect(),
+        }
+    }
+}
+
+/// Returns a key as written and, if different, in NFC.
+fn key_forms(key: &str) -> Vec<String> {
+    match text::nfc(key) {
+        Cow::Borrowed(_) => vec![key.to_string()],
+        Cow::Owned(normalized) => vec![key.to_string(), normalized],
+    }
+}
+
+/// A pattern an action name must match.
+enum NamePattern {
+    Exact(String),
+    Prefix(String),
+}
+
+/// Returns the top-level guard conditions of a rule on `field` (the leaves
+/// its implicit conjunction requires), comparing against literal values.
+fn guards<'a>(rule: &'a Rule, field: &'a str) -> impl Iterator<Item = &'a Condition> {
+    rule.conditions.iter().filter_map(move |expr| match expr {
+        ConditionExpr::Condition(c) if c.field == field && c.has_literal_value() => Some(c),
+        _ => None,
+    })
+}
+
+/// Extracts the string values an `equals`/`in` guard accepts.
+fn accepted_values(condition: &Condition) -> Option<Vec<String>> {
+    match condition.operator {
+        ConditionOperator::Equals => condition.value.as_str().map(|v| vec![v.to_string()]),
+        ConditionOperator::In => condition
+            .value
+            .as_array()?
+            .iter()
+            .map(|v| v.as_str().map(str::to_string))
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Builds a constraint from the first target list or guard condition found.
+fn constraint_from(target_values: Option<Vec<String>>, rule: &Rule, field: &str) -> Constraint {
+    if let Some(values) = target_values.filter(|v| !v.is_empty()) {
+        return Constraint::OneOf { values, strict: true };
+    }
+    guards(rule, field)
+        .find_map(accepted_values)
+        .map(|values| Constraint::OneOf { values, strict: false })
+        .unwrap_or(Constraint::Any)
+}
+
+fn resource_type_constraint(rule: &Rule) -> Constraint {
+    let target = rule
+        .target
+        .as_ref()
+        .map(|t| t.resource_types.iter().map(|r| r.as_str().to_string()).collect());
+    constraint_from(target, rule, "resource.resource_type")
+}
+
+fn action_type_constraint(rule: &Rule) -> Constraint {
+    let target = rule
+        .target
+        .as_ref()
+        .map(|t| t.action_types.iter().map(|a| a.as_str().to_string()).collect());
+    constraint_from(target, rule, "action.action_type")
+}
+
+fn role_constraint(rule: &Rule) -> Constraint {
+    let target = rule
+        .target
+        .as_ref()
+        .map(|t| t.roles.iter().map(|r| r.as_str().to_string()).collect());
+    constraint_from(target, rule, "role")
+}
+
+/// Returns the action name patterns a rule requires, or `None` if it may
+/// apply to any action name.
+fn action_name_constraint(rule: &Rule) -> Option<Vec<NamePattern>> {
+    if let Some(target) = rule.target.as_ref().filter(|t| !t.action_names.is_empty()) {
+        return Some(
+            target
+                .action_names
+                .iter()
+                .map(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => NamePattern::Prefix(prefix.to_string()),
+                    None => NamePattern::Exact(pattern.clone()),
+                })
+                .collect(),
+        );
+    }
+
+    guards(rule, "action.action_name").find_map(|condition| match condition.operator {
+        ConditionOperator::StartsWith => condition
+            .value
+            .as_str()
+            .map(|prefix| vec![NamePattern::Prefix(prefix.to_string())]),
+        ConditionOperator::Glob => {
+            let glob = Glob::new(condition.value.as_str()?).ok()?;
+            let prefix = glob.literal_prefix().to_string();
+            Some(vec![if glob.is_literal() { NamePattern::Exact(prefix) } else { NamePattern::Prefix(prefix) }])
+        }
+        _ => accepted_values(condition).map(|names| names.into_iter().map(NamePattern::Exact).collect()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Action, ActionType, Identity, Resource, ResourceType, Role, Tenant, TenantType};
+
+    fn create_test_context(role: Option<Role>, action_name: &str) -> EvaluationContext {
+        let mut ctx = EvaluationContext::new(
+            Identity {
+                user_id: "u:test".to_string(),
+                email: "test@example.com".to_string(),
+                email_domain: "example.com".to_string(),
+                groups: vec![],
+                is_service: false,
+            },
+            Tenant {
+                tenant_id: "t:example.com".to_string(),
+                tenant_type: TenantType::Customer,
+            },
+            Resource {
+                resource_type: ResourceType::Tool,
+                resource_id: "tool:1".to_string(),
+                owner_id: None,
+                agreement_id: None,
+            },
+            Action {
+                action_type: ActionType::Execute,
+                action_name: action_name.to_string(),
+            },
+        );
+        ctx.role = role;
+        ctx
+    }
+
+    #[test]
+    fn test_bitset_iter() {
+        let mut set = BitSet::new(130);
+        set.insert(0);
+        set.insert(64);
+        set.insert(129);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![0, 64, 129]);
+    }
+
+    #[test]
+    fn test_candidates() {
+        let policy = Policy::from_yaml(
+            r#"
+id: tools
+version: "1.0.0"
+name: Tools
+rules:
+  - id: messenger
+    effect: allow
+    target:
+      resource_types: [tool]
+      action_names: ["messenger.*"]
+    conditions: []
+    priority: 10
+  - id: office-by-condition
+    effect: allow
+    conditions:
+      - field: action.action_name
+        operator: starts_with
+        value: office.
+      - field: role
+        operator: in
+        value: [member, admin]
+    priority: 20
+  - id: rooms-only
+    effect: allow
+    conditions:
+      - field: resource.resource_type
+        operator: equals
+        value: room
+    priority: 30
+  - id: everything
+    effect: deny
+    conditions: []
+    priority: 0
+"#,
+        )
+        .unwrap();
+        let index = RuleIndex::build(&policy);
+        assert_eq!(index.len(), 4);
+
+        let ids = |ctx: &EvaluationContext| -> Vec<String> {
+            index.candidates(&policy, ctx, false).iter().map(|r| r.id.clone()).collect()
+        };
+
+        assert_eq!(ids(&create_test_context(Some(Role::Member), "messenger.send")), vec!["messenger", "everything"]);
+        assert_eq!(ids(&create_test_context(Some(Role::Member), "office.llm.complete")), vec!["office-by-condition", "everything"]);
+        assert_eq!(ids(&create_test_context(Some(Role::Guest), "office.llm.complete")), vec!["everything"]);
+
+        // A missing role makes the role guard indeterminate, so the rule stays a candidate
+        assert_eq!(ids(&create_test_context(None, "office.llm.complete")), vec!["office-by-condition", "everything"]);
+    }
+
+    #[test]
+    fn test_glob_guards() {
+        let policy = Policy::from_yaml(
+            r#"
+id: globs
+version: "1.0.0"
+name: Globs
+rules:
+  - id: office-reads
+    effect: allow
+    conditions:
+      - field: action.action_name
+        operator: glob
+        value: office.*.read
+    priority: 10
+  - id: exact
+    effect: allow
+    conditions:
+      - field: action.action_name
+        operator: glob
+        value: messenger.send
+    priority: 10
+  - id: any-read
+    effect: allow
+    conditions:
+      - field: action.action_name
+        operator: glob
+        value: "**.read"
+    priority: 10
+"#,
+        )
+        .unwrap();
+        let index = RuleIndex::build(&policy);
+        let ids = |name: &str| -> Vec<String> {
+            let ctx = create_test_context(Some(Role::Member), name);
+            index.candidates(&policy, &ctx, false).iter().map(|r| r.id.clone()).collect()
+        };
+
+        assert_eq!(ids("office.docs.read"), vec!["office-reads", "any-read"]);
+        assert_eq!(ids("messenger.send"), vec!["exact", "any-read"]);
+        assert_eq!(ids("messenger.sender"), vec!["any-read"]);
+    }
+}
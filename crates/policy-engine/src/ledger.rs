@@ -0,0 +1,391 @@
+//! Ledger chain verification.
+//!
+//! A ledger shard is exported as NDJSON, one entry per line, either as a
+//! record `{"seq": 1, "head_hash": "h:...", "atom": {...}}` or as a bare
+//! atom (whose seq is then taken to follow the previous one). Starting from
+//! `GENESIS_HASH` at seq 1, every entry must satisfy:
+//!
+//! - `seq` is one more than the previous entry's;
+//! - the atom's `kind` is `action.v1` or `effect.v1`;
+//! - its `cid` is the atom's CID as `crate::atom::compute_value_cid`
+//!   computes it, which leaves out an action's `prev_hash` as the Worker's
+//!   `computeCID` does (the head hash covers the chain position instead);
+//! - an action's `prev_hash` is the head before it;
+//! - an effect's `ref_action_cid` is the CID of an earlier action;
+//! - the new head, `compute_head_hash(head, cid)`, equals the record's
+//!   `head_hash`, if it has one.
+//!
+//! Verification stops at the first entry that does not, reporting it as a
+//! `Divergence`. `conformance/worker-ledger.ndjson` is an export produced
+//! by the Worker's `LedgerShardObject`, and must verify with the `Jcs`
+//! rules.
+
+use crate::atom::{compute_value_cid, ACTION_KIND, EFFECT_KIND};
+use crate::canonicalization::CanonicalizationMode;
+use crate::hash;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use thiserror::Error;
+
+/// The first point at which a ledger export diverges from a valid chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Error)]
+#[error("Ledger diverges at line {line}: {kind}")]
+pub struct Divergence {
+    /// The 1-based line of the entry.
+    pub line: usize,
+
+    /// The entry's seq, if it could be read.
+    pub seq: Option<u64>,
+
+    pub kind: DivergenceKind,
+}
+
+/// How an entry diverges.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Error)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DivergenceKind {
+    #[error("malformed entry: {message}")]
+    Malformed { message: String },
+
+    #[error("expected seq {expected}, found {actual}")]
+    SeqGap { expected: u64, actual: u64 },
+
+    #[error("unknown atom kind '{kind}'")]
+    UnknownKind { kind: String },
+
+    #[error("cid is {actual}, computed {expected}")]
+    CidMismatch { expected: String, actual: String },
+
+    #[error("prev_hash is {actual}, expected head {expected}")]
+    PrevHashMismatch { expected: String, actual: String },
+
+    #[error("head_hash is {actual}, computed {expected}")]
+    HeadHashMismatch { expected: String, actual: String },
+
+    #[error("ref_action_cid {ref_action_cid} is not an earlier action")]
+    DanglingEffect { ref_action_cid: String },
+}
+
+/// The state of a verified chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainSummary {
+    /// The seq of the last entry, or 0 for an empty ledger.
+    pub seq: u64,
+    pub head_hash: String,
+    pub actions: usize,
+    pub effects: usize,
+}
+
+/// Verifies a ledger export entry by entry.
+#[derive(Debug, Clone)]
+pub struct ChainVerifier {
+    mode: CanonicalizationMode,
+    line: usize,
+    summary: ChainSummary,
+    action_cids: HashSet<String>,
+}
+
+impl Default for ChainVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChainVerifier {
+    /// Creates a verifier at the genesis head, using the `Jcs` rules the
+    /// Workers hash with.
+    pub fn new() -> Self {
+        Self {
+            mode: CanonicalizationMode::Jcs,
+            line: 0,
+            summary: ChainSummary {
+                seq: 0,
+                head_hash: hash::GENESIS_HASH.to_string(),
+                actions: 0,
+                effects: 0,
+            },
+            action_cids: HashSet::new(),
+        }
+    }
+
+    /// Sets the canonicalization rules CIDs were computed with.
+    pub fn with_mode(mut self, mode: CanonicalizationMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Verifies the next NDJSON line. Blank lines are skipped.
+    pub fn push_line(&mut self, line: &str) -> std::result::Result<(), Divergence> {
+        self.line += 1;
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+
+        let entry = crate::strict_json::parse(line, self.mode).map_err(|e| self.divergence(None, malformed(e)))?;
+        let Value::Object(mut record) = entry else {
+            return Err(self.divergence(None, malformed("entry is not an object")));
+        };
+        if !record.contains_key("atom") {
+            let seq = self.summary.seq + 1;
+            return self.push(seq, &Value::Object(record), None);
+        }
+
+        let seq = record.get("seq").and_then(Value::as_u64);
+        let seq = seq.ok_or_else(|| self.divergence(None, malformed("seq must be a non-negative integer")))?;
+        let head_hash = match record.remove("head_hash") {
+            None => None,
+            Some(Value::String(head_hash)) => Some(head_hash),
+            Some(_) => return Err(self.divergence(Some(seq), malformed("head_hash must be a string"))),
+        };
+        let atom = record.remove("atom").unwrap_or_default();
+        self.push(seq, &atom, head_hash.as_deref())
+    }
+
+    /// Verifies the next atom.
+    pub fn push(&mut self, seq: u64, atom: &Value, head_hash: Option<&str>) -> std::result::Result<(), Divergence> {
+        let fail = |kind| Err(self.divergence(Some(seq), kind));
+
+        if seq != self.summary.seq + 1 {
+            return fail(DivergenceKind::SeqGap {
+                expected: self.summary.seq + 1,
+                actual: seq,
+            });
+        }
+        if !atom.is_object() {
+            return fail(malformed("atom is not an object"));
+        }
+        let field = |name: &str| atom.get(name).and_then(Value::as_str);
+
+        let Some(cid) = field("cid") else {
+            return fail(malformed("atom has no cid"));
+        };
+        let expected_cid = match compute_value_cid(atom, self.mode) {
+            Ok(cid) => cid,
+            Err(e) => return fail(malformed(e)),
+        };
+        if cid != expected_cid {
+            return fail(DivergenceKind::CidMismatch {
+                expected: expected_cid,
+                actual: cid.to_string(),
+            });
+        }
+
+        let is_action = match field("kind") {
+            Some(ACTION_KIND) => {
+                let prev_hash = field("prev_hash").unwrap_or_default();
+                if prev_hash != self.summary.head_hash {
+                    return fail(DivergenceKind::PrevHashMismatch {
+                        expected: self.summary.head_hash.clone(),
+                        actual: prev_hash.to_string(),
+                    });
+                }
+                true
+            }
+            Some(EFFECT_KIND) => {
+                let ref_action_cid = field("ref_action_cid").unwrap_or_default();
+                if !self.action_cids.contains(ref_action_cid) {
+                    return fail(DivergenceKind::DanglingEffect {
+                        ref_action_cid: ref_action_cid.to_string(),
+                    });
+                }
+                false
+            }
+            kind => {
+                return fail(DivergenceKind::UnknownKind {
+                    kind: kind.unwrap_or_default().to_string(),
+                })
+            }
+        };
+
+        let expected_head = hash::compute_head_hash(&self.summary.head_hash, cid);
+        if let Some(head_hash) = head_hash.filter(|head_hash| *head_hash != expected_head) {
+            return fail(DivergenceKind::HeadHashMismatch {
+                expected: expected_head,
+                actual: head_hash.to_string(),
+            });
+        }
+
+        if is_action {
+            self.action_cids.insert(cid.to_string());
+            self.summary.actions += 1;
+        } else {
+            self.summary.effects += 1;
+        }
+        self.summary.seq = seq;
+        self.summary.head_hash = expected_head;
+        Ok(())
+    }
+
+    /// Returns the state of the chain verified so far.
+    pub fn summary(&self) -> &ChainSummary {
+        &self.summary
+    }
+
+    fn divergence(&self, seq: Option<u64>, kind: DivergenceKind) -> Divergence {
+        Divergence {
+            line: self.line,
+            seq,
+            kind,
+        }
+    }
+}
+
+fn malformed(message: impl ToString) -> DivergenceKind {
+    DivergenceKind::Malformed {
+        message: message.to_string(),
+    }
+}
+
+/// Verifies a complete NDJSON ledger export.
+pub fn verify_ndjson(ndjson: &str, mode: CanonicalizationMode) -> std::result::Result<ChainSummary, Divergence> {
+    let mut verifier = ChainVerifier::new().with_mode(mode);
+    for line in ndjson.lines() {
+        verifier.push_line(line)?;
+    }
+    Ok(verifier.summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonicalization::canonicalize_with;
+    use serde_json::json;
+
+    /// Appends atoms the way the ledger shard does, returning the export:
+    /// an action's cid is computed before its prev_hash is set.
+    fn export(atoms: Vec<Value>) -> Vec<Value> {
+        let mut head = hash::GENESIS_HASH.to_string();
+        let mut records = Vec::new();
+        for (i, mut atom) in atoms.into_iter().enumerate() {
+            if atom["kind"] == "action.v1" {
+                atom["prev_hash"] = json!("");
+            }
+            let canonical = canonicalize_with(&atom, CanonicalizationMode::Jcs).unwrap();
+            let cid = hash::compute_cid(&canonical);
+            if atom["kind"] == "action.v1" {
+                atom["prev_hash"] = json!(head);
+            }
+            atom["cid"] = json!(cid);
+            head = hash::compute_head_hash(&head, &cid);
+            records.push(json!({ "seq": i + 1, "head_hash": head, "atom": atom }));
+        }
+        records
+    }
+
+    fn action(msg_id: &str) -> Value {
+        json!({
+            "kind": "action.v1",
+            "tenant_id": "t:example.com",
+            "when": "2026-01-07T12:34:56.790Z",
+            "who": { "user_id": "u:alice", "email": "alice@example.com" },
+            "did": "messenger.send",
+            "this": { "room_id": "r:general", "msg_id": msg_id, "room_seq": 42 },
+            "status": "executed",
+            "trace": { "request_id": "req:1" },
+        })
+    }
+
+    fn effect(ref_action_cid: &str) -> Value {
+        json!({
+            "kind": "effect.v1",
+            "tenant_id": "t:example.com",
+            "ref_action_cid": ref_action_cid,
+            "when": "2026-01-07T12:34:56.800Z",
+            "outcome": "ok",
+            "effects": [{ "op": "room.append", "room_id": "r:general", "room_seq": 42 }],
+            "pointers": { "msg_id": "m:1" },
+        })
+    }
+
+    /// Builds a ledger of two action/effect pairs.
+    fn ledger() -> Vec<Value> {
+        let first = export(vec![action("m:1")]);
+        let first_cid = first[0]["atom"]["cid"].as_str().unwrap().to_string();
+        let records = export(vec![action("m:1"), effect(&first_cid), action("m:2")]);
+        let second_cid = records[2]["atom"]["cid"].as_str().unwrap().to_string();
+        export(vec![action("m:1"), effect(&first_cid), action("m:2"), effect(&second_cid)])
+    }
+
+    fn ndjson(records: &[Value]) -> String {
+        records.iter().map(|r| r.to_string() + "\n").collect()
+    }
+
+    fn divergence(records: &[Value]) -> Divergence {
+        verify_ndjson(&ndjson(records), CanonicalizationMode::Jcs).unwrap_err()
+    }
+
+    #[test]
+    fn test_valid_chain() {
+        let records = ledger();
+        let summary = verify_ndjson(&ndjson(&records), CanonicalizationMode::Jcs).unwrap();
+        assert_eq!(summary.seq, 4);
+        assert_eq!(summary.actions, 2);
+        assert_eq!(summary.effects, 2);
+        assert_eq!(summary.head_hash, records[3]["head_hash"]);
+
+        // Bare atoms and blank lines
+        let bare: String = records.iter().map(|r| r["atom"].to_string() + "\n\n").collect();
+        assert_eq!(verify_ndjson(&bare, CanonicalizationMode::Jcs).unwrap(), summary);
+
+        let empty = verify_ndjson("", CanonicalizationMode::Jcs).unwrap();
+        assert_eq!(empty.head_hash, hash::GENESIS_HASH);
+    }
+
+    #[test]
+    fn test_worker_export() {
+        let export = include_str!("../conformance/worker-ledger.ndjson");
+        let summary = verify_ndjson(export, CanonicalizationMode::Jcs).unwrap();
+        assert_eq!(summary.seq, 6);
+        assert_eq!((summary.actions, summary.effects), (3, 3));
+        let last: Value = serde_json::from_str(export.lines().last().unwrap()).unwrap();
+        assert_eq!(summary.head_hash, last["head_hash"]);
+
+        // Re-appending a stored action reproduces its cid
+        let first: Value = serde_json::from_str(export.lines().next().unwrap()).unwrap();
+        let mut again = first["atom"].clone();
+        again["prev_hash"] = json!(summary.head_hash);
+        assert_eq!(compute_value_cid(&again, CanonicalizationMode::Jcs).unwrap(), first["atom"]["cid"]);
+    }
+
+    #[test]
+    fn test_divergences() {
+        let mut tampered = ledger();
+        tampered[2]["atom"]["this"]["room_seq"] = json!(43);
+        let d = divergence(&tampered);
+        assert_eq!((d.line, d.seq), (3, Some(3)));
+        assert!(matches!(d.kind, DivergenceKind::CidMismatch { .. }));
+
+        let mut gap = ledger();
+        gap.remove(1);
+        let d = divergence(&gap);
+        assert_eq!(d.kind, DivergenceKind::SeqGap { expected: 2, actual: 3 });
+
+        // Renumbered after a removal: the effect's action is gone
+        let mut renumbered = ledger();
+        renumbered.remove(0);
+        for (i, record) in renumbered.iter_mut().enumerate() {
+            record["seq"] = json!(i + 1);
+        }
+        let d = divergence(&renumbered);
+        assert!(matches!(d.kind, DivergenceKind::DanglingEffect { .. }));
+
+        let mut head = ledger();
+        head[1]["head_hash"] = json!("h:forged");
+        let d = divergence(&head);
+        assert!(matches!(d.kind, DivergenceKind::HeadHashMismatch { ref actual, .. } if actual == "h:forged"));
+
+        // The cid leaves out prev_hash, so only the prev_hash check sees this
+        let mut rechained = ledger();
+        rechained[2]["atom"]["prev_hash"] = json!(hash::GENESIS_HASH);
+        let d = divergence(&rechained);
+        assert!(matches!(d.kind, DivergenceKind::PrevHashMismatch { ref actual, .. } if actual == "h:genesis"));
+
+        let d = verify_ndjson("{\"seq\":1,\"seq\":1,\"atom\":{}}\n", CanonicalizationMode::Jcs).unwrap_err();
+        assert!(matches!(d.kind, DivergenceKind::Malformed { ref message } if message.contains("Duplicate key")));
+        assert_eq!(
+            d.to_string(),
+            "Ledger diverges at line 1: malformed entry: Canonicalization error: Duplicate key \"seq\" at byte 9"
+        );
+    }
+}
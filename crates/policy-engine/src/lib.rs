@@ -11,34 +11,58 @@
 #[cfg(not(feature = "std"))]
 extern crate alloc;
 
+pub mod agreement;
+pub mod atom;
 pub mod canonicalization;
+pub mod clock;
+pub mod conformance;
 pub mod context;
 pub mod decision;
 pub mod error;
 pub mod evaluator;
+pub mod expression;
+pub mod glob;
 pub mod hash;
+pub mod index;
+pub mod ledger;
+pub mod network;
 pub mod parser;
+pub mod pattern;
 pub mod policy;
+pub mod rbac;
+pub mod registry;
+pub mod strict_json;
+pub mod target;
+pub mod temporal;
+pub mod text;
+pub mod trace;
 pub mod types;
 
 #[cfg(feature = "wasm")]
 pub mod wasm;
 
+pub use clock::{Clock, FixedClock, SystemClock};
 pub use context::EvaluationContext;
-pub use decision::{Decision, PolicyDecision};
+pub use decision::{Decision, DecisionMapping, PolicyDecision};
 pub use error::{PolicyError, Result};
 pub use evaluator::PolicyEvaluator;
-pub use policy::Policy;
+pub use policy::{Policy, PolicySet};
+pub use target::Target;
+pub use trace::EvaluationTrace;
 
 /// Version of the policy engine.
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Re-export commonly used types.
 pub mod prelude {
+    pub use crate::agreement::{Agreement, AgreementStatus, AgreementType, Party};
+    pub use crate::clock::{Clock, FixedClock, SystemClock};
     pub use crate::context::EvaluationContext;
-    pub use crate::decision::{Decision, PolicyDecision};
+    pub use crate::decision::{Decision, DecisionMapping, PolicyDecision};
     pub use crate::error::{PolicyError, Result};
     pub use crate::evaluator::PolicyEvaluator;
-    pub use crate::policy::Policy;
+    pub use crate::policy::{Policy, PolicySet};
+    pub use crate::target::Target;
+    pub use crate::trace::EvaluationTrace;
     pub use crate::types::*;
 }
@@ -0,0 +1,324 @@
+//! IP address and CIDR matching.
+//!
+//! The operands of `ip_in_cidr` and `ip_not_in_cidr` are a CIDR or a list
+//! of entries, each an IPv4/IPv6 CIDR, a bare address, or the name of a
+//! network list. Network lists are declared under `networks:` in a policy or
+//! policy set (or with `PolicyEvaluator::define_network`) and shared by every
+//! rule loaded into the evaluator:
+//!
+//! ```yaml
+//! networks:
+//!   corporate: [10.0.0.0/8, "2001:db8:1200::/40"]
+//! rules:
+//!   - id: admin-from-corporate
+//!     conditions:
+//!       - field: environment.ip_address
+//!         operator: ip_in_cidr
+//!         value: [corporate, 192.0.2.10]
+//! ```
+
+use crate::error::{PolicyError, Result};
+use crate::policy::{Policy, PolicySet};
+use crate::types::{ConditionExpr, ConditionOperator};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Returns true if the address lies in this network. IPv4-mapped IPv6
+    /// addresses are matched as IPv4.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.network, addr.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = PolicyError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || PolicyError::ValidationError(format!("Invalid CIDR '{}'", s));
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let network = IpAddr::from_str(addr).map_err(|_| invalid())?.to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(Self { network, prefix_len })
+    }
+}
+
+/// An entry in an `ip_in_cidr` operand.
+enum Entry<'a> {
+    Cidr(Cidr),
+    Network(&'a str),
+}
+
+impl<'a> Entry<'a> {
+    fn parse(entry: &'a serde_json::Value) -> Result<Self> {
+        let text = entry
+            .as_str()
+            .ok_or_else(|| PolicyError::ValidationError(format!("CIDR entries must be strings, got {}", entry)))?;
+        if is_network_name(text) {
+            return Ok(Entry::Network(text));
+        }
+        text.parse().map(Entry::Cidr)
+    }
+}
+
+/// Network names are identifiers, so they can never be mistaken for addresses.
+fn is_network_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && IpAddr::from_str(name).is_err()
+}
+
+/// Returns the entries of an operand, which is a single string or a list.
+fn entries(value: &serde_json::Value) -> Result<Vec<Entry<'_>>> {
+    match value {
+        serde_json::Value::Array(items) => items.iter().map(Entry::parse).collect(),
+        value => Ok(vec![Entry::parse(value)?]),
+    }
+}
+
+fn parse_network(name: &str, cidrs: &[String]) -> Result<Vec<Cidr>> {
+    if !is_network_name(name) {
+        return Err(PolicyError::ValidationError(format!("Invalid network name '{}'", name)));
+    }
+    cidrs.iter().map(|cidr| cidr.parse()).collect()
+}
+
+/// The named network lists known to an evaluator.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkRegistry {
+    networks: HashMap<String, Vec<Cidr>>,
+}
+
+impl NetworkRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines a named network list. Redefining a name with different
+    /// networks is an error.
+    pub fn define(&mut self, name: &str, cidrs: &[String]) -> Result<()> {
+        let parsed = parse_network(name, cidrs)?;
+        self.check_conflict(name, &parsed)?;
+        self.networks.insert(name.to_string(), parsed);
+        Ok(())
+    }
+
+    /// Defines the network lists declared in a policy.
+    ///
+    /// Nothing is defined if any list is invalid or conflicts.
+    pub fn add_policy(&mut self, policy: &Policy) -> Result<()> {
+        let mut declared = Vec::new();
+        collect_policy_networks(policy, &mut declared);
+        self.define_all(&declared)
+    }
+
+    /// Defines the network lists declared in a policy set and its members.
+    ///
+    /// Nothing is defined if any list is invalid or conflicts.
+    pub fn add_policy_set(&mut self, policy_set: &PolicySet) -> Result<()> {
+        let mut declared = Vec::new();
+        collect_policy_set_networks(policy_set, &mut declared);
+        self.define_all(&declared)
+    }
+
+    /// Checks that every network name a policy references is defined,
+    /// either already or by the policy itself.
+    pub fn check_policy(&self, policy: &Policy) -> Result<()> {
+        let mut declared = Vec::new();
+        collect_policy_networks(policy, &mut declared);
+        self.check_references(&declared, &policy.condition_exprs())
+    }
+
+    /// Checks that every network name a policy set references is defined,
+    /// either already or by the set itself.
+    pub fn check_policy_set(&self, policy_set: &PolicySet) -> Result<()> {
+        let mut declared = Vec::new();
+        collect_policy_set_networks(policy_set, &mut declared);
+        self.check_references(&declared, &policy_set.condition_exprs())
+    }
+
+    /// Returns true if `ip` lies in any network of `operand`.
+    pub fn contains(&self, ip: &serde_json::Value, operand: &serde_json::Value) -> Result<bool> {
+        let addr = ip
+            .as_str()
+            .and_then(|ip| IpAddr::from_str(ip).ok())
+            .ok_or_else(|| PolicyError::ConditionError(format!("Invalid IP address {}", ip)))?;
+
+        let entries = entries(operand).map_err(|e| match e {
+            PolicyError::ValidationError(message) => PolicyError::ConditionError(message),
+            e => e,
+        })?;
+        for entry in entries {
+            let found = match entry {
+                Entry::Cidr(cidr) => cidr.contains(&addr),
+                Entry::Network(name) => self
+                    .networks
+                    .get(name)
+                    .ok_or_else(|| PolicyError::ConditionError(format!("Unknown network '{}'", name)))?
+                    .iter()
+                    .any(|cidr| cidr.contains(&addr)),
+            };
+            if found {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn check_conflict(&self, name: &str, cidrs: &[Cidr]) -> Result<()> {
+        match self.networks.get(name) {
+            Some(existing) if existing != cidrs => Err(PolicyError::ValidationError(format!(
+                "Network '{}' is already defined differently",
+                name
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn define_all(&mut self, declared: &[(&String, &Vec<String>)]) -> Result<()> {
+        let mut parsed = Vec::new();
+        for (name, cidrs) in declared {
+            let cidrs = parse_network(name, cidrs)?;
+            self.check_conflict(name, &cidrs)?;
+            if let Some((_, other)) = parsed.iter().find(|(other_name, _)| other_name == *name) {
+                if *other != cidrs {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Network '{}' is declared more than once with different networks",
+                        name
+                    )));
+                }
+            }
+            parsed.push((name.to_string(), cidrs));
+        }
+        self.networks.extend(parsed);
+        Ok(())
+    }
+
+    fn check_references(&self, declared: &[(&String, &Vec<String>)], exprs: &[&ConditionExpr]) -> Result<()> {
+        for expr in exprs {
+            for condition in expr.leaf_conditions() {
+                if !is_ip_operator(condition.operator) || !condition.has_literal_value() {
+                    continue;
+                }
+                for entry in entries(&condition.value)? {
+                    if let Entry::Network(name) = entry {
+                        if !self.networks.contains_key(name) && !declared.iter().any(|(n, _)| n.as_str() == name) {
+                            return Err(PolicyError::ValidationError(format!("Unknown network '{}'", name)));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the operator matches IP addresses against networks.
+pub fn is_ip_operator(operator: ConditionOperator) -> bool {
+    matches!(operator, ConditionOperator::IpInCidr | ConditionOperator::IpNotInCidr)
+}
+
+/// Validates a policy's `networks` declarations.
+pub(crate) fn validate_networks(networks: &BTreeMap<String, Vec<String>>, owner: &str) -> Result<()> {
+    for (name, cidrs) in networks {
+        parse_network(name, cidrs).map_err(|e| in_owner(e, owner))?;
+    }
+    Ok(())
+}
+
+/// Validates the literal operand of an IP operator at load time.
+pub(crate) fn validate_operand(value: &serde_json::Value, owner: &str) -> Result<()> {
+    if value.as_array().is_some_and(|items| items.is_empty()) {
+        return Err(PolicyError::ValidationError(format!("Empty CIDR list can never match in {}", owner)));
+    }
+    entries(value).map(|_| ()).map_err(|e| in_owner(e, owner))
+}
+
+fn in_owner(e: PolicyError, owner: &str) -> PolicyError {
+    match e {
+        PolicyError::ValidationError(message) => PolicyError::ValidationError(format!("{} in {}", message, owner)),
+        e => e,
+    }
+}
+
+fn collect_policy_networks<'a>(policy: &'a Policy, declared: &mut Vec<(&'a String, &'a Vec<String>)>) {
+    declared.extend(&policy.networks);
+}
+
+fn collect_policy_set_networks<'a>(policy_set: &'a PolicySet, declared: &mut Vec<(&'a String, &'a Vec<String>)>) {
+    declared.extend(&policy_set.networks);
+    for policy in &policy_set.policies {
+        collect_policy_networks(policy, declared);
+    }
+    for nested in &policy_set.policy_sets {
+        collect_policy_set_networks(nested, declared);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cidr_contains() {
+        let private: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(private.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(private.contains(&"::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!private.contains(&"11.0.0.1".parse().unwrap()));
+
+        let egress: Cidr = "2001:db8:1200::/40".parse().unwrap();
+        assert!(egress.contains(&"2001:db8:12ff::1".parse().unwrap()));
+        assert!(!egress.contains(&"2001:db8:1300::1".parse().unwrap()));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&"203.0.113.9".parse().unwrap()));
+
+        let host: Cidr = "192.0.2.10".parse().unwrap();
+        assert!(host.contains(&"192.0.2.10".parse().unwrap()));
+        assert!(!host.contains(&"192.0.2.11".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = NetworkRegistry::new();
+        registry.define("corporate", &["10.0.0.0/8".to_string()]).unwrap();
+        assert!(registry.define("corporate", &["10.0.0.0/8".to_string()]).is_ok());
+        assert!(registry.define("corporate", &["192.168.0.0/16".to_string()]).is_err());
+
+        assert!(registry.contains(&json!("10.9.8.7"), &json!("corporate")).unwrap());
+        assert!(registry.contains(&json!("192.0.2.10"), &json!(["corporate", "192.0.2.0/24"])).unwrap());
+        assert!(!registry.contains(&json!("192.0.3.10"), &json!(["corporate", "192.0.2.0/24"])).unwrap());
+        assert!(registry.contains(&json!("10.0.0.1"), &json!("vpn")).is_err());
+        assert!(registry.contains(&json!("not-an-ip"), &json!("corporate")).is_err());
+    }
+}
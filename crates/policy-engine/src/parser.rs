@@ -1,7 +1,7 @@
 //! Policy file parser.
 
 use crate::error::{PolicyError, Result};
-use crate::policy::Policy;
+use crate::policy::{Policy, PolicySet};
 
 /// Supported policy file formats.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -47,6 +47,14 @@ pub fn parse_policy_with_format(content: &str, format: PolicyFormat) -> Result<P
     }
 }
 
+/// Parses a policy set from a string, auto-detecting format.
+pub fn parse_policy_set(content: &str) -> Result<PolicySet> {
+    match PolicyFormat::detect(content) {
+        PolicyFormat::Yaml => PolicySet::from_yaml(content),
+        PolicyFormat::Json => PolicySet::from_json(content),
+    }
+}
+
 /// Parses multiple policies from a YAML document with multiple documents.
 pub fn parse_policies_yaml(content: &str) -> Result<Vec<Policy>> {
     let mut policies = Vec::new();
@@ -84,6 +92,10 @@ pub struct PolicyPack {
     /// Policies in this pack.
     pub policies: Vec<Policy>,
 
+    /// Policy sets in this pack.
+    #[serde(default)]
+    pub policy_sets: Vec<PolicySet>,
+
     /// Pack metadata.
     #[serde(default)]
     pub metadata: std::collections::HashMap<String, serde_json::Value>,
@@ -98,6 +110,7 @@ impl PolicyPack {
             name: name.into(),
             description: None,
             policies: Vec::new(),
+            policy_sets: Vec::new(),
             metadata: std::collections::HashMap::new(),
         }
     }
@@ -107,14 +120,22 @@ impl PolicyPack {
         self.policies.push(policy);
     }
 
+    /// Adds a policy set to the pack.
+    pub fn add_policy_set(&mut self, policy_set: PolicySet) {
+        self.policy_sets.push(policy_set);
+    }
+
     /// Parses a policy pack from YAML.
     pub fn from_yaml(yaml: &str) -> Result<Self> {
         let pack: PolicyPack = serde_yaml::from_str(yaml)?;
 
-        // Validate all policies
+        // Validate all policies and policy sets
         for policy in &pack.policies {
             policy.validate()?;
         }
+        for policy_set in &pack.policy_sets {
+            policy_set.validate()?;
+        }
 
         Ok(pack)
     }
@@ -170,4 +191,29 @@ rules: []
         let policies = parse_policies_yaml(yaml).unwrap();
         assert_eq!(policies.len(), 2);
     }
+
+    #[test]
+    fn test_pack_with_policy_sets() {
+        let yaml = r#"
+id: pack
+version: "1.0.0"
+name: Pack
+policies: []
+policy_sets:
+  - id: tenant
+    version: "1.0.0"
+    name: Tenant
+    combining_algorithm: allow_overrides
+    policies:
+      - id: tenant-policy
+        version: "1.0.0"
+        name: Tenant Policy
+        rules: []
+"#;
+        let pack = PolicyPack::from_yaml(yaml).unwrap();
+        assert_eq!(pack.policy_sets[0].policies[0].id, "tenant-policy");
+
+        let set = parse_policy_set(r#"{"id": "s", "version": "1.0.0", "name": "S"}"#).unwrap();
+        assert!(set.policies.is_empty());
+    }
 }
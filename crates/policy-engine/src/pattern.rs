@@ -0,0 +1,206 @@
+//! Compiled regular expressions for the `matches` operator.
+//!
+//! A policy's patterns are checked for syntax when it is validated, then
+//! compiled within the evaluator's limits when it is loaded and looked up by
+//! their source text at evaluation time. The `regex` crate matches in linear time,
+//! so the remaining risk from tenant-supplied patterns is their size: the
+//! limits below bound pattern length, nesting and compiled program size.
+
+use crate::error::{PolicyError, Result};
+use crate::policy::{Policy, PolicySet};
+use crate::types::{ConditionExpr, ConditionOperator};
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Limits applied when compiling `matches` patterns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegexLimits {
+    /// Maximum pattern length in bytes.
+    pub max_pattern_len: usize,
+
+    /// Maximum size of the compiled program in bytes.
+    pub size_limit: usize,
+
+    /// Maximum size of the lazy DFA cache in bytes.
+    pub dfa_size_limit: usize,
+
+    /// Maximum nesting depth of groups and repetitions.
+    pub nest_limit: u32,
+}
+
+impl Default for RegexLimits {
+    fn default() -> Self {
+        Self {
+            max_pattern_len: 1024,
+            size_limit: 1 << 20,
+            dfa_size_limit: 1 << 20,
+            nest_limit: 32,
+        }
+    }
+}
+
+impl RegexLimits {
+    /// Compiles a pattern within these limits.
+    pub fn compile(&self, pattern: &str) -> Result<Regex> {
+        if pattern.len() > self.max_pattern_len {
+            return Err(PolicyError::ValidationError(format!(
+                "Regex pattern is {} bytes, exceeding the limit of {}",
+                pattern.len(),
+                self.max_pattern_len
+            )));
+        }
+
+        RegexBuilder::new(pattern)
+            .size_limit(self.size_limit)
+            .dfa_size_limit(self.dfa_size_limit)
+            .nest_limit(self.nest_limit)
+            .build()
+            .map_err(|e| PolicyError::ValidationError(format!("Invalid regex pattern '{}': {}", pattern, e)))
+    }
+}
+
+/// Regexes compiled from the `matches` conditions of loaded policies.
+#[derive(Debug, Clone, Default)]
+pub struct RegexCache {
+    limits: RegexLimits,
+    compiled: HashMap<String, Regex>,
+}
+
+impl RegexCache {
+    /// Creates an empty cache with the given limits.
+    pub fn new(limits: RegexLimits) -> Self {
+        Self {
+            limits,
+            compiled: HashMap::new(),
+        }
+    }
+
+    /// Returns the limits patterns are compiled with.
+    pub fn limits(&self) -> &RegexLimits {
+        &self.limits
+    }
+
+    /// Compiles every literal `matches` pattern in a policy.
+    ///
+    /// Nothing is added if any pattern fails to compile.
+    pub fn add_policy(&mut self, policy: &Policy) -> Result<()> {
+        self.add_exprs(&policy.condition_exprs())
+    }
+
+    /// Compiles every literal `matches` pattern in a policy set.
+    ///
+    /// Nothing is added if any pattern fails to compile.
+    pub fn add_policy_set(&mut self, policy_set: &PolicySet) -> Result<()> {
+        self.add_exprs(&policy_set.condition_exprs())
+    }
+
+    /// Returns the regex for a pattern, compiling it within the limits if it
+    /// was not seen at load time (e.g. a pattern read from the context).
+    pub fn get(&self, pattern: &str) -> Result<Cow<'_, Regex>> {
+        match self.compiled.get(pattern) {
+            Some(regex) => Ok(Cow::Borrowed(regex)),
+            None => match self.limits.compile(pattern) {
+                Ok(regex) => Ok(Cow::Owned(regex)),
+                Err(PolicyError::ValidationError(message)) => Err(PolicyError::ConditionError(message)),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    /// Returns the number of compiled patterns.
+    pub fn len(&self) -> usize {
+        self.compiled.len()
+    }
+
+    /// Returns true if no patterns have been compiled.
+    pub fn is_empty(&self) -> bool {
+        self.compiled.is_empty()
+    }
+
+    fn add_exprs(&mut self, exprs: &[&ConditionExpr]) -> Result<()> {
+        let mut compiled = Vec::new();
+        for expr in exprs {
+            for condition in expr.leaf_conditions() {
+                if condition.operator != ConditionOperator::Matches || !condition.has_literal_value() {
+                    continue;
+                }
+                let pattern = literal_pattern(condition.value.as_str())?;
+                if !self.compiled.contains_key(pattern) {
+                    compiled.push((pattern.to_string(), self.limits.compile(pattern)?));
+                }
+            }
+        }
+        self.compiled.extend(compiled);
+        Ok(())
+    }
+}
+
+/// Checks that a literal `matches` value is a pattern string.
+fn literal_pattern(value: Option<&str>) -> Result<&str> {
+    value.ok_or_else(|| PolicyError::ValidationError("The matches operator requires a string pattern".to_string()))
+}
+
+/// Validates the syntax of a literal `matches` pattern. Its size is checked
+/// against the evaluator's `RegexLimits` when the `RegexCache` compiles it.
+pub(crate) fn validate_pattern(value: &serde_json::Value, owner: &str) -> Result<()> {
+    let Some(pattern) = value.as_str() else {
+        return Err(PolicyError::ValidationError(format!(
+            "The matches operator requires a string pattern in {}",
+            owner
+        )));
+    };
+    regex_syntax::ParserBuilder::new()
+        .nest_limit(u32::MAX)
+        .build()
+        .parse(pattern)
+        .map(|_| ())
+        .map_err(|e| PolicyError::ValidationError(format!("Invalid regex pattern '{}': {} in {}", pattern, e, owner)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let limits = RegexLimits::default();
+        assert!(limits.compile("^messenger\\.").is_ok());
+        assert!(limits.compile("(unclosed").is_err());
+        assert!(limits.compile(&"a".repeat(2000)).is_err());
+        assert!(limits.compile(&format!("{}a{}", "(".repeat(40), ")".repeat(40))).is_err());
+
+        let tight = RegexLimits {
+            size_limit: 1024,
+            ..RegexLimits::default()
+        };
+        assert!(tight.compile("\\w{100}").is_err());
+    }
+
+    #[test]
+    fn test_cache() {
+        let policy = Policy::from_yaml(
+            r#"
+id: regex-policy
+version: "1.0.0"
+name: Regex Policy
+rules:
+  - id: ids
+    effect: allow
+    conditions:
+      - field: identity.user_id
+        operator: matches
+        value: "^u:[a-z]+$"
+    priority: 10
+"#,
+        )
+        .unwrap();
+
+        let mut cache = RegexCache::default();
+        cache.add_policy(&policy).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(matches!(cache.get("^u:[a-z]+$").unwrap(), Cow::Borrowed(_)));
+        assert!(matches!(cache.get("^t:").unwrap(), Cow::Owned(_)));
+        assert!(cache.get("(").is_err());
+    }
+}
@@ -1,8 +1,14 @@
 //! Policy definition and management.
 
+use crate::context::EvaluationContext;
 use crate::error::{PolicyError, Result};
-use crate::types::{CombiningAlgorithm, Effect, Rule};
+use crate::rbac::RoleDefinition;
+use crate::registry::TypeDeclarations;
+use crate::target::Target;
+use crate::types::{CombiningAlgorithm, Condition, ConditionExpr, ConditionOperator, Effect, Obligation, Rule};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// A complete policy definition.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +26,11 @@ pub struct Policy {
     #[serde(default)]
     pub description: Option<String>,
 
+    /// Which requests this policy applies to. Checked before any rule; when
+    /// it does not match the policy is not applicable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+
     /// The rules in this policy.
     pub rules: Vec<Rule>,
 
@@ -27,17 +38,42 @@ pub struct Policy {
     #[serde(default)]
     pub combining_algorithm: CombiningAlgorithm,
 
-    /// Default effect when no rules match.
+    /// Default effect when no rules apply. When unset (`null`), the policy
+    /// is not applicable to such requests.
     #[serde(default = "default_effect")]
-    pub default_effect: Effect,
+    pub default_effect: Option<Effect>,
+
+    /// Obligations returned whenever this policy's decision matches.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub obligations: Vec<Obligation>,
+
+    /// Advice returned whenever this policy's decision matches.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub advice: Vec<Obligation>,
+
+    /// Named network lists for `ip_in_cidr` conditions.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub networks: BTreeMap<String, Vec<String>>,
+
+    /// Role declarations for `role_at_least` and `has_permission`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roles: BTreeMap<String, RoleDefinition>,
+
+    /// Named permission sets of action-name globs.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub permission_sets: BTreeMap<String, Vec<String>>,
+
+    /// Resource and action types used beyond the well-known ones.
+    #[serde(default, skip_serializing_if = "TypeDeclarations::is_empty")]
+    pub types: TypeDeclarations,
 
     /// Policy metadata.
     #[serde(default)]
     pub metadata: std::collections::HashMap<String, serde_json::Value>,
 }
 
-fn default_effect() -> Effect {
-    Effect::Deny
+fn default_effect() -> Option<Effect> {
+    Some(Effect::Deny)
 }
 
 impl Policy {
@@ -48,9 +84,16 @@ impl Policy {
             version: "1.0.0".to_string(),
             name: name.into(),
             description: None,
+            target: None,
             rules: Vec::new(),
             combining_algorithm: CombiningAlgorithm::default(),
-            default_effect: Effect::Deny,
+            default_effect: Some(Effect::Deny),
+            obligations: Vec::new(),
+            advice: Vec::new(),
+            networks: BTreeMap::new(),
+            roles: BTreeMap::new(),
+            permission_sets: BTreeMap::new(),
+            types: TypeDeclarations::default(),
             metadata: std::collections::HashMap::new(),
         }
     }
@@ -61,6 +104,12 @@ impl Policy {
         self
     }
 
+    /// Sets the target.
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.target = Some(target);
+        self
+    }
+
     /// Sets the combining algorithm.
     pub fn with_combining_algorithm(mut self, algorithm: CombiningAlgorithm) -> Self {
         self.combining_algorithm = algorithm;
@@ -69,7 +118,26 @@ impl Policy {
 
     /// Sets the default effect.
     pub fn with_default_effect(mut self, effect: Effect) -> Self {
-        self.default_effect = effect;
+        self.default_effect = Some(effect);
+        self
+    }
+
+    /// Clears the default effect, making the policy not applicable when no
+    /// rules apply.
+    pub fn without_default_effect(mut self) -> Self {
+        self.default_effect = None;
+        self
+    }
+
+    /// Adds an obligation to the policy.
+    pub fn with_obligation(mut self, obligation: Obligation) -> Self {
+        self.obligations.push(obligation);
+        self
+    }
+
+    /// Adds advice to the policy.
+    pub fn with_advice(mut self, advice: Obligation) -> Self {
+        self.advice.push(advice);
         self
     }
 
@@ -107,41 +175,427 @@ impl Policy {
             return Err(PolicyError::ValidationError("Policy name is required".to_string()));
         }
 
+        let owner = format!("policy '{}'", self.id);
+        if let Some(target) = &self.target {
+            validate_target(target, &owner)?;
+        }
+        validate_obligations(&self.obligations, &self.advice, &owner)?;
+        crate::network::validate_networks(&self.networks, &owner)?;
+        crate::rbac::validate_declarations(&self.roles, &self.permission_sets, &owner)?;
+        self.types.validate().map_err(|e| match e {
+            PolicyError::ValidationError(message) => PolicyError::ValidationError(format!("{} in {}", message, owner)),
+            e => e,
+        })?;
+
         // Validate each rule
         for rule in &self.rules {
             if rule.id.is_empty() {
                 return Err(PolicyError::ValidationError("Rule ID is required".to_string()));
             }
 
-            // Validate conditions
-            for condition in &rule.conditions {
-                if condition.field.is_empty() {
-                    return Err(PolicyError::ValidationError(
-                        format!("Condition field is required in rule '{}'", rule.id)
-                    ));
+            let owner = format!("rule '{}'", rule.id);
+            if let Some(target) = &rule.target {
+                validate_target(target, &owner)?;
+            }
+            validate_obligations(&rule.obligations, &rule.advice, &owner)?;
+
+            if let (Some(from), Some(until)) = (rule.valid_from, rule.valid_until) {
+                if from >= until {
+                    return Err(PolicyError::ValidationError(format!(
+                        "valid_from must be before valid_until in {}",
+                        owner
+                    )));
                 }
             }
+
+            // Validate conditions
+            for expr in &rule.conditions {
+                validate_condition_expr(expr, &owner)?;
+            }
         }
 
         Ok(())
     }
 
+    /// Returns every condition expression in the policy: those of its
+    /// target, and of each rule's target and conditions.
+    pub fn condition_exprs(&self) -> Vec<&ConditionExpr> {
+        let mut exprs = Vec::new();
+        if let Some(target) = &self.target {
+            exprs.extend(&target.conditions);
+        }
+        for rule in &self.rules {
+            if let Some(target) = &rule.target {
+                exprs.extend(&target.conditions);
+            }
+            exprs.extend(&rule.conditions);
+        }
+        exprs
+    }
+
     /// Returns rules sorted by priority (higher priority first).
     pub fn sorted_rules(&self) -> Vec<&Rule> {
         let mut rules: Vec<&Rule> = self.rules.iter().collect();
-        rules.sort_by(|a, b| b.priority.cmp(&a.priority));
+        rules.sort_by_key(|r| std::cmp::Reverse(r.priority));
         rules
     }
 }
 
+/// A hierarchical group of policies and nested policy sets.
+///
+/// A set applies only to requests matching its `target`. The decisions of
+/// its policies (first, in order) and nested sets (after, in order) are
+/// combined with the set's own combining algorithm, which lets platform,
+/// tenant and room-level policies be layered deliberately.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicySet {
+    /// Unique identifier for the policy set.
+    pub id: String,
+
+    /// Version of the policy set.
+    pub version: String,
+
+    /// Human-readable name.
+    pub name: String,
+
+    /// Description of what this policy set does.
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// Which requests this policy set applies to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+
+    /// Algorithm for combining the decisions of the members.
+    #[serde(default)]
+    pub combining_algorithm: CombiningAlgorithm,
+
+    /// Member policies.
+    #[serde(default)]
+    pub policies: Vec<Policy>,
+
+    /// Nested policy sets.
+    #[serde(default)]
+    pub policy_sets: Vec<PolicySet>,
+
+    /// Obligations returned whenever this set's decision matches.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub obligations: Vec<Obligation>,
+
+    /// Advice returned whenever this set's decision matches.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub advice: Vec<Obligation>,
+
+    /// Named network lists for `ip_in_cidr` conditions.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub networks: BTreeMap<String, Vec<String>>,
+
+    /// Role declarations for `role_at_least` and `has_permission`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roles: BTreeMap<String, RoleDefinition>,
+
+    /// Named permission sets of action-name globs.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub permission_sets: BTreeMap<String, Vec<String>>,
+
+    /// Resource and action types used beyond the well-known ones.
+    #[serde(default, skip_serializing_if = "TypeDeclarations::is_empty")]
+    pub types: TypeDeclarations,
+
+    /// Policy set metadata.
+    #[serde(default)]
+    pub metadata: std::collections::HashMap<String, serde_json::Value>,
+}
+
+impl PolicySet {
+    /// Creates a new, empty policy set.
+    pub fn new(id: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            version: "1.0.0".to_string(),
+            name: name.into(),
+            description: None,
+            target: None,
+            combining_algorithm: CombiningAlgorithm::default(),
+            policies: Vec::new(),
+            policy_sets: Vec::new(),
+            obligations: Vec::new(),
+            advice: Vec::new(),
+            networks: BTreeMap::new(),
+            roles: BTreeMap::new(),
+            permission_sets: BTreeMap::new(),
+            types: TypeDeclarations::default(),
+            metadata: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Adds a member policy.
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policies.push(policy);
+        self
+    }
+
+    /// Adds a nested policy set.
+    pub fn with_policy_set(mut self, policy_set: PolicySet) -> Self {
+        self.policy_sets.push(policy_set);
+        self
+    }
+
+    /// Sets the target.
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Sets the combining algorithm.
+    pub fn with_combining_algorithm(mut self, algorithm: CombiningAlgorithm) -> Self {
+        self.combining_algorithm = algorithm;
+        self
+    }
+
+    /// Adds an obligation to the policy set.
+    pub fn with_obligation(mut self, obligation: Obligation) -> Self {
+        self.obligations.push(obligation);
+        self
+    }
+
+    /// Parses a policy set from YAML.
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let policy_set: PolicySet = serde_yaml::from_str(yaml)?;
+        policy_set.validate()?;
+        Ok(policy_set)
+    }
+
+    /// Parses a policy set from JSON.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let policy_set: PolicySet = serde_json::from_str(json)?;
+        policy_set.validate()?;
+        Ok(policy_set)
+    }
+
+    /// Serializes the policy set to YAML.
+    pub fn to_yaml(&self) -> Result<String> {
+        serde_yaml::to_string(self).map_err(|e| PolicyError::SerializationError(e.to_string()))
+    }
+
+    /// Serializes the policy set to JSON.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| PolicyError::SerializationError(e.to_string()))
+    }
+
+    /// Returns every condition expression in the set's target and members.
+    pub fn condition_exprs(&self) -> Vec<&ConditionExpr> {
+        let mut exprs = Vec::new();
+        if let Some(target) = &self.target {
+            exprs.extend(&target.conditions);
+        }
+        for policy in &self.policies {
+            exprs.extend(policy.condition_exprs());
+        }
+        for nested in &self.policy_sets {
+            exprs.extend(nested.condition_exprs());
+        }
+        exprs
+    }
+
+    /// Validates the policy set and everything it contains.
+    pub fn validate(&self) -> Result<()> {
+        if self.id.is_empty() {
+            return Err(PolicyError::ValidationError("Policy set ID is required".to_string()));
+        }
+
+        let owner = format!("policy set '{}'", self.id);
+        if let Some(target) = &self.target {
+            validate_target(target, &owner)?;
+        }
+        validate_obligations(&self.obligations, &self.advice, &owner)?;
+        crate::network::validate_networks(&self.networks, &owner)?;
+        crate::rbac::validate_declarations(&self.roles, &self.permission_sets, &owner)?;
+        self.types.validate().map_err(|e| match e {
+            PolicyError::ValidationError(message) => PolicyError::ValidationError(format!("{} in {}", message, owner)),
+            e => e,
+        })?;
+
+        for policy in &self.policies {
+            policy.validate()?;
+        }
+        for policy_set in &self.policy_sets {
+            policy_set.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Validates a target and its conditions.
+fn validate_target(target: &Target, owner: &str) -> Result<()> {
+    target.validate(owner)?;
+    for expr in &target.conditions {
+        validate_condition_expr(expr, &format!("target of {}", owner))?;
+    }
+    Ok(())
+}
+
+/// Validates the obligations and advice attached to a policy or rule.
+fn validate_obligations(obligations: &[Obligation], advice: &[Obligation], owner: &str) -> Result<()> {
+    for obligation in obligations.iter().chain(advice) {
+        if obligation.id.is_empty() {
+            return Err(PolicyError::ValidationError(
+                format!("Obligation ID is required in {}", owner)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Validates a condition expression tree. `owner` names where it appears,
+/// e.g. "rule 'x'".
+fn validate_condition_expr(expr: &ConditionExpr, owner: &str) -> Result<()> {
+    match expr {
+        ConditionExpr::AllOf { all_of } => {
+            for sub in all_of {
+                validate_condition_expr(sub, owner)?;
+            }
+        }
+        ConditionExpr::AnyOf { any_of } => {
+            if any_of.is_empty() {
+                return Err(PolicyError::ValidationError(
+                    format!("Empty any_of can never match in {}", owner)
+                ));
+            }
+            for sub in any_of {
+                validate_condition_expr(sub, owner)?;
+            }
+        }
+        ConditionExpr::Not { not } => validate_condition_expr(not, owner)?,
+        ConditionExpr::Condition(condition) => {
+            match &condition.expr {
+                Some(_) if !condition.field.is_empty() => {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Condition on '{}' sets both field and expr in {}",
+                        condition.field, owner
+                    )));
+                }
+                Some(expr) => crate::expression::validate_expression(expr, owner)?,
+                None if condition.field.is_empty() => {
+                    return Err(PolicyError::ValidationError(
+                        format!("Condition field is required in {}", owner)
+                    ));
+                }
+                None => {}
+            }
+
+            if condition.expr.is_none() && crate::context::parse_path(&condition.field).is_none() {
+                return Err(PolicyError::ValidationError(format!(
+                    "Invalid field path '{}' in {}",
+                    condition.field, owner
+                )));
+            }
+
+            if let Some(value_expr) = &condition.value_expr {
+                if matches!(condition.operator, ConditionOperator::Exists | ConditionOperator::NotExists) {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Operator {:?} takes no value, but value_expr is set in {}",
+                        condition.operator, owner
+                    )));
+                }
+                if !condition.value.is_null() || condition.value_field.is_some() {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Condition on '{}' sets value_expr together with value or value_field in {}",
+                        condition.subject(), owner
+                    )));
+                }
+                crate::expression::validate_expression(value_expr, owner)?;
+            }
+
+            if condition.has_literal_value() {
+                if condition.operator == ConditionOperator::Matches {
+                    crate::pattern::validate_pattern(&condition.value, owner)?;
+                }
+
+                if condition.operator == ConditionOperator::Glob {
+                    crate::glob::validate_operand(&condition.value, owner)?;
+                }
+
+                if crate::temporal::is_temporal(condition.operator) {
+                    crate::temporal::validate_operand(condition.operator, &condition.value, owner)?;
+                }
+
+                if crate::network::is_ip_operator(condition.operator) {
+                    crate::network::validate_operand(&condition.value, owner)?;
+                }
+
+                if crate::rbac::is_role_operator(condition.operator) {
+                    crate::rbac::validate_operand(condition.operator, &condition.value, owner)?;
+                }
+
+                validate_set_operand(condition, owner)?;
+            }
+
+            if let Some(value_field) = &condition.value_field {
+                if matches!(condition.operator, ConditionOperator::Exists | ConditionOperator::NotExists) {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Operator {:?} takes no value, but value_field is set in {}",
+                        condition.operator, owner
+                    )));
+                }
+                if !condition.value.is_null() {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Condition on '{}' sets both value and value_field in {}",
+                        condition.subject(), owner
+                    )));
+                }
+                if !EvaluationContext::is_known_path(value_field) {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Unknown context path '{}' in value_field of {}",
+                        value_field, owner
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the literal operand of a set or count operator.
+fn validate_set_operand(condition: &Condition, owner: &str) -> Result<()> {
+    match condition.operator {
+        ConditionOperator::Intersects
+        | ConditionOperator::Disjoint
+        | ConditionOperator::SubsetOf
+        | ConditionOperator::SupersetOf
+            if !condition.value.is_array() =>
+        {
+            Err(PolicyError::ValidationError(format!(
+                "Operator {:?} on '{}' requires an array value in {}",
+                condition.operator, condition.subject(), owner
+            )))
+        }
+        ConditionOperator::CountEquals | ConditionOperator::CountAtLeast | ConditionOperator::CountAtMost
+            if !condition.value.is_u64() =>
+        {
+            Err(PolicyError::ValidationError(format!(
+                "Operator {:?} on '{}' requires a non-negative integer value in {}",
+                condition.operator, condition.subject(), owner
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Builder for creating rules.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct RuleBuilder {
     id: String,
     description: Option<String>,
     effect: Effect,
-    conditions: Vec<crate::types::Condition>,
+    target: Option<Target>,
+    conditions: Vec<ConditionExpr>,
     priority: i32,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+    obligations: Vec<Obligation>,
+    advice: Vec<Obligation>,
 }
 
 impl RuleBuilder {
@@ -151,8 +605,13 @@ impl RuleBuilder {
             id: id.into(),
             description: None,
             effect: Effect::Allow,
+            target: None,
             conditions: Vec::new(),
             priority: 0,
+            valid_from: None,
+            valid_until: None,
+            obligations: Vec::new(),
+            advice: Vec::new(),
         }
     }
 
@@ -180,9 +639,15 @@ impl RuleBuilder {
         self
     }
 
-    /// Adds a condition.
-    pub fn condition(mut self, condition: crate::types::Condition) -> Self {
-        self.conditions.push(condition);
+    /// Sets the target.
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Adds a condition or condition expression.
+    pub fn condition(mut self, condition: impl Into<ConditionExpr>) -> Self {
+        self.conditions.push(condition.into());
         self
     }
 
@@ -192,22 +657,56 @@ impl RuleBuilder {
         self
     }
 
+    /// Sets the time from which the rule applies.
+    pub fn valid_from(mut self, valid_from: DateTime<Utc>) -> Self {
+        self.valid_from = Some(valid_from);
+        self
+    }
+
+    /// Sets the time from which the rule no longer applies.
+    pub fn valid_until(mut self, valid_until: DateTime<Utc>) -> Self {
+        self.valid_until = Some(valid_until);
+        self
+    }
+
+    /// Adds an obligation.
+    pub fn obligation(mut self, obligation: Obligation) -> Self {
+        self.obligations.push(obligation);
+        self
+    }
+
+    /// Adds advice.
+    pub fn advice(mut self, advice: Obligation) -> Self {
+        self.advice.push(advice);
+        self
+    }
+
     /// Builds the rule.
     pub fn build(self) -> Rule {
         Rule {
             id: self.id,
             description: self.description,
             effect: self.effect,
+            target: self.target,
             conditions: self.conditions,
             priority: self.priority,
+            valid_from: self.valid_from,
+            valid_until: self.valid_until,
+            obligations: self.obligations,
+            advice: self.advice,
         }
     }
 }
 
+impl Default for RuleBuilder {
+    fn default() -> Self {
+        Self::new(String::new())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{Condition, ConditionOperator};
 
     #[test]
     fn test_policy_from_yaml() {
@@ -239,11 +738,7 @@ default_effect: deny
         let rule = RuleBuilder::new("test-rule")
             .description("Test rule")
             .allow()
-            .condition(Condition {
-                field: "role".to_string(),
-                operator: ConditionOperator::Equals,
-                value: serde_json::json!("member"),
-            })
+            .condition(Condition::new("role", ConditionOperator::Equals, serde_json::json!("member")))
             .priority(10)
             .build();
 
@@ -251,4 +746,275 @@ default_effect: deny
         assert_eq!(rule.effect, Effect::Allow);
         assert_eq!(rule.conditions.len(), 1);
     }
+
+    #[test]
+    fn test_condition_tree_round_trip() {
+        let yaml = r#"
+id: tree-policy
+version: "1.0.0"
+name: Tree Policy
+rules:
+  - id: admin-or-owner
+    effect: allow
+    conditions:
+      - field: action.action_type
+        operator: equals
+        value: write
+      - any_of:
+          - field: role
+            operator: equals
+            value: admin
+          - all_of:
+              - field: resource.owner_id
+                operator: exists
+                value: null
+              - not:
+                  field: identity.is_service
+                  operator: equals
+                  value: true
+    priority: 10
+"#;
+
+        let policy = Policy::from_yaml(yaml).unwrap();
+        let rule = &policy.rules[0];
+        assert_eq!(rule.conditions.len(), 2);
+        assert!(matches!(rule.conditions[0], ConditionExpr::Condition(_)));
+        assert!(matches!(rule.conditions[1], ConditionExpr::AnyOf { .. }));
+        assert_eq!(rule.conditions[1].leaf_conditions().len(), 3);
+
+        let reparsed = Policy::from_json(&policy.to_json().unwrap()).unwrap();
+        assert_eq!(reparsed.to_json().unwrap(), policy.to_json().unwrap());
+        let reparsed = Policy::from_yaml(&policy.to_yaml().unwrap()).unwrap();
+        assert_eq!(reparsed.to_json().unwrap(), policy.to_json().unwrap());
+    }
+
+    #[test]
+    fn test_validate_nested_condition() {
+        let yaml = r#"
+id: bad-policy
+version: "1.0.0"
+name: Bad Policy
+rules:
+  - id: bad-rule
+    effect: allow
+    conditions:
+      - not:
+          any_of:
+            - field: ""
+              operator: equals
+              value: admin
+"#;
+        assert!(Policy::from_yaml(yaml).is_err());
+
+        let empty_any = Policy::new("p", "P").with_rule(
+            RuleBuilder::new("r").condition(ConditionExpr::any_of(vec![])).build(),
+        );
+        assert!(empty_any.validate().is_err());
+    }
+
+    #[test]
+    fn test_ambiguous_condition_expr() {
+        let policy = |conditions: &str| {
+            Policy::from_yaml(&format!(
+                "id: p\nversion: \"1.0.0\"\nname: P\nrules:\n  - id: r\n    effect: allow\n    conditions:\n{}\n    priority: 10\n",
+                conditions
+            ))
+        };
+
+        // A guard next to a combinator is not silently dropped
+        let mixed = policy(
+            r#"      - all_of:
+          - field: role
+            operator: equals
+            value: admin
+        not:
+          field: identity.is_service
+          operator: equals
+          value: true"#,
+        );
+        let err = mixed.unwrap_err().to_string();
+        assert!(err.contains("all_of and not cannot be combined"), "{}", err);
+
+        let leaf_and_combinator = policy(
+            r#"      - field: role
+        operator: equals
+        value: admin
+        any_of: []"#,
+        );
+        assert!(leaf_and_combinator.unwrap_err().to_string().contains("'any_of' cannot be combined"));
+
+        // A typo inside a branch is reported, not read as another shape
+        let typo = policy(
+            r#"      - all_of:
+          - field: role
+            operator: equlas
+            value: admin"#,
+        );
+        let err = typo.unwrap_err().to_string();
+        assert!(err.contains("all_of[0]: unknown variant `equlas`"), "{}", err);
+    }
+
+    #[test]
+    fn test_obligations_from_yaml() {
+        let yaml = r#"
+id: obligation-policy
+version: "1.0.0"
+name: Obligation Policy
+rules:
+  - id: allow-redacted
+    effect: allow
+    conditions: []
+    priority: 10
+    obligations:
+      - id: redact-body
+        fulfill_on: allow
+        attributes:
+          fields: [body]
+    advice:
+      - id: rate-limit
+        fulfill_on: allow
+        attributes:
+          bucket: messages
+obligations:
+  - id: emit-receipt
+    fulfill_on: deny
+"#;
+
+        let policy = Policy::from_yaml(yaml).unwrap();
+        assert_eq!(policy.obligations[0].fulfill_on, Effect::Deny);
+        assert_eq!(policy.rules[0].obligations[0].id, "redact-body");
+        assert_eq!(policy.rules[0].advice[0].attributes["bucket"], "messages");
+
+        let invalid = Policy::new("p", "P").with_obligation(Obligation::new("", Effect::Allow));
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_policy_set_from_yaml() {
+        let yaml = r#"
+id: platform
+version: "1.0.0"
+name: Platform
+combining_algorithm: first_applicable
+policies:
+  - id: platform-guards
+    version: "1.0.0"
+    name: Platform Guards
+    rules: []
+    default_effect: null
+policy_sets:
+  - id: rooms
+    version: "1.0.0"
+    name: Rooms
+    target:
+      resource_types: [room]
+      action_names: ["messenger.*"]
+    policies:
+      - id: room-policy
+        version: "1.0.0"
+        name: Room Policy
+        rules: []
+"#;
+
+        let set = PolicySet::from_yaml(yaml).unwrap();
+        assert_eq!(set.combining_algorithm, CombiningAlgorithm::FirstApplicable);
+        assert_eq!(set.policies.len(), 1);
+        assert_eq!(set.policy_sets[0].policies[0].id, "room-policy");
+        assert!(set.policy_sets[0].target.is_some());
+
+        let reparsed = PolicySet::from_json(&set.to_json().unwrap()).unwrap();
+        assert_eq!(reparsed.to_json().unwrap(), set.to_json().unwrap());
+
+        // Nested policies are validated too
+        let invalid = PolicySet::new("outer", "Outer")
+            .with_policy_set(PolicySet::new("inner", "Inner").with_policy(Policy::new("", "Nameless")));
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_targets_from_yaml() {
+        let yaml = r#"
+id: room-policy
+version: "1.0.0"
+name: Room Policy
+target:
+  resource_types: [room]
+rules:
+  - id: member-write
+    effect: allow
+    target:
+      action_types: [write]
+      roles: [member, admin, owner]
+    conditions: []
+    priority: 10
+"#;
+
+        let policy = Policy::from_yaml(yaml).unwrap();
+        assert_eq!(policy.target.as_ref().unwrap().resource_types.len(), 1);
+        assert_eq!(policy.rules[0].target.as_ref().unwrap().roles.len(), 3);
+
+        let invalid = Policy::new("p", "P").with_rule(
+            RuleBuilder::new("r")
+                .target(Target::new().with_action_names(vec![String::new()]))
+                .build(),
+        );
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_value_field() {
+        let valid = Policy::new("p", "P").with_rule(
+            RuleBuilder::new("owner-edit")
+                .condition(Condition::compare_fields(
+                    "resource.owner_id",
+                    ConditionOperator::Equals,
+                    "identity.user_id",
+                ))
+                .build(),
+        );
+        assert!(valid.validate().is_ok());
+
+        let unknown_path = Policy::new("p", "P").with_rule(
+            RuleBuilder::new("r")
+                .condition(Condition::compare_fields(
+                    "resource.owner_id",
+                    ConditionOperator::Equals,
+                    "identity.user_idd",
+                ))
+                .build(),
+        );
+        assert!(unknown_path.validate().is_err());
+
+        let yaml = r#"
+id: p
+version: "1.0.0"
+name: P
+rules:
+  - id: both-sides
+    effect: allow
+    conditions:
+      - field: resource.owner_id
+        operator: equals
+        value: u:alice
+        value_field: identity.user_id
+"#;
+        assert!(Policy::from_yaml(yaml).is_err());
+    }
+
+    #[test]
+    fn test_validate_regex() {
+        let policy = |pattern: serde_json::Value| {
+            Policy::new("p", "P").with_rule(
+                RuleBuilder::new("r")
+                    .condition(Condition::new("identity.user_id", ConditionOperator::Matches, pattern))
+                    .build(),
+            )
+        };
+
+        assert!(policy(serde_json::json!("^u:[a-z]+$")).validate().is_ok());
+        assert!(policy(serde_json::json!("^u:(")).validate().is_err());
+        assert!(policy(serde_json::json!(42)).validate().is_err());
+        // Size is left to the limits of the evaluator the policy is loaded into
+        assert!(policy(serde_json::json!("a".repeat(5000))).validate().is_ok());
+    }
 }
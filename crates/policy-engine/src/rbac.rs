@@ -0,0 +1,452 @@
+//! Role hierarchies and permission sets.
+//!
+//! Policies can declare roles, the roles they inherit, and named permission
+//! sets of action-name globs (see `crate::glob`):
+//!
+//! ```yaml
+//! roles:
+//!   moderator:
+//!     inherits: [member]
+//!     permissions: [room.moderate]
+//!   member:
+//!     permissions: [room.write]
+//! permission_sets:
+//!   room.write: [messenger.send, messenger.edit]
+//!   room.moderate: ["messenger.**"]
+//! ```
+//!
+//! The built-in roles always form the chain `owner > admin > member > guest`;
+//! declarations add to it. `role_at_least: moderator` holds when the
+//! request's role is `moderator` or inherits it, directly or transitively.
+//! `has_permission` holds when the role, or a role it inherits, is granted a
+//! permission set that is named by the operand or has a glob matching it, so
+//! both `value: room.write` and `value_field: action.action_name` work.
+//!
+//! Like network lists, declarations are shared by every loaded policy, and
+//! a name declared in several places must be declared identically.
+
+use crate::error::{PolicyError, Result};
+use crate::glob::Glob;
+use crate::policy::{Policy, PolicySet};
+use crate::types::{ConditionExpr, ConditionOperator, Role};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// A policy's declaration of a role.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoleDefinition {
+    /// Roles whose permissions this role has, and which it ranks at least as.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inherits: Vec<String>,
+
+    /// Permission sets granted to this role.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
+}
+
+/// The role and permission set declarations of a policy or policy set.
+#[derive(Default)]
+struct Declarations<'a> {
+    roles: Vec<(&'a String, &'a RoleDefinition)>,
+    permission_sets: Vec<(&'a String, &'a Vec<String>)>,
+}
+
+impl<'a> Declarations<'a> {
+    fn of_policy(policy: &'a Policy) -> Self {
+        let mut declarations = Self::default();
+        declarations.collect_policy(policy);
+        declarations
+    }
+
+    fn of_policy_set(policy_set: &'a PolicySet) -> Self {
+        let mut declarations = Self::default();
+        declarations.collect_policy_set(policy_set);
+        declarations
+    }
+
+    fn collect_policy(&mut self, policy: &'a Policy) {
+        self.roles.extend(&policy.roles);
+        self.permission_sets.extend(&policy.permission_sets);
+    }
+
+    fn collect_policy_set(&mut self, policy_set: &'a PolicySet) {
+        self.roles.extend(&policy_set.roles);
+        self.permission_sets.extend(&policy_set.permission_sets);
+        for policy in &policy_set.policies {
+            self.collect_policy(policy);
+        }
+        for nested in &policy_set.policy_sets {
+            self.collect_policy_set(nested);
+        }
+    }
+}
+
+/// The roles and permission sets known to an evaluator.
+#[derive(Debug, Clone, Default)]
+pub struct RoleRegistry {
+    roles: HashMap<String, RoleDefinition>,
+    permission_sets: HashMap<String, Vec<Glob>>,
+}
+
+impl RoleRegistry {
+    /// Creates a registry with only the built-in roles.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a role. Redeclaring a role differently, referencing an
+    /// unknown role or permission set, or creating an inheritance cycle is
+    /// an error.
+    pub fn define_role(&mut self, name: &str, definition: RoleDefinition) -> Result<()> {
+        let name = name.to_string();
+        *self = self.merged(
+            &Declarations {
+                roles: vec![(&name, &definition)],
+                permission_sets: Vec::new(),
+            },
+            true,
+        )?;
+        Ok(())
+    }
+
+    /// Declares a permission set of action-name globs. Redeclaring a set
+    /// differently is an error.
+    pub fn define_permission_set(&mut self, name: &str, patterns: Vec<String>) -> Result<()> {
+        let name = name.to_string();
+        *self = self.merged(
+            &Declarations {
+                roles: Vec::new(),
+                permission_sets: vec![(&name, &patterns)],
+            },
+            true,
+        )?;
+        Ok(())
+    }
+
+    /// Adds the declarations of a policy.
+    ///
+    /// Nothing is added if any declaration is invalid or conflicts.
+    pub fn add_policy(&mut self, policy: &Policy) -> Result<()> {
+        *self = self.merged(&Declarations::of_policy(policy), true)?;
+        Ok(())
+    }
+
+    /// Adds the declarations of a policy set and its members.
+    ///
+    /// Nothing is added if any declaration is invalid or conflicts.
+    pub fn add_policy_set(&mut self, policy_set: &PolicySet) -> Result<()> {
+        *self = self.merged(&Declarations::of_policy_set(policy_set), true)?;
+        Ok(())
+    }
+
+    /// Checks that a policy's declarations can be added and that every role
+    /// its `role_at_least` conditions name is known.
+    pub fn check_policy(&self, policy: &Policy) -> Result<()> {
+        self.merged(&Declarations::of_policy(policy), true)?
+            .check_references(&policy.condition_exprs())
+    }
+
+    /// Checks that a policy set's declarations can be added and that every
+    /// role its `role_at_least` conditions name is known.
+    pub fn check_policy_set(&self, policy_set: &PolicySet) -> Result<()> {
+        self.merged(&Declarations::of_policy_set(policy_set), true)?
+            .check_references(&policy_set.condition_exprs())
+    }
+
+    /// Returns true if `role` is a built-in or declared role.
+    pub fn is_known(&self, role: &str) -> bool {
+        Role::BUILTIN.iter().any(|builtin| builtin.as_str() == role) || self.roles.contains_key(role)
+    }
+
+    /// Returns true if `role` is `required` or inherits it.
+    ///
+    /// An unknown `role` only ranks as itself; an unknown `required` role is
+    /// an error.
+    pub fn at_least(&self, role: &str, required: &str) -> Result<bool> {
+        if !self.is_known(required) {
+            return Err(PolicyError::ConditionError(format!("Unknown role '{}'", required)));
+        }
+        Ok(self.ancestors(role).contains(required))
+    }
+
+    /// Returns true if `role`, or a role it inherits, is granted a
+    /// permission set named `permission` or with a glob matching it.
+    pub fn has_permission(&self, role: &str, permission: &str) -> bool {
+        self.ancestors(role).into_iter().any(|role| {
+            self.roles.get(role).is_some_and(|definition| {
+                definition.permissions.iter().any(|set| {
+                    set == permission
+                        || self
+                            .permission_sets
+                            .get(set)
+                            .is_some_and(|globs| globs.iter().any(|glob| glob.matches(permission)))
+                })
+            })
+        })
+    }
+
+    /// Evaluates a `role_at_least` or `has_permission` condition.
+    pub fn evaluate(&self, operator: ConditionOperator, left: &serde_json::Value, right: &serde_json::Value) -> Result<bool> {
+        let (Some(role), Some(operand)) = (left.as_str(), right.as_str()) else {
+            return Ok(false);
+        };
+        match operator {
+            ConditionOperator::RoleAtLeast => self.at_least(role, operand),
+            ConditionOperator::HasPermission => Ok(self.has_permission(role, operand)),
+            _ => Err(PolicyError::InternalError(format!("{:?} is not a role operator", operator))),
+        }
+    }
+
+    /// Returns the roles `role` inherits, including itself.
+    fn ancestors<'r>(&'r self, role: &'r str) -> HashSet<&'r str> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![role];
+        while let Some(role) = stack.pop() {
+            if seen.insert(role) {
+                stack.extend(self.parents(role));
+            }
+        }
+        seen
+    }
+
+    fn parents<'r>(&'r self, role: &'r str) -> impl Iterator<Item = &'r str> {
+        let declared = self.roles.get(role).into_iter().flat_map(|d| d.inherits.iter().map(String::as_str));
+        builtin_parent(role).into_iter().chain(declared)
+    }
+
+    /// Returns a copy of the registry with the declarations added. With
+    /// `strict`, references to unknown roles and permission sets are errors;
+    /// without it, only the declarations' own consistency is checked.
+    fn merged(&self, declarations: &Declarations<'_>, strict: bool) -> Result<Self> {
+        let mut merged = self.clone();
+
+        for (name, patterns) in &declarations.permission_sets {
+            check_name(name, "permission set")?;
+            let globs = patterns.iter().map(|p| Glob::new(p)).collect::<Result<Vec<_>>>()?;
+            match merged.permission_sets.get(*name) {
+                Some(existing) if *existing != globs => {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Permission set '{}' is already defined differently",
+                        name
+                    )));
+                }
+                _ => {
+                    merged.permission_sets.insert(name.to_string(), globs);
+                }
+            }
+        }
+
+        for (name, definition) in &declarations.roles {
+            check_name(name, "role")?;
+            match merged.roles.get(*name) {
+                Some(existing) if existing != *definition => {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Role '{}' is already defined differently",
+                        name
+                    )));
+                }
+                _ => {
+                    merged.roles.insert(name.to_string(), (*definition).clone());
+                }
+            }
+        }
+
+        for (name, definition) in &declarations.roles {
+            if strict {
+                if let Some(parent) = definition.inherits.iter().find(|parent| !merged.is_known(parent)) {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Role '{}' inherits unknown role '{}'",
+                        name, parent
+                    )));
+                }
+                if let Some(set) = definition.permissions.iter().find(|set| !merged.permission_sets.contains_key(*set)) {
+                    return Err(PolicyError::ValidationError(format!(
+                        "Role '{}' is granted unknown permission set '{}'",
+                        name, set
+                    )));
+                }
+            }
+            merged.check_acyclic(name)?;
+        }
+
+        Ok(merged)
+    }
+
+    /// Checks that no inheritance path from `role` leads back to it.
+    fn check_acyclic(&self, role: &str) -> Result<()> {
+        let mut stack: Vec<&str> = self.parents(role).collect();
+        let mut seen = HashSet::new();
+        while let Some(parent) = stack.pop() {
+            if parent == role {
+                return Err(PolicyError::ValidationError(format!("Role '{}' inherits itself", role)));
+            }
+            if seen.insert(parent) {
+                stack.extend(self.parents(parent));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_references(&self, exprs: &[&ConditionExpr]) -> Result<()> {
+        for expr in exprs {
+            for condition in expr.leaf_conditions() {
+                if condition.operator != ConditionOperator::RoleAtLeast || !condition.has_literal_value() {
+                    continue;
+                }
+                if let Some(role) = condition.value.as_str().filter(|role| !self.is_known(role)) {
+                    return Err(PolicyError::ValidationError(format!("Unknown role '{}'", role)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The role a built-in role directly inherits.
+fn builtin_parent(role: &str) -> Option<&'static str> {
+    match role {
+        "member" => Some("guest"),
+        "admin" => Some("member"),
+        "owner" => Some("admin"),
+        _ => None,
+    }
+}
+
+fn check_name(name: &str, kind: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'));
+    if !valid {
+        return Err(PolicyError::ValidationError(format!("Invalid {} name '{}'", kind, name)));
+    }
+    Ok(())
+}
+
+/// Returns true if the operator is resolved against the role registry.
+pub fn is_role_operator(operator: ConditionOperator) -> bool {
+    matches!(operator, ConditionOperator::RoleAtLeast | ConditionOperator::HasPermission)
+}
+
+/// Validates a policy's `roles` and `permission_sets` declarations on their
+/// own: names, globs and inheritance cycles. References to roles declared
+/// elsewhere are checked when the policy is loaded.
+pub(crate) fn validate_declarations(
+    roles: &BTreeMap<String, RoleDefinition>,
+    permission_sets: &BTreeMap<String, Vec<String>>,
+    owner: &str,
+) -> Result<()> {
+    let declarations = Declarations {
+        roles: roles.iter().collect(),
+        permission_sets: permission_sets.iter().collect(),
+    };
+    RoleRegistry::new()
+        .merged(&declarations, false)
+        .map(|_| ())
+        .map_err(|e| match e {
+            PolicyError::ValidationError(message) => PolicyError::ValidationError(format!("{} in {}", message, owner)),
+            e => e,
+        })
+}
+
+/// Validates the literal operand of a role operator.
+pub(crate) fn validate_operand(operator: ConditionOperator, value: &serde_json::Value, owner: &str) -> Result<()> {
+    if !value.is_string() {
+        return Err(PolicyError::ValidationError(format!(
+            "Operator {:?} requires a string value in {}",
+            operator, owner
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn role(inherits: &[&str], permissions: &[&str]) -> RoleDefinition {
+        RoleDefinition {
+            inherits: inherits.iter().map(|s| s.to_string()).collect(),
+            permissions: permissions.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let mut registry = RoleRegistry::new();
+        assert!(registry.at_least("owner", "guest").unwrap());
+        assert!(!registry.at_least("member", "admin").unwrap());
+        assert!(registry.at_least("moderator", "member").is_ok());
+
+        registry.define_role("moderator", role(&["member"], &[])).unwrap();
+        registry.define_role("auditor", role(&["guest"], &[])).unwrap();
+        assert!(registry.at_least("moderator", "guest").unwrap());
+        assert!(!registry.at_least("moderator", "auditor").unwrap());
+        assert!(!registry.at_least("admin", "moderator").unwrap());
+        assert!(!registry.at_least("stranger", "guest").unwrap());
+        assert!(registry.at_least("member", "nobody").is_err());
+
+        assert!(registry.define_role("moderator", role(&["member"], &[])).is_ok());
+        assert!(registry.define_role("moderator", role(&["admin"], &[])).is_err());
+        assert!(registry.define_role("ghost", role(&["nobody"], &[])).is_err());
+    }
+
+    #[test]
+    fn test_cycles() {
+        let mut registry = RoleRegistry::new();
+        assert!(registry.define_role("guest", role(&["owner"], &[])).is_err());
+        assert!(registry.define_role("a", role(&["a"], &[])).is_err());
+        assert_eq!(registry.roles.len(), 0);
+
+        let mut roles = BTreeMap::new();
+        roles.insert("a".to_string(), role(&["b"], &[]));
+        roles.insert("b".to_string(), role(&["a"], &[]));
+        assert!(validate_declarations(&roles, &BTreeMap::new(), "policy 'p'").is_err());
+    }
+
+    #[test]
+    fn test_permissions() {
+        let mut registry = RoleRegistry::new();
+        registry
+            .define_permission_set("room.write", vec!["messenger.send".to_string(), "messenger.edit".to_string()])
+            .unwrap();
+        registry
+            .define_permission_set("room.moderate", vec!["messenger.**".to_string()])
+            .unwrap();
+        registry.define_role("member", role(&[], &["room.write"])).unwrap();
+        registry.define_role("moderator", role(&["member"], &["room.moderate"])).unwrap();
+
+        assert!(registry.has_permission("member", "room.write"));
+        assert!(registry.has_permission("member", "messenger.send"));
+        assert!(!registry.has_permission("member", "messenger.delete"));
+        assert!(!registry.has_permission("guest", "messenger.send"));
+        assert!(registry.has_permission("admin", "messenger.send"));
+        assert!(registry.has_permission("moderator", "messenger.delete"));
+        assert!(registry.has_permission("moderator", "room.write"));
+
+        assert!(registry.define_role("viewer", role(&[], &["room.read"])).is_err());
+        assert!(registry
+            .define_permission_set("room.write", vec!["messenger.send".to_string()])
+            .is_err());
+    }
+
+    #[test]
+    fn test_role_names() {
+        use crate::types::Role;
+        use std::collections::HashSet;
+
+        assert_eq!(Role::Custom("admin".to_string()), Role::Admin);
+        assert_eq!(Role::parse("admin"), Role::Admin);
+        let roles: HashSet<Role> = [Role::Admin, Role::Custom("admin".to_string())].into_iter().collect();
+        assert_eq!(roles.len(), 1);
+
+        assert!(Role::Owner > Role::Admin);
+        assert!(Role::Custom("owner".to_string()) > Role::Guest);
+        let moderator = Role::Custom("moderator".to_string());
+        assert_eq!(moderator.partial_cmp(&Role::Owner), None);
+        assert!(moderator.has_permission(moderator.clone()));
+        assert!(!moderator.has_permission(Role::Guest));
+        assert!(Role::Admin.has_permission(Role::Custom("member".to_string())));
+    }
+}
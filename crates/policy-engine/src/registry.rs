@@ -0,0 +1,266 @@
+//! Declared resource and action types.
+//!
+//! `ResourceType` and `ActionType` accept any name, so requests for types
+//! the engine does not know about (a Blueprint `wallet`, say) still parse.
+//! Policies that use such types declare them:
+//!
+//! ```yaml
+//! types:
+//!   resource_types: [wallet, container, workflow, sandbox]
+//!   action_types: [transfer]
+//! ```
+//!
+//! When a policy is loaded, every type named in its targets, or compared
+//! against `resource.resource_type` or `action.action_type` in a condition,
+//! must be well-known or declared, so a misspelt type is rejected instead of
+//! silently never matching. Declarations are shared by every loaded policy.
+
+use crate::error::{PolicyError, Result};
+use crate::policy::{Policy, PolicySet};
+use crate::target::Target;
+use crate::types::{ActionType, ConditionExpr, ConditionOperator, ResourceType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// The types a policy declares beyond the well-known ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TypeDeclarations {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resource_types: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub action_types: Vec<String>,
+}
+
+impl TypeDeclarations {
+    /// Returns true if nothing is declared.
+    pub fn is_empty(&self) -> bool {
+        self.resource_types.is_empty() && self.action_types.is_empty()
+    }
+
+    /// Checks that every declared name is valid.
+    pub fn validate(&self) -> Result<()> {
+        for name in self.resource_types.iter().chain(&self.action_types) {
+            check_name(name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The resource and action types known to an evaluator.
+#[derive(Debug, Clone, Default)]
+pub struct TypeRegistry {
+    resource_types: HashSet<String>,
+    action_types: HashSet<String>,
+}
+
+impl TypeRegistry {
+    /// Creates a registry with only the well-known types.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a resource type.
+    pub fn declare_resource_type(&mut self, name: &str) -> Result<()> {
+        check_name(name)?;
+        self.resource_types.insert(name.to_string());
+        Ok(())
+    }
+
+    /// Declares an action type.
+    pub fn declare_action_type(&mut self, name: &str) -> Result<()> {
+        check_name(name)?;
+        self.action_types.insert(name.to_string());
+        Ok(())
+    }
+
+    /// Adds the types declared by a policy.
+    ///
+    /// Nothing is added if any name is invalid.
+    pub fn add_policy(&mut self, policy: &Policy) -> Result<()> {
+        self.add_all(&[&policy.types])
+    }
+
+    /// Adds the types declared by a policy set and its members.
+    ///
+    /// Nothing is added if any name is invalid.
+    pub fn add_policy_set(&mut self, policy_set: &PolicySet) -> Result<()> {
+        let mut declared = Vec::new();
+        collect_policy_set_types(policy_set, &mut declared);
+        self.add_all(&declared)
+    }
+
+    /// Checks that every type a policy uses is known, either already or by
+    /// the policy's own declarations.
+    pub fn check_policy(&self, policy: &Policy) -> Result<()> {
+        let mut merged = self.clone();
+        merged.add_policy(policy)?;
+        let mut targets = Vec::new();
+        collect_policy_targets(policy, &mut targets);
+        merged.check_references(&targets, &policy.condition_exprs())
+    }
+
+    /// Checks that every type a policy set uses is known, either already or
+    /// by the declarations in the set.
+    pub fn check_policy_set(&self, policy_set: &PolicySet) -> Result<()> {
+        let mut merged = self.clone();
+        merged.add_policy_set(policy_set)?;
+        let mut targets = Vec::new();
+        collect_policy_set_targets(policy_set, &mut targets);
+        merged.check_references(&targets, &policy_set.condition_exprs())
+    }
+
+    /// Returns true if the resource type is well-known or declared.
+    pub fn is_known_resource_type(&self, resource_type: &ResourceType) -> bool {
+        resource_type.is_well_known() || self.resource_types.contains(resource_type.as_str())
+    }
+
+    /// Returns true if the action type is well-known or declared.
+    pub fn is_known_action_type(&self, action_type: &ActionType) -> bool {
+        action_type.is_well_known() || self.action_types.contains(action_type.as_str())
+    }
+
+    fn add_all(&mut self, declared: &[&TypeDeclarations]) -> Result<()> {
+        for types in declared {
+            types.validate()?;
+        }
+        for types in declared {
+            self.resource_types.extend(types.resource_types.iter().cloned());
+            self.action_types.extend(types.action_types.iter().cloned());
+        }
+        Ok(())
+    }
+
+    fn check_references(&self, targets: &[&Target], exprs: &[&ConditionExpr]) -> Result<()> {
+        for target in targets {
+            if let Some(t) = target.resource_types.iter().find(|t| !self.is_known_resource_type(t)) {
+                return Err(unknown("resource", t.as_str()));
+            }
+            if let Some(t) = target.action_types.iter().find(|t| !self.is_known_action_type(t)) {
+                return Err(unknown("action", t.as_str()));
+            }
+        }
+
+        for expr in exprs {
+            for condition in expr.leaf_conditions() {
+                let compares_names = matches!(
+                    condition.operator,
+                    ConditionOperator::Equals | ConditionOperator::NotEquals | ConditionOperator::In | ConditionOperator::NotIn
+                );
+                if !compares_names || !condition.has_literal_value() || condition.expr.is_some() {
+                    continue;
+                }
+                let names: Vec<&str> = match &condition.value {
+                    serde_json::Value::Array(items) => items.iter().filter_map(|v| v.as_str()).collect(),
+                    value => value.as_str().into_iter().collect(),
+                };
+                for name in names {
+                    match condition.field.as_str() {
+                        "resource.resource_type" if !self.is_known_resource_type(&ResourceType::parse(name)) => {
+                            return Err(unknown("resource", name));
+                        }
+                        "action.action_type" if !self.is_known_action_type(&ActionType::parse(name)) => {
+                            return Err(unknown("action", name));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn unknown(kind: &str, name: &str) -> PolicyError {
+    PolicyError::ValidationError(format!("Unknown {} type '{}'; declare it under types.{}_types", kind, name, kind))
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.'));
+    if !valid {
+        return Err(PolicyError::ValidationError(format!("Invalid type name '{}'", name)));
+    }
+    Ok(())
+}
+
+fn collect_policy_set_types<'a>(policy_set: &'a PolicySet, declared: &mut Vec<&'a TypeDeclarations>) {
+    declared.push(&policy_set.types);
+    declared.extend(policy_set.policies.iter().map(|policy| &policy.types));
+    for nested in &policy_set.policy_sets {
+        collect_policy_set_types(nested, declared);
+    }
+}
+
+fn collect_policy_targets<'a>(policy: &'a Policy, targets: &mut Vec<&'a Target>) {
+    targets.extend(&policy.target);
+    targets.extend(policy.rules.iter().filter_map(|rule| rule.target.as_ref()));
+}
+
+fn collect_policy_set_targets<'a>(policy_set: &'a PolicySet, targets: &mut Vec<&'a Target>) {
+    targets.extend(&policy_set.target);
+    for policy in &policy_set.policies {
+        collect_policy_targets(policy, targets);
+    }
+    for nested in &policy_set.policy_sets {
+        collect_policy_set_targets(nested, targets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_types() {
+        let wallet: ResourceType = serde_json::from_str("\"wallet\"").unwrap();
+        assert_eq!(wallet, ResourceType::Custom("wallet".to_string()));
+        assert_eq!(serde_json::to_string(&wallet).unwrap(), "\"wallet\"");
+        assert_eq!(serde_json::from_str::<ResourceType>("\"room\"").unwrap(), ResourceType::Room);
+        assert_eq!(ResourceType::Custom("room".to_string()), ResourceType::Room);
+        assert!(ResourceType::parse("room").is_well_known());
+        assert!(!wallet.is_well_known());
+    }
+
+    #[test]
+    fn test_registry() {
+        let policy = Policy::from_yaml(
+            r#"
+id: wallets
+version: "1.0.0"
+name: Wallets
+types:
+  resource_types: [wallet]
+  action_types: [transfer]
+rules:
+  - id: transfers
+    effect: allow
+    target:
+      resource_types: [wallet]
+    conditions:
+      - field: action.action_type
+        operator: in
+        value: [transfer, read]
+    priority: 10
+"#,
+        )
+        .unwrap();
+
+        let mut registry = TypeRegistry::new();
+        registry.check_policy(&policy).unwrap();
+        assert!(!registry.is_known_resource_type(&ResourceType::parse("wallet")));
+        registry.add_policy(&policy).unwrap();
+        assert!(registry.is_known_resource_type(&ResourceType::parse("wallet")));
+        assert!(registry.is_known_action_type(&ActionType::parse("transfer")));
+
+        let mut undeclared = policy.clone();
+        undeclared.types = TypeDeclarations::default();
+        assert!(TypeRegistry::new().check_policy(&undeclared).is_err());
+        assert!(registry.check_policy(&undeclared).is_ok());
+
+        assert!(registry.declare_resource_type("Bad Name").is_err());
+    }
+}
@@ -0,0 +1,438 @@
+//! Strict JSON parsing for canonicalization.
+//!
+//! `serde_json` resolves ambiguous input silently: a repeated object key
+//! keeps its last value and a lone surrogate escape becomes U+FFFD, so two
+//! different payloads can canonicalize, and hash, identically. `parse`
+//! rejects both instead, reporting the byte offset of the offending token.
+//!
+//! Numbers are read as the given canonicalization mode reads them. `V1`
+//! reads them exactly as `serde_json` always has, so it canonicalizes them
+//! as it always has: integers that fit 64 bits keep every digit and other
+//! numbers become a double, which for some long literals is one ULP off the
+//! nearest. `Jcs` reads every number as the nearest double, as `JSON.parse`
+//! does. Either way, a literal whose canonical form would have a different
+//! value is rejected, so `0.1000000000000000000001` is not accepted as
+//! `0.1`, nor, under `Jcs`, `9007199254740993` as `9007199254740992`:
+//! distinct numbers never share a canonical form. So are numbers beyond the
+//! range of a double.
+//!
+//! Input must otherwise be RFC 8259 JSON, nested at most 128 levels deep.
+
+use crate::canonicalization::CanonicalizationMode;
+use crate::error::{PolicyError, Result};
+use serde_json::{Map, Number, Value};
+
+/// The deepest nesting of arrays and objects accepted.
+pub const MAX_DEPTH: usize = 128;
+
+/// Parses JSON text, reading numbers as `mode` does and rejecting input
+/// whose value would be ambiguous.
+pub fn parse(json: &str, mode: CanonicalizationMode) -> Result<Value> {
+    let mut parser = Parser {
+        mode,
+        input: json,
+        bytes: json.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    parser.skip_whitespace();
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.bytes.len() {
+        return Err(parser.error_at(parser.pos, "Trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    mode: CanonicalizationMode,
+    input: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error_at(&self, offset: usize, message: impl std::fmt::Display) -> PolicyError {
+        PolicyError::CanonicalizationError(format!("{} at byte {}", message, offset))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.peek() != Some(byte) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn unexpected(&self) -> PolicyError {
+        match self.input[self.pos..].chars().next() {
+            Some(c) => self.error_at(self.pos, format!("Unexpected character {:?}", c)),
+            None => self.error_at(self.pos, "Unexpected end of input"),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value>) -> Result<Value> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error_at(self.pos, format!("Nesting deeper than {} levels", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn literal(&mut self, text: &str, value: Value) -> Result<Value> {
+        if !self.input[self.pos..].starts_with(text) {
+            return Err(self.unexpected());
+        }
+        self.pos += text.len();
+        Ok(value)
+    }
+
+    fn object(&mut self) -> Result<Value> {
+        self.expect(b'{')?;
+        let mut map = Map::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(map));
+        }
+        loop {
+            self.skip_whitespace();
+            let key_offset = self.pos;
+            if self.peek() != Some(b'"') {
+                return Err(self.unexpected());
+            }
+            let key = self.string()?;
+            if map.contains_key(&key) {
+                return Err(self.error_at(key_offset, format!("Duplicate key {:?}", key)));
+            }
+            self.skip_whitespace();
+            self.expect(b':')?;
+            self.skip_whitespace();
+            let value = self.value()?;
+            map.insert(key, value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(map));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            self.skip_whitespace();
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while self.peek().is_some_and(|b| b != b'"' && b != b'\\' && b >= 0x20) {
+                self.pos += 1;
+            }
+            out.push_str(&self.input[start..self.pos]);
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => out.push(self.escape()?),
+                Some(_) => return Err(self.error_at(self.pos, "Unescaped control character in string")),
+                None => return Err(self.error_at(self.pos, "Unterminated string")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char> {
+        let offset = self.pos;
+        self.pos += 1;
+        let c = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.pos += 1;
+                let unit = self.hex4()?;
+                return match unit {
+                    0xD800..=0xDBFF => {
+                        let low_offset = self.pos;
+                        if !self.input[self.pos..].starts_with("\\u") {
+                            return Err(self.error_at(offset, format!("Lone surrogate \\u{:04x}", unit)));
+                        }
+                        self.pos += 2;
+                        let low = self.hex4()?;
+                        if !(0xDC00..=0xDFFF).contains(&low) {
+                            return Err(self.error_at(offset, format!("Lone surrogate \\u{:04x}", unit)));
+                        }
+                        let code = 0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00);
+                        char::from_u32(code).ok_or_else(|| self.error_at(low_offset, "Invalid surrogate pair"))
+                    }
+                    0xDC00..=0xDFFF => Err(self.error_at(offset, format!("Lone surrogate \\u{:04x}", unit))),
+                    _ => char::from_u32(unit).ok_or_else(|| self.error_at(offset, "Invalid escape")),
+                };
+            }
+            _ => return Err(self.error_at(offset, "Invalid escape")),
+        };
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error_at(self.pos, "Invalid \\u escape"))?;
+        self.pos += 4;
+        u32::from_str_radix(digits, 16).map_err(|_| self.error_at(self.pos - 4, "Invalid \\u escape"))
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let start = self.pos;
+        let negative = self.peek() == Some(b'-');
+        if negative {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(self.unexpected()),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.required_digits()?;
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            self.required_digits()?;
+        }
+        let text = &self.input[start..self.pos];
+        let out_of_range = || self.error_at(start, format!("Number {} is out of range", text));
+        let mut number = text.parse::<Number>().map_err(|_| out_of_range())?;
+        let canonical = match self.mode {
+            CanonicalizationMode::V1 => number.to_string(),
+            CanonicalizationMode::Jcs => {
+                if number.is_f64() {
+                    // The standard library rounds correctly, where serde_json may not
+                    number = text.parse::<f64>().ok().and_then(Number::from_f64).ok_or_else(out_of_range)?;
+                }
+                let double = number.as_f64().ok_or_else(out_of_range)?;
+                crate::canonicalization::format_es_number(double)?
+            }
+        };
+        if Decimal::parse(text) != Decimal::parse(&canonical) {
+            return Err(self.error_at(start, format!("Number {} would canonicalize as {}", text, canonical)));
+        }
+        Ok(Value::Number(number))
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+    }
+
+    fn required_digits(&mut self) -> Result<()> {
+        if !self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            return Err(self.unexpected());
+        }
+        self.digits();
+        Ok(())
+    }
+}
+
+/// The exact value of a number literal: `digits × 10^exponent`, with no
+/// leading or trailing zeros in `digits`. Zero is `0`, whatever its sign.
+#[derive(Debug, PartialEq, Eq)]
+struct Decimal {
+    negative: bool,
+    digits: String,
+    exponent: i64,
+}
+
+impl Decimal {
+    fn parse(text: &str) -> Self {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (mantissa, exponent) = text.split_once(['e', 'E']).unwrap_or((text, "0"));
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        // An exponent too large for i64 is out of range for a double anyway
+        let exponent = exponent.trim_start_matches('+').parse::<i64>().unwrap_or(i64::MAX / 2);
+
+        let digits = format!("{}{}", integer, fraction);
+        let digits = digits.trim_start_matches('0');
+        let trimmed = digits.trim_end_matches('0');
+        if trimmed.is_empty() {
+            return Self {
+                negative: false,
+                digits: String::new(),
+                exponent: 0,
+            };
+        }
+        Self {
+            negative,
+            digits: trimmed.to_string(),
+            exponent: exponent - fraction.len() as i64 + (digits.len() - trimmed.len()) as i64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_v1(json: &str) -> Result<Value> {
+        parse(json, CanonicalizationMode::V1)
+    }
+
+    fn error(result: Result<Value>) -> String {
+        match result {
+            Err(PolicyError::CanonicalizationError(message)) => message,
+            other => panic!("expected a canonicalization error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_matches_serde_json() {
+        let json = r#" {"a": [1, -2, 3.5, 1e3, -0, true, false, null], "b": {"c": "é😀\n"}, "": {}} "#;
+        assert_eq!(parse_v1(json).unwrap(), serde_json::from_str::<Value>(json).unwrap());
+        assert_eq!(parse_v1("18446744073709551615").unwrap(), serde_json::json!(u64::MAX));
+        assert_eq!(parse_v1("-9223372036854775808").unwrap(), serde_json::json!(i64::MIN));
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        assert_eq!(error(parse_v1(r#"{"a":1,"a":2}"#)), "Duplicate key \"a\" at byte 7");
+        assert_eq!(error(parse_v1(r#"{"x":{"a":1, "a":2}}"#)), "Duplicate key \"a\" at byte 13");
+        assert!(parse_v1(r#"{"a":{"a":1},"b":{"a":2}}"#).is_ok());
+    }
+
+    #[test]
+    fn test_surrogates() {
+        assert_eq!(error(parse_v1(r#""x\ud800""#)), "Lone surrogate \\ud800 at byte 2");
+        assert_eq!(error(parse_v1(r#""\ud800A""#)), "Lone surrogate \\ud800 at byte 1");
+        assert_eq!(error(parse_v1(r#""\udc00""#)), "Lone surrogate \\udc00 at byte 1");
+        assert_eq!(parse_v1(r#""😀""#).unwrap(), Value::from("\u{1F600}"));
+    }
+
+    #[test]
+    fn test_numbers() {
+        for json in ["18446744073709552000", "9007199254740993", "0.1", "1e-7", "-0", "1.50", "1E30"] {
+            assert_eq!(parse_v1(json).unwrap(), serde_json::from_str::<Value>(json).unwrap(), "{}", json);
+        }
+        assert_eq!(parse_v1("9007199254740993").unwrap(), serde_json::json!(9007199254740993u64));
+        assert_eq!(error(parse_v1("[1e400]")), "Number 1e400 is out of range at byte 1");
+        assert!(parse_v1("-1e309").is_err());
+
+        // Jcs reads the nearest double, where serde_json may be one ULP off
+        let literal = "3.0261999441573203e-52";
+        assert_eq!(parse(literal, CanonicalizationMode::Jcs).unwrap().as_f64(), literal.parse().ok());
+        assert_eq!(
+            error(parse_v1(literal)),
+            "Number 3.0261999441573203e-52 would canonicalize as 3.0261999441573207e-52 at byte 0"
+        );
+
+        // Distinct literals never share a canonical form: one is rejected
+        for (exact, lossy) in [("0.1", "0.1000000000000000000001"), ("18446744073709552000", "18446744073709551616")] {
+            for mode in [CanonicalizationMode::V1, CanonicalizationMode::Jcs] {
+                assert!(parse(exact, mode).is_ok(), "{}", exact);
+                assert!(parse(lossy, mode).is_err(), "{}", lossy);
+            }
+        }
+        assert_eq!(
+            error(parse_v1("[0.1000000000000000000001]")),
+            "Number 0.1000000000000000000001 would canonicalize as 0.1 at byte 1"
+        );
+        assert!(parse_v1("1e-400").is_err());
+
+        // V1 keeps every digit of a 64-bit integer; Jcs would round it
+        let canonical = |json, mode| crate::canonicalization::canonicalize_str(json, mode).unwrap();
+        assert_ne!(canonical("9007199254740993", CanonicalizationMode::V1), canonical("9007199254740992", CanonicalizationMode::V1));
+        assert_eq!(
+            error(parse("9007199254740993", CanonicalizationMode::Jcs)),
+            "Number 9007199254740993 would canonicalize as 9007199254740992 at byte 0"
+        );
+    }
+
+    #[test]
+    fn test_decimal() {
+        assert_eq!(Decimal::parse("1.50"), Decimal::parse("15e-1"));
+        assert_eq!(Decimal::parse("100"), Decimal::parse("1e+2"));
+        assert_eq!(Decimal::parse("-0.0"), Decimal::parse("0"));
+        assert_ne!(Decimal::parse("-1"), Decimal::parse("1"));
+        assert_ne!(Decimal::parse("0.1"), Decimal::parse("0.10000000000000001"));
+    }
+
+    #[test]
+    fn test_syntax() {
+        assert_eq!(error(parse_v1("[1,]")), "Unexpected character ']' at byte 3");
+        assert_eq!(error(parse_v1("{} x")), "Trailing characters at byte 3");
+        assert_eq!(error(parse_v1("\"a\nb\"")), "Unescaped control character in string at byte 2");
+        assert_eq!(error(parse_v1("01")), "Trailing characters at byte 1");
+        assert!(parse_v1(r#""\x""#).is_err());
+        assert!(parse_v1("1.").is_err());
+        assert!(parse_v1("[").is_err());
+        assert!(parse_v1(&"[".repeat(MAX_DEPTH + 1)).unwrap_err().to_string().contains("Nesting"));
+        assert!(parse_v1(&format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH))).is_ok());
+    }
+}
@@ -0,0 +1,186 @@
+//! Targets: applicability filters checked before conditions.
+
+use crate::context::EvaluationContext;
+use crate::error::{PolicyError, Result};
+use crate::types::{ActionType, ConditionExpr, ResourceType, Role};
+use serde::{Deserialize, Serialize};
+
+/// Describes which requests a policy set, policy or rule applies to.
+///
+/// Every non-empty list must contain the request's value, and every
+/// condition must hold. An empty target applies to every request.
+///
+/// ```yaml
+/// target:
+///   resource_types: [room]
+///   action_types: [read, write]
+///   action_names: ["messenger.*"]
+///   roles: [member, admin, owner]
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Target {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resource_types: Vec<ResourceType>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub action_types: Vec<ActionType>,
+
+    /// Action names. An entry ending in `*` matches by prefix.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub action_names: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<Role>,
+
+    /// Additional conditions, all of which must hold.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<ConditionExpr>,
+}
+
+impl Target {
+    /// Creates an empty target that applies to every request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restricts the target to the given resource types.
+    pub fn with_resource_types(mut self, resource_types: Vec<ResourceType>) -> Self {
+        self.resource_types = resource_types;
+        self
+    }
+
+    /// Restricts the target to the given action types.
+    pub fn with_action_types(mut self, action_types: Vec<ActionType>) -> Self {
+        self.action_types = action_types;
+        self
+    }
+
+    /// Restricts the target to the given action names or `prefix*` patterns.
+    pub fn with_action_names(mut self, action_names: Vec<String>) -> Self {
+        self.action_names = action_names;
+        self
+    }
+
+    /// Restricts the target to the given roles.
+    pub fn with_roles(mut self, roles: Vec<Role>) -> Self {
+        self.roles = roles;
+        self
+    }
+
+    /// Returns true if the target places no restrictions.
+    pub fn is_empty(&self) -> bool {
+        self.resource_types.is_empty()
+            && self.action_types.is_empty()
+            && self.action_names.is_empty()
+            && self.roles.is_empty()
+            && self.conditions.is_empty()
+    }
+
+    /// Checks the attribute lists (everything except `conditions`) against
+    /// the request.
+    pub fn matches_attributes(&self, context: &EvaluationContext) -> bool {
+        if !self.resource_types.is_empty()
+            && !self.resource_types.contains(&context.resource.resource_type)
+        {
+            return false;
+        }
+
+        if !self.action_types.is_empty() && !self.action_types.contains(&context.action.action_type) {
+            return false;
+        }
+
+        if !self.action_names.is_empty()
+            && !self
+                .action_names
+                .iter()
+                .any(|pattern| action_name_matches(pattern, &context.action.action_name))
+        {
+            return false;
+        }
+
+        if !self.roles.is_empty() && !context.role.as_ref().is_some_and(|role| self.roles.contains(role)) {
+            return false;
+        }
+
+        true
+    }
+
+    /// Validates the target's own fields (conditions are validated by the owner).
+    pub fn validate(&self, owner: &str) -> Result<()> {
+        if self.action_names.iter().any(|name| name.is_empty() || name == "*") {
+            return Err(PolicyError::ValidationError(format!(
+                "Target action names must be non-empty and not a bare '*' in {}",
+                owner
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Matches an action name against an exact name or a `prefix*` pattern.
+fn action_name_matches(pattern: &str, action_name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => action_name.starts_with(prefix),
+        None => pattern == action_name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Action, Identity, Resource, Tenant, TenantType};
+
+    fn create_test_context() -> EvaluationContext {
+        EvaluationContext::new(
+            Identity {
+                user_id: "u:test".to_string(),
+                email: "test@example.com".to_string(),
+                email_domain: "example.com".to_string(),
+                groups: vec![],
+                is_service: false,
+            },
+            Tenant {
+                tenant_id: "t:example.com".to_string(),
+                tenant_type: TenantType::Customer,
+            },
+            Resource {
+                resource_type: ResourceType::Room,
+                resource_id: "r:general".to_string(),
+                owner_id: None,
+                agreement_id: None,
+            },
+            Action {
+                action_type: ActionType::Write,
+                action_name: "messenger.send".to_string(),
+            },
+        )
+        .with_role(Role::Member)
+    }
+
+    #[test]
+    fn test_matches_attributes() {
+        let ctx = create_test_context();
+
+        assert!(Target::new().matches_attributes(&ctx));
+        assert!(Target::new()
+            .with_resource_types(vec![ResourceType::Room])
+            .with_action_names(vec!["messenger.*".to_string()])
+            .with_roles(vec![Role::Member, Role::Admin])
+            .matches_attributes(&ctx));
+        assert!(!Target::new()
+            .with_action_types(vec![ActionType::Read])
+            .matches_attributes(&ctx));
+        assert!(!Target::new()
+            .with_action_names(vec!["messenger.edit".to_string()])
+            .matches_attributes(&ctx));
+        assert!(!Target::new()
+            .with_roles(vec![Role::Owner])
+            .matches_attributes(&ctx));
+    }
+
+    #[test]
+    fn test_validate() {
+        let target = Target::new().with_action_names(vec!["*".to_string()]);
+        assert!(target.validate("policy 'p'").is_err());
+    }
+}
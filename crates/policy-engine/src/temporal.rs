@@ -0,0 +1,453 @@
+//! Date and time operands for time-window conditions.
+//!
+//! Datetimes are RFC 3339 strings and durations are ISO 8601 durations made
+//! of weeks, days, hours, minutes and seconds (`P1W`, `P2DT12H`, `PT15M`).
+//! Relative operators (`within_last`, `older_than`) and rule validity
+//! periods are evaluated against `environment.now`.
+//!
+//! Day-of-week and time-of-day predicates take an object value with an
+//! optional IANA timezone, given literally or read from the context:
+//!
+//! ```yaml
+//! - field: environment.now
+//!   operator: time_of_day_between
+//!   value: { start: "09:00", end: "17:00", timezone_field: attributes.timezone }
+//! - field: environment.now
+//!   operator: day_of_week_in
+//!   value: { days: [mon, tue, wed, thu, fri], timezone: Europe/Berlin }
+//! ```
+//!
+//! Literal window operands are compiled, timezone included, when a policy
+//! is loaded (see `WindowCache`).
+
+use crate::context::ContextAt;
+use crate::error::{PolicyError, Result};
+use crate::policy::{Policy, PolicySet};
+use crate::types::{ConditionExpr, ConditionOperator};
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Parses an RFC 3339 datetime.
+pub fn parse_datetime(value: &serde_json::Value) -> Result<DateTime<Utc>> {
+    let text = value
+        .as_str()
+        .ok_or_else(|| PolicyError::ConditionError(format!("Expected an RFC 3339 datetime, got {}", value)))?;
+    DateTime::parse_from_rfc3339(text)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|e| PolicyError::ConditionError(format!("Invalid datetime '{}': {}", text, e)))
+}
+
+/// Parses an ISO 8601 duration of the form `PnW` or `PnDTnHnMnS`.
+pub fn parse_duration(value: &serde_json::Value) -> Result<Duration> {
+    let invalid = || PolicyError::ConditionError(format!("Invalid ISO 8601 duration {}", value));
+    let text = value.as_str().ok_or_else(invalid)?;
+    let body = text.strip_prefix('P').ok_or_else(invalid)?;
+    if body.is_empty() || body == "T" {
+        return Err(invalid());
+    }
+
+    let mut total = Duration::zero();
+    let mut in_time = false;
+    let mut digits = String::new();
+    for c in body.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            'T' if !in_time && digits.is_empty() => in_time = true,
+            _ => {
+                let n: i64 = digits.parse().map_err(|_| invalid())?;
+                digits.clear();
+                let part = match (in_time, c) {
+                    (false, 'W') => Duration::try_weeks(n),
+                    (false, 'D') => Duration::try_days(n),
+                    (true, 'H') => Duration::try_hours(n),
+                    (true, 'M') => Duration::try_minutes(n),
+                    (true, 'S') => Duration::try_seconds(n),
+                    _ => None,
+                };
+                total = part.and_then(|part| total.checked_add(&part)).ok_or_else(invalid)?;
+            }
+        }
+    }
+    if !digits.is_empty() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+/// The object value of `day_of_week_in` and `time_of_day_between`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WindowSpec {
+    #[serde(default)]
+    days: Vec<String>,
+    #[serde(default)]
+    start: Option<String>,
+    #[serde(default)]
+    end: Option<String>,
+    #[serde(default)]
+    timezone: Option<String>,
+    #[serde(default)]
+    timezone_field: Option<String>,
+}
+
+impl WindowSpec {
+    fn parse(value: &serde_json::Value) -> Result<Self> {
+        let spec: WindowSpec = serde_json::from_value(value.clone())
+            .map_err(|e| PolicyError::ConditionError(format!("Invalid time window {}: {}", value, e)))?;
+        if spec.timezone.is_some() && spec.timezone_field.is_some() {
+            return Err(PolicyError::ConditionError(
+                "Time window sets both timezone and timezone_field".to_string(),
+            ));
+        }
+        Ok(spec)
+    }
+
+    fn days(&self) -> Result<Vec<Weekday>> {
+        if self.days.is_empty() {
+            return Err(PolicyError::ConditionError("day_of_week_in requires at least one day".to_string()));
+        }
+        self.days
+            .iter()
+            .map(|day| {
+                day.parse::<Weekday>()
+                    .map_err(|_| PolicyError::ConditionError(format!("Invalid day of week '{}'", day)))
+            })
+            .collect()
+    }
+
+    fn bounds(&self) -> Result<(NaiveTime, NaiveTime)> {
+        let parse = |name: &str, time: &Option<String>| {
+            let time = time
+                .as_deref()
+                .ok_or_else(|| PolicyError::ConditionError(format!("time_of_day_between requires '{}'", name)))?;
+            NaiveTime::parse_from_str(time, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+                .map_err(|_| PolicyError::ConditionError(format!("Invalid time of day '{}'", time)))
+        };
+        Ok((parse("start", &self.start)?, parse("end", &self.end)?))
+    }
+}
+
+/// A compiled `day_of_week_in` or `time_of_day_between` operand.
+#[derive(Debug, Clone)]
+pub struct Window {
+    kind: WindowKind,
+    timezone: WindowTimezone,
+}
+
+#[derive(Debug, Clone)]
+enum WindowKind {
+    Days(Vec<Weekday>),
+    /// The start is inclusive and the end exclusive.
+    TimeOfDay(NaiveTime, NaiveTime),
+}
+
+#[derive(Debug, Clone)]
+enum WindowTimezone {
+    Fixed(Tz),
+    /// Read from the context at evaluation time.
+    Field(String),
+}
+
+impl Window {
+    /// Compiles the operand of a window operator.
+    pub fn compile(operator: ConditionOperator, value: &serde_json::Value) -> Result<Self> {
+        let spec = WindowSpec::parse(value)?;
+        let kind = match operator {
+            ConditionOperator::DayOfWeekIn => WindowKind::Days(spec.days()?),
+            ConditionOperator::TimeOfDayBetween => {
+                let (start, end) = spec.bounds()?;
+                WindowKind::TimeOfDay(start, end)
+            }
+            _ => return Err(PolicyError::InternalError(format!("{:?} is not a window operator", operator))),
+        };
+        let timezone = match (spec.timezone, spec.timezone_field) {
+            (Some(timezone), _) => WindowTimezone::Fixed(parse_timezone(&timezone)?),
+            (None, Some(field)) => WindowTimezone::Field(field),
+            (None, None) => WindowTimezone::Fixed(Tz::UTC),
+        };
+        Ok(Self { kind, timezone })
+    }
+
+    /// Returns true if `at`, in the window's timezone, falls in the window.
+    pub fn contains(&self, at: DateTime<Utc>, context: &ContextAt) -> Result<bool> {
+        let timezone = match &self.timezone {
+            WindowTimezone::Fixed(timezone) => *timezone,
+            WindowTimezone::Field(field) => {
+                let value = context
+                    .get_value(field)
+                    .ok_or_else(|| PolicyError::ConditionError(format!("Field '{}' not found", field)))?;
+                let name = value.as_str().ok_or_else(|| {
+                    PolicyError::ConditionError(format!("Timezone in '{}' must be a string", field))
+                })?;
+                parse_timezone(name)?
+            }
+        };
+        let local = at.with_timezone(&timezone);
+        Ok(match &self.kind {
+            WindowKind::Days(days) => days.contains(&local.weekday()),
+            // A window whose end precedes its start wraps past midnight.
+            WindowKind::TimeOfDay(start, end) if start <= end => *start <= local.time() && local.time() < *end,
+            WindowKind::TimeOfDay(start, end) => local.time() >= *start || local.time() < *end,
+        })
+    }
+}
+
+/// Windows compiled from the literal operands of the `day_of_week_in` and
+/// `time_of_day_between` conditions of loaded policies.
+#[derive(Debug, Clone, Default)]
+pub struct WindowCache {
+    compiled: HashMap<(ConditionOperator, String), Window>,
+}
+
+impl WindowCache {
+    /// Compiles every literal window operand in a policy.
+    ///
+    /// Nothing is added if any operand is invalid.
+    pub fn add_policy(&mut self, policy: &Policy) -> Result<()> {
+        self.add_exprs(&policy.condition_exprs())
+    }
+
+    /// Compiles every literal window operand in a policy set.
+    ///
+    /// Nothing is added if any operand is invalid.
+    pub fn add_policy_set(&mut self, policy_set: &PolicySet) -> Result<()> {
+        self.add_exprs(&policy_set.condition_exprs())
+    }
+
+    /// Returns the window for an operand, compiling it if it was not seen at
+    /// load time (e.g. an operand read from the context).
+    pub fn get(&self, operator: ConditionOperator, value: &serde_json::Value) -> Result<Cow<'_, Window>> {
+        match self.compiled.get(&(operator, value.to_string())) {
+            Some(window) => Ok(Cow::Borrowed(window)),
+            None => Window::compile(operator, value).map(Cow::Owned),
+        }
+    }
+
+    /// Returns the number of compiled windows.
+    pub fn len(&self) -> usize {
+        self.compiled.len()
+    }
+
+    /// Returns true if no windows have been compiled.
+    pub fn is_empty(&self) -> bool {
+        self.compiled.is_empty()
+    }
+
+    fn add_exprs(&mut self, exprs: &[&ConditionExpr]) -> Result<()> {
+        let mut compiled = Vec::new();
+        for expr in exprs {
+            for condition in expr.leaf_conditions() {
+                let is_window = matches!(
+                    condition.operator,
+                    ConditionOperator::DayOfWeekIn | ConditionOperator::TimeOfDayBetween
+                );
+                if !is_window || !condition.has_literal_value() {
+                    continue;
+                }
+                let window = Window::compile(condition.operator, &condition.value)
+                    .map_err(|e| match e {
+                        PolicyError::ConditionError(message) => PolicyError::ValidationError(message),
+                        e => e,
+                    })?;
+                compiled.push(((condition.operator, condition.value.to_string()), window));
+            }
+        }
+        self.compiled.extend(compiled);
+        Ok(())
+    }
+}
+
+fn parse_timezone(name: &str) -> Result<Tz> {
+    name.parse::<Tz>()
+        .map_err(|_| PolicyError::ConditionError(format!("Unknown IANA timezone '{}'", name)))
+}
+
+/// Returns the request time (`environment.now`).
+pub fn request_time(context: &ContextAt) -> Result<DateTime<Utc>> {
+    let now = context
+        .get_value("environment.now")
+        .ok_or_else(|| PolicyError::ConditionError("Field 'environment.now' not found".to_string()))?;
+    parse_datetime(&now)
+}
+
+/// Returns true if the operator compares dates and times.
+pub fn is_temporal(operator: ConditionOperator) -> bool {
+    matches!(
+        operator,
+        ConditionOperator::Before
+            | ConditionOperator::After
+            | ConditionOperator::WithinLast
+            | ConditionOperator::OlderThan
+            | ConditionOperator::DayOfWeekIn
+            | ConditionOperator::TimeOfDayBetween
+    )
+}
+
+/// Evaluates a temporal operator.
+pub fn evaluate(
+    operator: ConditionOperator,
+    left: &serde_json::Value,
+    right: &serde_json::Value,
+    windows: &WindowCache,
+    context: &ContextAt,
+) -> Result<bool> {
+    let at = parse_datetime(left)?;
+    match operator {
+        ConditionOperator::Before => Ok(at < parse_datetime(right)?),
+        ConditionOperator::After => Ok(at > parse_datetime(right)?),
+        ConditionOperator::WithinLast => {
+            let now = request_time(context)?;
+            Ok(at <= now && now - at <= parse_duration(right)?)
+        }
+        ConditionOperator::OlderThan => {
+            let now = request_time(context)?;
+            Ok(now - at > parse_duration(right)?)
+        }
+        ConditionOperator::DayOfWeekIn | ConditionOperator::TimeOfDayBetween => {
+            windows.get(operator, right)?.contains(at, context)
+        }
+        _ => Err(PolicyError::InternalError(format!("{:?} is not a temporal operator", operator))),
+    }
+}
+
+/// Validates the literal operand of a temporal operator at load time.
+pub(crate) fn validate_operand(operator: ConditionOperator, value: &serde_json::Value, owner: &str) -> Result<()> {
+    let result = match operator {
+        ConditionOperator::Before | ConditionOperator::After => parse_datetime(value).map(|_| ()),
+        ConditionOperator::WithinLast | ConditionOperator::OlderThan => parse_duration(value).map(|_| ()),
+        ConditionOperator::DayOfWeekIn | ConditionOperator::TimeOfDayBetween => {
+            Window::compile(operator, value).map(|_| ())
+        }
+        _ => Ok(()),
+    };
+    result.map_err(|e| match e {
+        PolicyError::ConditionError(message) => PolicyError::ValidationError(format!("{} in {}", message, owner)),
+        e => e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::EvaluationContext;
+    use crate::types::{Action, ActionType, Identity, Resource, ResourceType, Tenant, TenantType};
+    use serde_json::json;
+
+    fn create_test_context(now: &str) -> EvaluationContext {
+        let mut ctx = EvaluationContext::new(
+            Identity {
+                user_id: "u:test".to_string(),
+                email: "test@example.com".to_string(),
+                email_domain: "example.com".to_string(),
+                groups: vec![],
+                is_service: false,
+            },
+            Tenant {
+                tenant_id: "t:example.com".to_string(),
+                tenant_type: TenantType::Customer,
+            },
+            Resource {
+                resource_type: ResourceType::Room,
+                resource_id: "r:general".to_string(),
+                owner_id: None,
+                agreement_id: None,
+            },
+            Action {
+                action_type: ActionType::Write,
+                action_name: "messenger.send".to_string(),
+            },
+        )
+        .with_attribute("timezone", json!("America/New_York"));
+        ctx.environment.now = Some(now.to_string());
+        ctx
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration(&json!("PT15M")).unwrap(), Duration::minutes(15));
+        assert_eq!(parse_duration(&json!("P1W2DT3H")).unwrap(), Duration::hours(9 * 24 + 3));
+        assert!(parse_duration(&json!("P")).is_err());
+        assert!(parse_duration(&json!("PT")).is_err());
+        assert!(parse_duration(&json!("P1H")).is_err());
+        assert!(parse_duration(&json!("15m")).is_err());
+    }
+
+    #[test]
+    fn test_comparisons() {
+        // Saturday 2026-01-03 14:30 UTC is 09:30 in New York
+        let ctx = create_test_context("2026-01-03T14:30:00Z");
+        let ctx = ContextAt::from(&ctx);
+        let windows = WindowCache::default();
+        let now = json!("2026-01-03T14:30:00Z");
+
+        assert!(evaluate(ConditionOperator::Before, &now, &json!("2026-12-31T23:59:59Z"), &windows, &ctx).unwrap());
+        assert!(evaluate(ConditionOperator::After, &now, &json!("2026-01-03T15:00:00+02:00"), &windows, &ctx).unwrap());
+        assert!(evaluate(ConditionOperator::WithinLast, &json!("2026-01-03T14:20:00Z"), &json!("PT15M"), &windows, &ctx).unwrap());
+        assert!(evaluate(ConditionOperator::OlderThan, &json!("2025-12-01T00:00:00Z"), &json!("P30D"), &windows, &ctx).unwrap());
+        assert!(evaluate(ConditionOperator::Before, &json!("yesterday"), &now, &windows, &ctx).is_err());
+
+        let business_hours = json!({ "start": "09:00", "end": "17:00", "timezone_field": "attributes.timezone" });
+        assert!(evaluate(ConditionOperator::TimeOfDayBetween, &now, &business_hours, &windows, &ctx).unwrap());
+        let tokyo = json!({ "start": "09:00", "end": "17:00", "timezone": "Asia/Tokyo" });
+        assert!(!evaluate(ConditionOperator::TimeOfDayBetween, &now, &tokyo, &windows, &ctx).unwrap());
+        let overnight = json!({ "start": "22:00", "end": "06:00" });
+        assert!(!evaluate(ConditionOperator::TimeOfDayBetween, &now, &overnight, &windows, &ctx).unwrap());
+
+        let weekdays = json!({ "days": ["mon", "tue", "wed", "thu", "fri"] });
+        assert!(!evaluate(ConditionOperator::DayOfWeekIn, &now, &weekdays, &windows, &ctx).unwrap());
+        // Already Sunday at UTC+14
+        let sunday = json!({ "days": ["Sunday"], "timezone": "Pacific/Kiritimati" });
+        assert!(evaluate(ConditionOperator::DayOfWeekIn, &now, &sunday, &windows, &ctx).unwrap());
+        assert!(!evaluate(ConditionOperator::DayOfWeekIn, &now, &json!({ "days": ["sun"] }), &windows, &ctx).unwrap());
+    }
+
+    #[test]
+    fn test_validate_operand() {
+        assert!(validate_operand(ConditionOperator::WithinLast, &json!("PT1H"), "rule 'r'").is_ok());
+        assert!(validate_operand(ConditionOperator::Before, &json!("2026-13-01"), "rule 'r'").is_err());
+        assert!(validate_operand(ConditionOperator::DayOfWeekIn, &json!({ "days": ["funday"] }), "rule 'r'").is_err());
+        assert!(validate_operand(
+            ConditionOperator::TimeOfDayBetween,
+            &json!({ "start": "09:00", "end": "17:00", "timezone": "Mars/Olympus" }),
+            "rule 'r'"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_cache() {
+        let policy = Policy::from_yaml(
+            r#"
+id: hours-policy
+version: "1.0.0"
+name: Hours Policy
+rules:
+  - id: hours
+    effect: allow
+    conditions:
+      - field: environment.now
+        operator: time_of_day_between
+        value: { start: "09:00", end: "17:00", timezone: Europe/Berlin }
+      - field: environment.now
+        operator: day_of_week_in
+        value: { days: [mon, fri] }
+    priority: 10
+"#,
+        )
+        .unwrap();
+
+        let mut cache = WindowCache::default();
+        cache.add_policy(&policy).unwrap();
+        assert_eq!(cache.len(), 2);
+        let hours = json!({ "start": "09:00", "end": "17:00", "timezone": "Europe/Berlin" });
+        assert!(matches!(cache.get(ConditionOperator::TimeOfDayBetween, &hours).unwrap(), Cow::Borrowed(_)));
+        let days = json!({ "days": ["mon", "fri"] });
+        assert!(matches!(cache.get(ConditionOperator::DayOfWeekIn, &days).unwrap(), Cow::Borrowed(_)));
+        assert!(matches!(cache.get(ConditionOperator::DayOfWeekIn, &json!({ "days": ["sun"] })).unwrap(), Cow::Owned(_)));
+        assert!(cache.get(ConditionOperator::DayOfWeekIn, &hours).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Unicode-aware string comparison.
+//!
+//! The `*_ignore_case` operators compare strings after Unicode default case
+//! folding (so `Straße` equals `STRASSE`, which ASCII lowercasing misses),
+//! applied to the canonical decomposition so precomposed and combining forms
+//! of the same character agree. Separately, an evaluator can NFC-normalize
+//! every string it compares (see `PolicyEvaluator::normalize_unicode`).
+
+use caseless::Caseless;
+use serde_json::Value;
+use std::borrow::Cow;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+/// Case-folds a string for caseless comparison, returning it in NFC.
+pub fn fold(s: &str) -> String {
+    s.chars().nfd().default_case_fold().nfc().collect()
+}
+
+/// Returns a string in Normalization Form C, borrowing it if it already is.
+pub fn nfc(s: &str) -> Cow<'_, str> {
+    match is_nfc_quick(s.chars()) {
+        IsNormalized::Yes => Cow::Borrowed(s),
+        _ => Cow::Owned(s.nfc().collect()),
+    }
+}
+
+/// NFC-normalizes every string in a value, borrowing it if none change.
+pub fn nfc_value(value: &Value) -> Cow<'_, Value> {
+    match value {
+        Value::String(s) => match nfc(s) {
+            Cow::Borrowed(_) => Cow::Borrowed(value),
+            Cow::Owned(s) => Cow::Owned(Value::String(s)),
+        },
+        Value::Array(items) => {
+            let normalized: Vec<Cow<'_, Value>> = items.iter().map(nfc_value).collect();
+            if normalized.iter().all(|item| matches!(item, Cow::Borrowed(_))) {
+                return Cow::Borrowed(value);
+            }
+            Cow::Owned(Value::Array(normalized.into_iter().map(Cow::into_owned).collect()))
+        }
+        Value::Object(map) => {
+            let normalized: Vec<(&String, Cow<'_, Value>)> = map.iter().map(|(k, v)| (k, nfc_value(v))).collect();
+            if normalized.iter().all(|(k, v)| matches!(v, Cow::Borrowed(_)) && matches!(nfc(k), Cow::Borrowed(_))) {
+                return Cow::Borrowed(value);
+            }
+            Cow::Owned(Value::Object(
+                normalized
+                    .into_iter()
+                    .map(|(k, v)| (nfc(k).into_owned(), v.into_owned()))
+                    .collect(),
+            ))
+        }
+        _ => Cow::Borrowed(value),
+    }
+}
+
+/// Returns true if two values are equal strings under case folding.
+pub fn equals_ignore_case(left: &Value, right: &Value) -> bool {
+    match (left.as_str(), right.as_str()) {
+        (Some(left), Some(right)) => left == right || fold(left) == fold(right),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold() {
+        assert_eq!(fold("Example.COM"), "example.com");
+        assert_eq!(fold("Straße"), fold("STRASSE"));
+        assert_eq!(fold("ΣΊΣΥΦΟΣ"), fold("σίσυφος"));
+        // Precomposed and combining forms fold alike.
+        assert_eq!(fold("CAF\u{00C9}"), fold("cafe\u{0301}"));
+        assert!(equals_ignore_case(&Value::from("Ǆ"), &Value::from("ǆ")));
+        assert!(!equals_ignore_case(&Value::from("1"), &Value::from(1)));
+    }
+
+    #[test]
+    fn test_nfc() {
+        assert!(matches!(nfc("café"), Cow::Borrowed(_)));
+        assert_eq!(nfc("cafe\u{0301}"), "caf\u{00E9}");
+
+        let value = serde_json::json!(["cafe\u{0301}", 1, { "k": "ok" }]);
+        assert_eq!(nfc_value(&value).into_owned(), serde_json::json!(["caf\u{00E9}", 1, { "k": "ok" }]));
+        assert!(matches!(nfc_value(&serde_json::json!(["ok"])), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,225 @@
+//! Evaluation traces for explainable decisions.
+//!
+//! A trace records every policy, rule and condition visited during an
+//! evaluation, along with the values that were compared and the combining
+//! steps that selected the final decision.
+
+use crate::decision::{Decision, PolicyDecision};
+use crate::error::{PolicyError, Result};
+use crate::types::{CombiningAlgorithm, ConditionOperator, Effect};
+use serde::{Deserialize, Serialize};
+
+/// The full trace of an evaluation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvaluationTrace {
+    /// The final decision, identical to what `evaluate` returns.
+    pub decision: PolicyDecision,
+
+    /// Per-policy traces, in load order.
+    pub policies: Vec<PolicyTrace>,
+
+    /// Per-policy-set traces, in load order.
+    #[serde(default)]
+    pub policy_sets: Vec<PolicySetTrace>,
+
+    /// The step that combined policy decisions into the final decision.
+    pub combining: CombiningTrace,
+}
+
+impl EvaluationTrace {
+    /// Serializes the trace to JSON.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| PolicyError::SerializationError(e.to_string()))
+    }
+}
+
+/// Trace of a policy set and its members.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicySetTrace {
+    pub policy_set_id: String,
+
+    /// Whether the set's target matched (always true without a target).
+    /// Members are only evaluated (and traced) when it did.
+    pub target_matched: bool,
+
+    pub policies: Vec<PolicyTrace>,
+    pub policy_sets: Vec<PolicySetTrace>,
+
+    /// The step that combined member decisions, if members were evaluated.
+    pub combining: Option<CombiningTrace>,
+
+    /// The decision this policy set produced.
+    pub decision: PolicyDecision,
+}
+
+/// Trace of a single policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyTrace {
+    pub policy_id: String,
+
+    /// Whether the policy's target matched (always true without a target).
+    /// Rules are only evaluated (and traced) when it did.
+    pub target_matched: bool,
+
+    /// Rules in evaluation (priority) order.
+    pub rules: Vec<RuleTrace>,
+
+    /// The step that combined matching rules into the policy decision, if
+    /// rules were evaluated.
+    pub combining: Option<CombiningTrace>,
+
+    /// The decision this policy produced.
+    pub decision: PolicyDecision,
+}
+
+/// Trace of a single rule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleTrace {
+    pub rule_id: String,
+    pub effect: Effect,
+    pub priority: i32,
+
+    /// Whether the rule's validity period and target matched (always true
+    /// without either). Conditions are only evaluated (and traced) when they did.
+    pub target_matched: bool,
+
+    /// Whether the target and all of the rule's conditions held.
+    pub matched: bool,
+
+    /// The rule's outcome: its effect, not applicable, or indeterminate.
+    pub decision: Decision,
+
+    /// Error that made the rule indeterminate, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Traces of the rule's top-level condition expressions.
+    pub conditions: Vec<ExprTrace>,
+}
+
+/// Trace of a condition expression.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExprTrace {
+    AllOf {
+        matched: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        children: Vec<ExprTrace>,
+    },
+    AnyOf {
+        matched: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        children: Vec<ExprTrace>,
+    },
+    Not {
+        matched: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        child: Box<ExprTrace>,
+    },
+    Condition(ConditionTrace),
+}
+
+impl ExprTrace {
+    /// Returns whether the expression held.
+    pub fn matched(&self) -> bool {
+        match self {
+            ExprTrace::AllOf { matched, .. }
+            | ExprTrace::AnyOf { matched, .. }
+            | ExprTrace::Not { matched, .. } => *matched,
+            ExprTrace::Condition(condition) => condition.matched,
+        }
+    }
+
+    /// Returns the error that stopped the expression from evaluating, if any.
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            ExprTrace::AllOf { error, .. }
+            | ExprTrace::AnyOf { error, .. }
+            | ExprTrace::Not { error, .. } => error.as_deref(),
+            ExprTrace::Condition(condition) => condition.error.as_deref(),
+        }
+    }
+}
+
+/// Trace of a single leaf condition.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConditionTrace {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub field: String,
+
+    /// The expression the left-hand side was computed from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expr: Option<String>,
+
+    pub operator: ConditionOperator,
+
+    /// The context path the right-hand side was read from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_field: Option<String>,
+
+    /// The expression the right-hand side was computed from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_expr: Option<String>,
+
+    /// The resolved left-hand value (`None` if the field was missing).
+    pub left: Option<serde_json::Value>,
+
+    /// The resolved right-hand value (`None` if missing or unused).
+    pub right: Option<serde_json::Value>,
+
+    pub matched: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Trace of a combining-algorithm step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CombiningTrace {
+    pub algorithm: CombiningAlgorithm,
+
+    /// IDs of the applicable inputs (rules, policies or policy sets), in order.
+    pub candidates: Vec<String>,
+
+    /// ID of the input that determined the outcome, if any.
+    pub selected: Option<String>,
+
+    pub decision: Decision,
+    pub reason: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expr_trace_serialization() {
+        let trace = ExprTrace::Not {
+            matched: true,
+            error: None,
+            child: Box::new(ExprTrace::Condition(ConditionTrace {
+                field: "role".to_string(),
+                expr: None,
+                operator: ConditionOperator::Equals,
+                value_field: None,
+                value_expr: None,
+                left: Some(serde_json::json!("member")),
+                right: Some(serde_json::json!("guest")),
+                matched: false,
+                error: None,
+            })),
+        };
+
+        let json = serde_json::to_value(&trace).unwrap();
+        assert_eq!(json["type"], "not");
+        assert_eq!(json["child"]["type"], "condition");
+        assert_eq!(json["child"]["left"], "member");
+        assert!(json.get("error").is_none());
+
+        let parsed: ExprTrace = serde_json::from_value(json).unwrap();
+        assert!(parsed.matched());
+    }
+}
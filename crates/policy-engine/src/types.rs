@@ -1,7 +1,9 @@
 //! Core types for the policy engine.
 
+use crate::target::Target;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// Represents a user identity in the policy context.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,7 +40,12 @@ pub struct Resource {
 }
 
 /// Type of resource.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// The variants are the well-known types; any other name deserializes as
+/// `Custom` and is accepted by policies that declare it (see
+/// `crate::registry`). Types compare by name, so `Custom("room")` equals
+/// `Room`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ResourceType {
     Tenant,
@@ -48,10 +55,31 @@ pub enum ResourceType {
     Document,
     Tool,
     Receipt,
+    #[serde(untagged)]
+    Custom(String),
 }
 
 impl ResourceType {
-    pub fn as_str(&self) -> &'static str {
+    /// The well-known resource types.
+    pub const WELL_KNOWN: [ResourceType; 7] = [
+        ResourceType::Tenant,
+        ResourceType::Room,
+        ResourceType::Message,
+        ResourceType::Workspace,
+        ResourceType::Document,
+        ResourceType::Tool,
+        ResourceType::Receipt,
+    ];
+
+    /// Returns the resource type with the given name.
+    pub fn parse(name: &str) -> Self {
+        Self::WELL_KNOWN
+            .into_iter()
+            .find(|t| t.as_str() == name)
+            .unwrap_or_else(|| ResourceType::Custom(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
         match self {
             ResourceType::Tenant => "tenant",
             ResourceType::Room => "room",
@@ -60,8 +88,28 @@ impl ResourceType {
             ResourceType::Document => "document",
             ResourceType::Tool => "tool",
             ResourceType::Receipt => "receipt",
+            ResourceType::Custom(name) => name,
         }
     }
+
+    /// Returns true if this is one of the well-known types.
+    pub fn is_well_known(&self) -> bool {
+        Self::WELL_KNOWN.iter().any(|t| t.as_str() == self.as_str())
+    }
+}
+
+impl PartialEq for ResourceType {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for ResourceType {}
+
+impl std::hash::Hash for ResourceType {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
 }
 
 /// Represents an action being performed.
@@ -72,7 +120,9 @@ pub struct Action {
 }
 
 /// Type of action.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// Open in the same way as `ResourceType`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ActionType {
     Read,
@@ -81,10 +131,30 @@ pub enum ActionType {
     Delete,
     Execute,
     Admin,
+    #[serde(untagged)]
+    Custom(String),
 }
 
 impl ActionType {
-    pub fn as_str(&self) -> &'static str {
+    /// The well-known action types.
+    pub const WELL_KNOWN: [ActionType; 6] = [
+        ActionType::Read,
+        ActionType::Write,
+        ActionType::Create,
+        ActionType::Delete,
+        ActionType::Execute,
+        ActionType::Admin,
+    ];
+
+    /// Returns the action type with the given name.
+    pub fn parse(name: &str) -> Self {
+        Self::WELL_KNOWN
+            .into_iter()
+            .find(|t| t.as_str() == name)
+            .unwrap_or_else(|| ActionType::Custom(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
         match self {
             ActionType::Read => "read",
             ActionType::Write => "write",
@@ -92,39 +162,117 @@ impl ActionType {
             ActionType::Delete => "delete",
             ActionType::Execute => "execute",
             ActionType::Admin => "admin",
+            ActionType::Custom(name) => name,
         }
     }
+
+    /// Returns true if this is one of the well-known types.
+    pub fn is_well_known(&self) -> bool {
+        Self::WELL_KNOWN.iter().any(|t| t.as_str() == self.as_str())
+    }
+}
+
+impl PartialEq for ActionType {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for ActionType {}
+
+impl std::hash::Hash for ActionType {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
 }
 
 /// Role within a context.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+///
+/// Roles compare by name, so `Custom("admin")` equals `Admin`. The built-in
+/// roles are ordered `guest < member < admin < owner`; any other name is a
+/// custom role, unordered here, whose place in the hierarchy is declared by
+/// policies (see `crate::rbac`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Role {
     Guest,
     Member,
     Admin,
     Owner,
+    #[serde(untagged)]
+    Custom(String),
 }
 
 impl Role {
-    pub fn as_str(&self) -> &'static str {
+    /// The built-in roles, lowest first.
+    pub const BUILTIN: [Role; 4] = [Role::Guest, Role::Member, Role::Admin, Role::Owner];
+
+    /// Returns the role with the given name.
+    pub fn parse(name: &str) -> Self {
+        Self::BUILTIN
+            .into_iter()
+            .find(|role| role.as_str() == name)
+            .unwrap_or_else(|| Role::Custom(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
         match self {
             Role::Guest => "guest",
             Role::Member => "member",
             Role::Admin => "admin",
             Role::Owner => "owner",
+            Role::Custom(name) => name,
         }
     }
 
     /// Returns true if this role has at least the given permission level.
+    ///
+    /// Only built-in roles are ordered here; a custom role only has its own
+    /// level. Use the `role_at_least` operator for policy-declared
+    /// hierarchies.
     pub fn has_permission(&self, required: Role) -> bool {
         *self >= required
     }
+
+    /// The position of a built-in role in `BUILTIN`.
+    fn level(&self) -> Option<usize> {
+        Self::BUILTIN.iter().position(|role| role.as_str() == self.as_str())
+    }
+}
+
+impl PartialEq for Role {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Role {}
+
+impl std::hash::Hash for Role {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl PartialOrd for Role {
+    /// Orders built-in roles by level; a custom role is only comparable
+    /// with itself.
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match (self.level(), other.level()) {
+            (Some(level), Some(other_level)) => Some(level.cmp(&other_level)),
+            _ if self == other => Some(std::cmp::Ordering::Equal),
+            _ => None,
+        }
+    }
 }
 
 /// Environment context for policy evaluation.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Environment {
+    /// The evaluation time (RFC 3339). Evaluators read their clock instead
+    /// unless built with `trust_request_time`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub now: Option<String>,
     pub timestamp: Option<String>,
     pub request_id: Option<String>,
     pub ip_address: Option<String>,
@@ -133,7 +281,7 @@ pub struct Environment {
 }
 
 /// Condition operator for rule matching.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ConditionOperator {
     Equals,
@@ -151,14 +299,262 @@ pub enum ConditionOperator {
     LessThanOrEqual,
     Exists,
     NotExists,
+    /// The left datetime is earlier than the right datetime.
+    Before,
+    /// The left datetime is later than the right datetime.
+    After,
+    /// The left datetime lies within the duration before the request time.
+    WithinLast,
+    /// The left datetime is more than the duration before the request time.
+    OlderThan,
+    /// The left datetime falls on one of the given days of the week.
+    DayOfWeekIn,
+    /// The left datetime's local time lies in the given window.
+    TimeOfDayBetween,
+    /// The left IP address lies in one of the given networks.
+    IpInCidr,
+    /// The left IP address lies in none of the given networks.
+    IpNotInCidr,
+    /// The left and right arrays share at least one element.
+    Intersects,
+    /// The left and right arrays share no elements.
+    Disjoint,
+    /// Every element of the left array is in the right array.
+    SubsetOf,
+    /// Every element of the right array is in the left array.
+    SupersetOf,
+    /// The left array has exactly the given number of elements.
+    CountEquals,
+    /// The left array has at least the given number of elements.
+    CountAtLeast,
+    /// The left array has at most the given number of elements.
+    CountAtMost,
+    /// The left string equals the right one, ignoring case.
+    EqualsIgnoreCase,
+    /// The left string contains the right one (or the left array contains
+    /// it as an element), ignoring case.
+    ContainsIgnoreCase,
+    /// The left string starts with the right one, ignoring case.
+    StartsWithIgnoreCase,
+    /// The left string ends with the right one, ignoring case.
+    EndsWithIgnoreCase,
+    /// The left string is in the right array, ignoring case.
+    InIgnoreCase,
+    /// The left string matches the right glob pattern (see `crate::glob`).
+    Glob,
+    /// The left role is the right role or inherits it (see `crate::rbac`).
+    RoleAtLeast,
+    /// The left role is granted the right permission (see `crate::rbac`).
+    HasPermission,
 }
 
 /// A condition in a policy rule.
+///
+/// The right-hand side is either the literal `value` or, when `value_field`
+/// is set, the value found at that context path (e.g. comparing
+/// `resource.owner_id` against `identity.user_id`).
+///
+/// Either side may instead be computed: `expr` replaces `field` and
+/// `value_expr` replaces `value` (see `crate::expression`).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Condition {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub field: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expr: Option<String>,
     pub operator: ConditionOperator,
+    #[serde(default)]
     pub value: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_field: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_expr: Option<String>,
+}
+
+impl Condition {
+    /// Creates a condition comparing a field against a literal value.
+    pub fn new(field: impl Into<String>, operator: ConditionOperator, value: serde_json::Value) -> Self {
+        Self {
+            field: field.into(),
+            expr: None,
+            operator,
+            value,
+            value_field: None,
+            value_expr: None,
+        }
+    }
+
+    /// Creates a condition comparing a field against another context field.
+    pub fn compare_fields(
+        field: impl Into<String>,
+        operator: ConditionOperator,
+        value_field: impl Into<String>,
+    ) -> Self {
+        Self {
+            field: field.into(),
+            expr: None,
+            operator,
+            value: serde_json::Value::Null,
+            value_field: Some(value_field.into()),
+            value_expr: None,
+        }
+    }
+
+    /// Creates a condition comparing a computed value against a literal value.
+    pub fn computed(expr: impl Into<String>, operator: ConditionOperator, value: serde_json::Value) -> Self {
+        Self {
+            field: String::new(),
+            expr: Some(expr.into()),
+            operator,
+            value,
+            value_field: None,
+            value_expr: None,
+        }
+    }
+
+    /// Computes the right-hand side from an expression instead of `value`.
+    pub fn with_value_expr(mut self, value_expr: impl Into<String>) -> Self {
+        self.value = serde_json::Value::Null;
+        self.value_expr = Some(value_expr.into());
+        self
+    }
+
+    /// Returns true if the right-hand side is the literal `value`.
+    pub fn has_literal_value(&self) -> bool {
+        self.value_field.is_none() && self.value_expr.is_none()
+    }
+
+    /// Returns the left-hand side as written: the field path or expression.
+    pub fn subject(&self) -> &str {
+        self.expr.as_deref().unwrap_or(&self.field)
+    }
+}
+
+/// A boolean expression over conditions.
+///
+/// Expressions nest arbitrarily. A bare condition is itself an expression, so
+/// flat condition lists written before expressions existed still parse:
+///
+/// ```yaml
+/// conditions:
+///   - any_of:
+///       - field: role
+///         operator: equals
+///         value: admin
+///       - not:
+///           field: identity.is_service
+///           operator: equals
+///           value: true
+/// ```
+///
+/// A map is either a single combinator (`all_of`, `any_of` or `not`) or a
+/// leaf condition; maps mixing the two are rejected rather than read as
+/// whichever shape happens to parse.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum ConditionExpr {
+    /// Every sub-expression must hold (true when empty).
+    AllOf { all_of: Vec<ConditionExpr> },
+    /// At least one sub-expression must hold.
+    AnyOf { any_of: Vec<ConditionExpr> },
+    /// The sub-expression must not hold.
+    Not { not: Box<ConditionExpr> },
+    /// A single leaf condition.
+    Condition(Condition),
+}
+
+impl ConditionExpr {
+    /// Creates an `all_of` expression.
+    pub fn all_of(exprs: Vec<ConditionExpr>) -> Self {
+        ConditionExpr::AllOf { all_of: exprs }
+    }
+
+    /// Creates an `any_of` expression.
+    pub fn any_of(exprs: Vec<ConditionExpr>) -> Self {
+        ConditionExpr::AnyOf { any_of: exprs }
+    }
+
+    /// Creates a `not` expression.
+    pub fn negate(expr: ConditionExpr) -> Self {
+        ConditionExpr::Not { not: Box::new(expr) }
+    }
+
+    /// Returns every leaf condition in this expression, depth first.
+    pub fn leaf_conditions(&self) -> Vec<&Condition> {
+        let mut leaves = Vec::new();
+        self.collect_leaves(&mut leaves);
+        leaves
+    }
+
+    fn collect_leaves<'a>(&'a self, leaves: &mut Vec<&'a Condition>) {
+        match self {
+            ConditionExpr::AllOf { all_of: exprs } | ConditionExpr::AnyOf { any_of: exprs } => {
+                for expr in exprs {
+                    expr.collect_leaves(leaves);
+                }
+            }
+            ConditionExpr::Not { not } => not.collect_leaves(leaves),
+            ConditionExpr::Condition(condition) => leaves.push(condition),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ConditionExpr {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        ConditionExpr::from_value(value).map_err(serde::de::Error::custom)
+    }
+}
+
+impl ConditionExpr {
+    const COMBINATORS: [&'static str; 3] = ["all_of", "any_of", "not"];
+
+    fn from_value(value: serde_json::Value) -> std::result::Result<Self, String> {
+        let serde_json::Value::Object(mut map) = value else {
+            return Err("a condition expression must be a map".to_string());
+        };
+        let combinators: Vec<&str> = Self::COMBINATORS.into_iter().filter(|key| map.contains_key(*key)).collect();
+        let key = match combinators.as_slice() {
+            [] => {
+                return serde_json::from_value(serde_json::Value::Object(map))
+                    .map(ConditionExpr::Condition)
+                    .map_err(|e| e.to_string())
+            }
+            [key] if map.len() == 1 => *key,
+            [key] => {
+                let others: Vec<&str> = map.keys().map(String::as_str).filter(|k| k != key).collect();
+                return Err(format!("'{}' cannot be combined with {} in one map", key, others.join(", ")));
+            }
+            keys => return Err(format!("{} cannot be combined in one map; nest them instead", keys.join(" and "))),
+        };
+
+        let inner = map.remove(key).unwrap_or_default();
+        if key == "not" {
+            let not = ConditionExpr::from_value(inner).map_err(|e| format!("not: {}", e))?;
+            return Ok(ConditionExpr::negate(not));
+        }
+        let serde_json::Value::Array(items) = inner else {
+            return Err(format!("{} must be a list", key));
+        };
+        let exprs = items
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| ConditionExpr::from_value(item).map_err(|e| format!("{}[{}]: {}", key, i, e)))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(match key {
+            "all_of" => ConditionExpr::all_of(exprs),
+            _ => ConditionExpr::any_of(exprs),
+        })
+    }
+}
+
+impl From<Condition> for ConditionExpr {
+    fn from(condition: Condition) -> Self {
+        ConditionExpr::Condition(condition)
+    }
 }
 
 /// Effect of a policy rule.
@@ -169,23 +565,70 @@ pub enum Effect {
     Deny,
 }
 
+/// An obligation or piece of advice attached to a rule or policy.
+///
+/// Obligations must be fulfilled by the enforcement point (e.g. redact the
+/// body, emit an audit receipt); advice may be ignored. Either is only
+/// returned when the decision matches `fulfill_on`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Obligation {
+    pub id: String,
+    pub fulfill_on: Effect,
+    /// Attributes, kept sorted so decisions serialize deterministically.
+    #[serde(default)]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+}
+
+impl Obligation {
+    /// Creates an obligation fulfilled on the given effect.
+    pub fn new(id: impl Into<String>, fulfill_on: Effect) -> Self {
+        Self {
+            id: id.into(),
+            fulfill_on,
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    /// Adds an attribute.
+    pub fn with_attribute(mut self, key: impl Into<String>, value: serde_json::Value) -> Self {
+        self.attributes.insert(key.into(), value);
+        self
+    }
+}
+
 /// A single rule in a policy.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Rule {
     pub id: String,
     pub description: Option<String>,
     pub effect: Effect,
-    pub conditions: Vec<Condition>,
+    /// Checked before the conditions; when it does not match the rule is
+    /// skipped without evaluating them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+    /// Condition expressions, all of which must hold for the rule to match.
+    pub conditions: Vec<ConditionExpr>,
     pub priority: i32,
+    /// The rule is not applicable before this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<DateTime<Utc>>,
+    /// The rule is not applicable from this time on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub obligations: Vec<Obligation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub advice: Vec<Obligation>,
 }
 
 /// Combining algorithm for multiple rules.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum CombiningAlgorithm {
     /// First applicable rule wins
     FirstApplicable,
     /// Deny takes precedence
+    #[default]
     DenyOverrides,
     /// Allow takes precedence
     AllowOverrides,
@@ -194,9 +637,3 @@ pub enum CombiningAlgorithm {
     /// All rules must deny
     UnanimousDeny,
 }
-
-impl Default for CombiningAlgorithm {
-    fn default() -> Self {
-        CombiningAlgorithm::DenyOverrides
-    }
-}
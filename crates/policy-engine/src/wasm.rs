@@ -2,12 +2,27 @@
 
 #![cfg(feature = "wasm")]
 
+use crate::clock::{Clock, FixedClock};
 use crate::context::EvaluationContext;
-use crate::decision::PolicyDecision;
 use crate::evaluator::PolicyEvaluator;
 use crate::policy::Policy;
+use chrono::{DateTime, Utc};
 use wasm_bindgen::prelude::*;
 
+/// Clock backed by the host's `Date.now()`.
+#[derive(Debug, Clone, Copy)]
+struct JsClock;
+
+impl Clock for JsClock {
+    fn now(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(js_sys::Date::now() as i64).unwrap_or_default()
+    }
+
+    fn monotonic_micros(&self) -> Option<u64> {
+        None
+    }
+}
+
 /// WASM-compatible policy engine wrapper.
 #[wasm_bindgen]
 pub struct WasmPolicyEngine {
@@ -20,10 +35,43 @@ impl WasmPolicyEngine {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
         Self {
-            evaluator: PolicyEvaluator::new(),
+            evaluator: PolicyEvaluator::new().with_clock(JsClock),
         }
     }
 
+    /// Fixes `environment.now` at the given Unix time in milliseconds.
+    #[wasm_bindgen]
+    pub fn set_fixed_time(&mut self, unix_millis: f64) -> Result<(), JsValue> {
+        let clock = FixedClock::from_unix_millis(unix_millis as i64)
+            .ok_or_else(|| JsValue::from_str("Time out of range"))?;
+        self.evaluator = std::mem::take(&mut self.evaluator).with_clock(clock);
+        Ok(())
+    }
+
+    /// Evaluates requests at the `environment.now` they carry, if any.
+    #[wasm_bindgen]
+    pub fn set_trust_request_time(&mut self) {
+        self.evaluator = std::mem::take(&mut self.evaluator).trust_request_time();
+    }
+
+    /// Omits evaluation timing so identical inputs give identical output.
+    #[wasm_bindgen]
+    pub fn set_deterministic(&mut self) {
+        self.evaluator = std::mem::take(&mut self.evaluator).deterministic();
+    }
+
+    /// NFC-normalizes compared strings.
+    #[wasm_bindgen]
+    pub fn set_normalize_unicode(&mut self) {
+        self.evaluator = std::mem::take(&mut self.evaluator).normalize_unicode();
+    }
+
+    /// Denies requests not made under an active agreement.
+    #[wasm_bindgen]
+    pub fn set_require_agreement(&mut self) {
+        self.evaluator = std::mem::take(&mut self.evaluator).require_agreement();
+    }
+
     /// Loads a policy from YAML string.
     #[wasm_bindgen]
     pub fn load_policy_yaml(&mut self, yaml: &str) -> Result<(), JsValue> {
@@ -41,6 +89,14 @@ impl WasmPolicyEngine {
         Ok(())
     }
 
+    /// Loads a policy set from YAML string.
+    #[wasm_bindgen]
+    pub fn load_policy_set_yaml(&mut self, yaml: &str) -> Result<(), JsValue> {
+        self.evaluator
+            .load_policy_set_yaml(yaml)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
     /// Evaluates policies against a context (JSON string).
     /// Returns the decision as a JSON string.
     #[wasm_bindgen]
@@ -56,6 +112,20 @@ impl WasmPolicyEngine {
             .map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
+    /// Evaluates policies and returns the full evaluation trace as a JSON string.
+    #[wasm_bindgen]
+    pub fn evaluate_with_trace(&self, context_json: &str) -> Result<String, JsValue> {
+        let context: EvaluationContext = serde_json::from_str(context_json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid context: {}", e)))?;
+
+        let trace = self.evaluator
+            .evaluate_with_trace(&context)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        trace.to_json()
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
     /// Quick evaluation that returns just allow/deny as a boolean.
     #[wasm_bindgen]
     pub fn is_allowed(&self, context_json: &str) -> Result<bool, JsValue> {
@@ -72,7 +142,13 @@ impl WasmPolicyEngine {
     /// Returns the number of loaded policies.
     #[wasm_bindgen]
     pub fn policy_count(&self) -> usize {
-        self.evaluator.policies.len()
+        self.evaluator.policy_count()
+    }
+
+    /// Returns the number of loaded policy sets.
+    #[wasm_bindgen]
+    pub fn policy_set_count(&self) -> usize {
+        self.evaluator.policy_set_count()
     }
 }
 
@@ -82,16 +158,61 @@ impl Default for WasmPolicyEngine {
     }
 }
 
-/// Canonicalizes a JSON string.
+/// Canonicalizes a JSON string, rejecting duplicate keys, lone surrogates
+/// and numbers it cannot canonicalize exactly.
 #[wasm_bindgen]
 pub fn canonicalize_json(json: &str) -> Result<String, JsValue> {
-    let value: serde_json::Value = serde_json::from_str(json)
-        .map_err(|e| JsValue::from_str(&format!("Invalid JSON: {}", e)))?;
+    crate::canonicalization::canonicalize_str(json, crate::canonicalization::CanonicalizationMode::V1)
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
 
-    crate::canonicalization::canonicalize(&value)
+/// Canonicalizes a JSON string with the given rules (`"v1"` or `"jcs"`).
+#[wasm_bindgen]
+pub fn canonicalize_json_with_mode(json: &str, mode: &str) -> Result<String, JsValue> {
+    let mode: crate::canonicalization::CanonicalizationMode =
+        mode.parse().map_err(|e: crate::error::PolicyError| JsValue::from_str(&e.to_string()))?;
+
+    crate::canonicalization::canonicalize_str(json, mode)
         .map_err(|e| JsValue::from_str(&e.to_string()))
 }
 
+/// Returns the conformance vectors for a canonicalization mode as JSON.
+#[wasm_bindgen]
+pub fn conformance_vectors(mode: &str) -> Result<String, JsValue> {
+    let mode: crate::canonicalization::CanonicalizationMode =
+        mode.parse().map_err(|e: crate::error::PolicyError| JsValue::from_str(&e.to_string()))?;
+    Ok(crate::conformance::suite_json(mode).to_string())
+}
+
+/// Checks outputs computed by the caller, a JSON object mapping vector
+/// names to `{ canonical, body_hash, cid, head_hash, error }`, against the
+/// vectors for a mode. Returns the report as JSON.
+#[wasm_bindgen]
+pub fn verify_conformance(mode: &str, outputs_json: &str) -> Result<String, JsValue> {
+    let mode: crate::canonicalization::CanonicalizationMode =
+        mode.parse().map_err(|e: crate::error::PolicyError| JsValue::from_str(&e.to_string()))?;
+    let outputs: std::collections::HashMap<String, crate::conformance::Outputs> = serde_json::from_str(outputs_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid outputs: {}", e)))?;
+
+    let suite = crate::conformance::suite(mode).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let report = crate::conformance::check_outputs(&suite, &outputs);
+    serde_json::to_string(&report).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Verifies an NDJSON ledger export. Returns `{ "valid": true, "summary": ... }`
+/// or `{ "valid": false, "divergence": ... }` as JSON.
+#[wasm_bindgen]
+pub fn verify_ledger_ndjson(ndjson: &str, mode: &str) -> Result<String, JsValue> {
+    let mode: crate::canonicalization::CanonicalizationMode =
+        mode.parse().map_err(|e: crate::error::PolicyError| JsValue::from_str(&e.to_string()))?;
+
+    let result = match crate::ledger::verify_ndjson(ndjson, mode) {
+        Ok(summary) => serde_json::json!({ "valid": true, "summary": summary }),
+        Err(divergence) => serde_json::json!({ "valid": false, "divergence": divergence }),
+    };
+    Ok(result.to_string())
+}
+
 /// Computes SHA-256 hash of a string.
 #[wasm_bindgen]
 pub fn sha256(data: &str) -> String {
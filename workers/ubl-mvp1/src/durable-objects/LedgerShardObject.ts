@@ -364,14 +364,16 @@ export class LedgerShardObject extends DurableObject<Env> {
         errors.push(`Atom at index ${i} has invalid CID: expected ${expectedCid}, got ${atom.cid}`);
       }
 
-      // For action atoms, verify prev_hash and update computedHead
+      // For action atoms, verify prev_hash
       if (atom.kind === 'action.v1') {
         const actionAtom = atom as ActionAtom;
         if (actionAtom.prev_hash !== computedHead) {
           errors.push(`Atom at index ${i} has invalid prev_hash: expected ${computedHead}, got ${actionAtom.prev_hash}`);
         }
-        computedHead = await computeHeadHash(computedHead, atom.cid);
       }
+
+      // Every atom advances the head, as in appendAtom
+      computedHead = await computeHeadHash(computedHead, atom.cid);
     }
 
     // Verify final head matches stored head
@@ -76,7 +76,10 @@ export async function computeBodyHash(body: MessageBody): Promise<string> {
  * Computes the CID (Content Identifier) for a ledger atom.
  * cid = SHA256(canonical_json(atom_without_cid))
  *
- * The CID field itself is excluded from the hash computation.
+ * The CID field itself is excluded from the hash computation, and an
+ * action's prev_hash is hashed as '': the CID covers what the action is,
+ * not where it landed in the chain, so a re-appended action dedupes and a
+ * stored action still verifies after the ledger sets its prev_hash.
  *
  * @param atom - The atom object (with or without cid field)
  * @returns Promise resolving to CID string (prefixed with "c:")
@@ -84,9 +87,10 @@ export async function computeBodyHash(body: MessageBody): Promise<string> {
 export async function computeCID(atom: Omit<ActionAtom, 'cid'> | Omit<EffectAtom, 'cid'> | Atom): Promise<string> {
   // Remove the cid field if present
   const atomWithoutCid = 'cid' in atom ? removeField(atom as Atom, 'cid') : atom;
+  const content = atomWithoutCid.kind === 'action.v1' ? { ...atomWithoutCid, prev_hash: '' } : atomWithoutCid;
 
   // Canonicalize and hash
-  const canonical = canonicalizeJSON(atomWithoutCid);
+  const canonical = canonicalizeJSON(content);
   return sha256WithPrefix(canonical, HASH_PREFIX.CID);
 }
 
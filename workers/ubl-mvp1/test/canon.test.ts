@@ -0,0 +1,71 @@
+/**
+ * Checks canon.ts against the policy engine's conformance vectors, so the
+ * Worker and the Rust verifier hash atoms identically.
+ */
+
+import { describe, expect, it } from 'vitest';
+import { canonicalizeJSON, removeField } from '../src/utils/canon';
+import { computeHeadHash, sha256WithPrefix, HASH_PREFIX } from '../src/utils/hash';
+import jcs from '../../../crates/policy-engine/conformance/jcs.json';
+import v1 from '../../../crates/policy-engine/conformance/v1.json';
+
+interface Vector {
+  name: string;
+  input: string;
+  prev_hash: string;
+  known_divergence?: string;
+  error?: boolean;
+  canonical?: string;
+  body_hash?: string;
+  cid?: string;
+  head_hash?: string;
+}
+
+async function compute(vector: Vector) {
+  const value = JSON.parse(vector.input) as unknown;
+  const canonical = canonicalizeJSON(value);
+  const isObject = typeof value === 'object' && value !== null && !Array.isArray(value);
+  const withoutCid = isObject ? removeField(value as Record<string, unknown>, 'cid') : value;
+  const cid = await sha256WithPrefix(canonicalizeJSON(withoutCid), HASH_PREFIX.CID);
+  return {
+    canonical,
+    body_hash: await sha256WithPrefix(canonical, HASH_PREFIX.BODY),
+    cid,
+    head_hash: await computeHeadHash(vector.prev_hash, cid),
+  };
+}
+
+/** What canon.ts computes for a vector, or 'error' if it throws. */
+async function outcome(vector: Vector) {
+  try {
+    return await compute(vector);
+  } catch {
+    return 'error';
+  }
+}
+
+function expected(vector: Vector) {
+  if (vector.error) {
+    return 'error';
+  }
+  return {
+    canonical: vector.canonical,
+    body_hash: vector.body_hash,
+    cid: vector.cid,
+    head_hash: vector.head_hash,
+  };
+}
+
+// canon.ts must match every vector except those recording a known
+// divergence, which it must not match.
+describe.each([jcs, v1])('canon.ts against the $mode vectors', (suite) => {
+  for (const vector of (suite.vectors as Vector[])) {
+    it(vector.name, async () => {
+      if (vector.known_divergence) {
+        expect(await outcome(vector)).not.toEqual(expected(vector));
+      } else {
+        expect(await outcome(vector)).toEqual(expected(vector));
+      }
+    });
+  }
+});
@@ -0,0 +1,34 @@
+/**
+ * Checks the Worker's hashing against the ledger export the policy engine
+ * verifies (crates/policy-engine/conformance/worker-ledger.ndjson), which
+ * was produced by LedgerShardObject.appendAtom.
+ */
+
+import { describe, expect, it } from 'vitest';
+import { computeCID, computeHeadHash, GENESIS_HASH } from '../src/utils/hash';
+import type { Atom } from '../src/types';
+import ndjson from '../../../crates/policy-engine/conformance/worker-ledger.ndjson?raw';
+
+const records = ndjson
+  .split('\n')
+  .filter((line) => line.trim() !== '')
+  .map((line) => JSON.parse(line) as { seq: number; head_hash: string; atom: Atom });
+
+describe('worker-ledger.ndjson', () => {
+  it('chains every atom from genesis', async () => {
+    let head = GENESIS_HASH;
+    for (const { atom, head_hash } of records) {
+      expect(await computeCID(atom)).toBe(atom.cid);
+      if (atom.kind === 'action.v1') {
+        expect(atom.prev_hash).toBe(head);
+      }
+      head = await computeHeadHash(head, atom.cid);
+      expect(head_hash).toBe(head);
+    }
+  });
+
+  it('gives a re-appended action the cid it was stored with', async () => {
+    const first = records[0]!.atom;
+    expect(await computeCID({ ...first, cid: '', prev_hash: '' })).toBe(first.cid);
+  });
+});